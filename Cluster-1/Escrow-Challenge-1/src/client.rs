@@ -0,0 +1,166 @@
+//! Off-chain access to the escrow program over RPC, built with the `client` feature.
+//! Transactions are built from the escrow state on chain, so callers only name the escrow
+//! and their own accounts
+
+pub use escrow_client::ClientError;
+use escrow_client::{program_accounts, send, sign, token_program_of};
+use solana_client::{rpc_client::RpcClient, rpc_filter::RpcFilterType};
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+
+pub use crate::pda::{
+    config_address, escrow_address, fee_vault_address, pda_address, vault_address,
+};
+use crate::{instruction, state::Escrow};
+
+/// Decodes an escrow account as fetched from the cluster
+pub fn decode_escrow(
+    program_id: &Pubkey,
+    escrow: &Pubkey,
+    account: &Account,
+) -> Result<Escrow, ClientError> {
+    if account.owner != *program_id {
+        return Err(ClientError::NotAnEscrow(*escrow));
+    }
+    Escrow::unpack(&account.data).map_err(|_| ClientError::NotAnEscrow(*escrow))
+}
+
+/// The `getProgramAccounts` filters matching the open escrows, those of `initializer` only when given
+pub fn open_escrow_filters(initializer: Option<&Pubkey>) -> Vec<RpcFilterType> {
+    escrow_client::open_escrow_filters(Escrow::LEN, &[], Escrow::VERSION, initializer)
+}
+
+pub struct EscrowClient {
+    rpc: RpcClient,
+    program_id: Pubkey,
+}
+
+impl EscrowClient {
+    pub fn new(rpc: RpcClient, program_id: Pubkey) -> Self {
+        EscrowClient { rpc, program_id }
+    }
+
+    pub fn rpc(&self) -> &RpcClient {
+        &self.rpc
+    }
+
+    pub fn get_escrow(&self, escrow: &Pubkey) -> Result<Escrow, ClientError> {
+        let account = self.rpc.get_account(escrow)?;
+        decode_escrow(&self.program_id, escrow, &account)
+    }
+
+    /// Every open escrow of the program, with its address
+    pub fn open_escrows(&self) -> Result<Vec<(Pubkey, Escrow)>, ClientError> {
+        self.find_escrows(open_escrow_filters(None))
+    }
+
+    /// The open escrows of one initializer, with their addresses
+    pub fn open_escrows_of(
+        &self,
+        initializer: &Pubkey,
+    ) -> Result<Vec<(Pubkey, Escrow)>, ClientError> {
+        self.find_escrows(open_escrow_filters(Some(initializer)))
+    }
+
+    fn find_escrows(
+        &self,
+        filters: Vec<RpcFilterType>,
+    ) -> Result<Vec<(Pubkey, Escrow)>, ClientError> {
+        program_accounts(&self.rpc, &self.program_id, filters)?
+            .into_iter()
+            .map(|(escrow, account)| {
+                decode_escrow(&self.program_id, &escrow, &account).map(|state| (escrow, state))
+            })
+            .collect()
+    }
+
+    /// Opens an escrow of `initializer` under `seed`, see `EscrowInstruction::InitEscrow` for the terms
+    #[allow(clippy::too_many_arguments)]
+    pub fn init_escrow_transaction(
+        &self,
+        initializer: &Keypair,
+        deposit_account: &Pubkey,
+        token_to_receive_account: &Pubkey,
+        mint_a: &Pubkey,
+        seed: u64,
+        amount: u64,
+        deposit_amount: u64,
+        unlock_time: Option<u64>,
+        time_out: Option<u64>,
+        allowed_taker: Option<Pubkey>,
+    ) -> Result<Transaction, ClientError> {
+        let instruction = instruction::init_escrow(
+            &self.program_id,
+            &initializer.pubkey(),
+            deposit_account,
+            token_to_receive_account,
+            mint_a,
+            &token_program_of(&self.rpc, mint_a)?,
+            seed,
+            amount,
+            deposit_amount,
+            unlock_time,
+            time_out,
+            allowed_taker,
+        );
+        sign(&self.rpc, &[instruction], initializer)
+    }
+
+    /// Takes `receive_amount` of the deposit of `escrow`, paying at most `max_pay_amount` for it.
+    /// The taker's accounts are their wallet for a SOL side
+    pub fn exchange_transaction(
+        &self,
+        taker: &Keypair,
+        escrow: &Pubkey,
+        takers_sending_account: &Pubkey,
+        takers_receiving_account: &Pubkey,
+        receive_amount: u64,
+        max_pay_amount: u64,
+    ) -> Result<Transaction, ClientError> {
+        let state = self.get_escrow(escrow)?;
+        let instruction = instruction::exchange(
+            &self.program_id,
+            &taker.pubkey(),
+            takers_sending_account,
+            takers_receiving_account,
+            &state.initializer_pubkey,
+            &state.initializer_token_to_receive_account_pubkey,
+            escrow,
+            &state.mint_a,
+            &token_program_of(&self.rpc, &state.mint_a)?,
+            &state.mint_b,
+            &token_program_of(&self.rpc, &state.mint_b)?,
+            receive_amount,
+            max_pay_amount,
+        );
+        sign(&self.rpc, &[instruction], taker)
+    }
+
+    /// Cancels `escrow`, refunding what is left of the deposit to `refund_account`
+    pub fn cancel_transaction(
+        &self,
+        initializer: &Keypair,
+        escrow: &Pubkey,
+        refund_account: &Pubkey,
+    ) -> Result<Transaction, ClientError> {
+        let state = self.get_escrow(escrow)?;
+        let instruction = instruction::cancel(
+            &self.program_id,
+            &initializer.pubkey(),
+            escrow,
+            refund_account,
+            &state.mint_a,
+            &token_program_of(&self.rpc, &state.mint_a)?,
+        );
+        sign(&self.rpc, &[instruction], initializer)
+    }
+
+    /// Sends a signed transaction and waits for it to be confirmed
+    pub fn send(&self, transaction: &Transaction) -> Result<Signature, ClientError> {
+        send(&self.rpc, transaction)
+    }
+}
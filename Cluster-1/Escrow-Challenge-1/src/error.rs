@@ -22,6 +22,30 @@ pub enum EscrowError {
     /// Trade Timeout
     #[error("Trade Time Out")]
     TradeTimeOut,
+    /// Invalid Time Window
+    #[error("Invalid Time Window")]
+    InvalidTimeWindow,
+    /// Mint Mismatch
+    #[error("Mint Mismatch")]
+    MintMismatch,
+    /// Taker Not Allowed
+    #[error("Taker Not Allowed")]
+    TakerNotAllowed,
+    /// Fee Too High
+    #[error("Fee Too High")]
+    FeeTooHigh,
+    /// Invalid Admin
+    #[error("Invalid Admin")]
+    InvalidAdmin,
+    /// Program Paused
+    #[error("Program Paused")]
+    ProgramPaused,
+    /// Invalid Update
+    #[error("Invalid Update")]
+    InvalidUpdate,
+    /// Max Pay Amount Exceeded
+    #[error("Max Pay Amount Exceeded")]
+    MaxPayAmountExceeded,
 }
 
 impl From<EscrowError> for ProgramError {
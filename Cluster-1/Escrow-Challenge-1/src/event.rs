@@ -0,0 +1,105 @@
+use base64::{engine::general_purpose, Engine};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    entrypoint::ProgramResult, log::sol_log_data, program_error::ProgramError, pubkey::Pubkey,
+};
+
+/// Events of the escrow lifecycle, Borsh encoded and written to the transaction logs with `sol_log_data`,
+/// where they show up as `Program data: <base64>` lines. Slots are those at which the instruction ran
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub enum EscrowEvent {
+    /// An escrow was opened
+    Created {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        /// What reached the escrow, less any transfer fee of mint A
+        deposit_amount: u64,
+        expected_amount: u64,
+        unlock_time: u64,
+        time_out: u64,
+        allowed_taker: Option<Pubkey>,
+        slot: u64,
+    },
+    /// A taker filled the escrow, in part or in whole
+    Exchanged {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        taker: Pubkey,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        /// The part of the deposit taken and its price, before protocol and transfer fees
+        taken_amount: u64,
+        paid_amount: u64,
+        remaining_amount: u64,
+        slot: u64,
+    },
+    /// The initializer changed the terms of an open escrow. What is left of the deposit and its price
+    /// are offered as its deposit and expected amounts from now on
+    Updated {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        deposit_amount: u64,
+        expected_amount: u64,
+        time_out: u64,
+        allowed_taker: Option<Pubkey>,
+        slot: u64,
+    },
+    /// The escrow account was closed, either after its last fill or cancelled by the initializer,
+    /// who got back what was left of the deposit
+    Closed {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        refunded_amount: u64,
+        slot: u64,
+    },
+}
+
+impl EscrowEvent {
+    const LOG_PREFIX: &'static str = "Program data: ";
+
+    pub fn emit(&self) -> ProgramResult {
+        let data = borsh::to_vec(self).map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+        sol_log_data(&[&data]);
+        Ok(())
+    }
+
+    /// Decodes the events of the program deployed at `program_id` from the log messages of a transaction.
+    /// Data logged by other programs, including the ones it invokes, is skipped. The logs of a failed
+    /// transaction still hold the events written before it failed, so check its status first
+    pub fn from_logs(program_id: &Pubkey, logs: &[String]) -> Vec<EscrowEvent> {
+        let program_id = program_id.to_string();
+        // The programs being invoked, the innermost last
+        let mut invoked: Vec<&str> = vec![];
+        let mut events = vec![];
+        for log in logs {
+            if let Some(data) = log.strip_prefix(Self::LOG_PREFIX) {
+                if invoked.last() == Some(&program_id.as_str()) {
+                    events.extend(Self::decode(data));
+                }
+                continue;
+            }
+            let mut words = log.split(' ');
+            match (words.next(), words.next(), words.next()) {
+                (Some("Program"), Some(program), Some("invoke")) => invoked.push(program),
+                (Some("Program"), Some(program), Some("success" | "failed:"))
+                    if invoked.last() == Some(&program) =>
+                {
+                    invoked.pop();
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    /// Decodes the base64 data of a single `Program data:` line, `None` if it isn't an event
+    pub fn decode(data: &str) -> Option<EscrowEvent> {
+        // Each logged field is encoded on its own, events are logged as a single one
+        let data = general_purpose::STANDARD.decode(data).ok()?;
+        EscrowEvent::try_from_slice(&data).ok()
+    }
+}
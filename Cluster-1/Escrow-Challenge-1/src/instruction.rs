@@ -1,66 +1,529 @@
-use solana_program::program_error::ProgramError;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
 use std::convert::TryInto;
 
-use crate::error::EscrowError::InvalidInstruction;
+use crate::{
+    error::EscrowError::InvalidInstruction,
+    pda::{config_address, escrow_address, fee_vault_address, pda_address, vault_address},
+};
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum EscrowInstruction {
-    /// Starts the trade by creating and populating an escrow account and transferring ownership of the given temp token account to the PDA
+    /// Starts the trade by creating and populating the escrow account, creating the vault token account owned by the PDA and depositing tokens into it
     ///
+    /// Either side may be SOL, marked by the wrapped SOL mint. A SOL deposit is held as lamports by the escrow account
+    /// itself and a SOL price is paid to a wallet, so nobody needs a wrapped SOL account
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person initializing the escrow
-    /// 1. `[writable]` Temporary token account that should be created prior to this instruction and owned by the initializer
-    /// 2. `[]` The initializer's token account for the token they will receive should the trade go through
-    /// 3. `[writable]` The escrow account, it will hold all necessary info about the trade.
-    /// 4. `[]` The rent/clock sysvar
-    /// 5. `[]` The token program
+    /// 0. `[signer, writable]` The account of the person initializing the escrow, pays for the escrow and the vault
+    /// 1. `[writable]` The initializer's token account to take the deposit from, unused for a SOL deposit
+    /// 2. `[]` The initializer's token account for the token they will receive should the trade go through, of either token program,
+    ///    or the wallet to receive SOL
+    /// 3. `[writable]` The escrow account, the PDA derived from `[b"escrow", initializer, seed]`, it will hold all necessary info about the trade.
+    /// 4. `[writable]` The vault token account, the PDA derived from `[b"vault", escrow account]`, or the escrow account again for a SOL deposit
+    /// 5. `[]` The mint of the deposited token, the wrapped SOL mint for a SOL deposit
+    /// 6. `[]` The rent sysvar
+    /// 7. `[]` The token program owning the deposited mint, the original token program or Token-2022
+    /// 8. `[]` The system program
+    /// 9. `[]` The config account, no escrow can be opened while the program is paused
     InitEscrow {
+        /// Distinguishes the escrows of one initializer, encoded as little endian bytes in the escrow address seeds
+        seed: u64,
         /// The amount party A expects to receive of token Y
         amount: u64,
+        /// The amount of token X party A deposits into the vault. Transfer fees of the mint are taken out of it,
+        /// the escrow only offers what reaches the vault
+        deposit_amount: u64,
+        /// The slot after which the trade can be taken, defaults to 100 slots from now
+        unlock_time: Option<u64>,
+        /// The slot after which the trade can no longer be taken, defaults to 1000 slots after the unlock
+        time_out: Option<u64>,
+        /// The only account allowed to take the trade, anyone may take it when unset
+        allowed_taker: Option<Pubkey>,
     },
     /// Accepts a trade
     ///
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person taking the trade
-    /// 1. `[writable]` The taker's token account for the token they send
-    /// 2. `[writable]` The taker's token account for the token they will receive should the trade go through
-    /// 3. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 0. `[signer, writable]` The account of the person taking the trade, the allowed taker if the escrow names one.
+    ///    Pays for the fee vaults it creates
+    /// 1. `[writable]` The taker's token account for the token they send, unused when paying SOL
+    /// 2. `[writable]` The taker's token account for the token they will receive should the trade go through, or their wallet to receive SOL
+    /// 3. `[writable]` The PDA's vault token account to get tokens from and close once the offer is filled, or the escrow account for a SOL deposit
     /// 4. `[writable]` The initializer's main account to send their rent fees to
-    /// 5. `[writable]` The initializer's token account that will receive tokens
+    /// 5. `[writable]` The initializer's token account or wallet that will receive the price
     /// 6. `[writable]` The escrow account holding the escrow info
-    /// 7. `[]` The token program
+    /// 7. `[]` The token program owning mint A
     /// 8. `[]` The PDA account
+    /// 9. `[writable]` Mint A, the deposited token, writable so withheld transfer fees can be harvested before closing the vault
+    /// 10. `[]` Mint B, the token the initializer expects
+    /// 11. `[]` The token program owning mint B
+    /// 12. `[]` The system program
+    /// 13. `[writable]` The config account, the PDA derived from `[b"config"]`, it also collects protocol fees paid in SOL.
+    ///     No trade can be taken while the program is paused
+    /// 14. `[writable]` The fee vault of mint A, the PDA derived from `[b"fee_vault", mint A]`, or the config account for SOL.
+    ///     Created by the taker if the fee is taken and it does not exist yet
+    /// 15. `[writable]` The fee vault of mint B, likewise
     Exchange {
-        /// the amount the taker expects to be paid in the other token, as a u64 because that's the max possible supply of a token
-        amount: u64,
+        /// the amount the taker expects to be paid in the other token, as a u64 because that's the max possible supply of a token.
+        /// It may be less than what is left in the escrow, the taker then pays the same share of the remaining expected amount.
+        /// The protocol fee is skimmed from both legs: the taker gets `receive_amount` less the fee and the initializer
+        /// the price less the fee
+        receive_amount: u64,
+        /// The most the taker agrees to send for it, the price plus any transfer fee of mint B, which the taker
+        /// covers so that the initializer receives the whole price. Guards the taker against terms changed
+        /// before the exchange lands
+        max_pay_amount: u64,
+    },
+    /// Cancels a trade, refunding the deposit and closing the vault and escrow accounts.
+    /// It stays open while the program is paused, so deposits can always be taken back
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person who initialized the escrow, receives the rent fees
+    /// 1. `[writable]` The PDA's vault token account to refund tokens from and eventually close, or the escrow account for a SOL deposit
+    /// 2. `[writable]` The initializer's token account that will get the deposited tokens back, unused for a SOL deposit
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[]` The token program owning mint A
+    /// 5. `[]` The PDA account
+    /// 6. `[writable]` Mint A, the deposited token, writable so withheld transfer fees can be harvested before closing the vault
+    Cancel,
+    /// Creates the program config, only the upgrade authority of the program may and becomes its admin
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The admin, the upgrade authority of the program, pays for the config account
+    /// 1. `[writable]` The config account, the PDA derived from `[b"config"]`
+    /// 2. `[]` The account which may receive the protocol fees
+    /// 3. `[]` The system program
+    /// 4. `[]` The program data account of the program, naming its upgrade authority
+    InitConfig {
+        /// Protocol fee taken from each leg of an exchange, at most `EscrowConfig::MAX_FEE_BASIS_POINTS`
+        fee_basis_points: u16,
+    },
+    /// Changes the protocol fee
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin
+    /// 1. `[writable]` The config account
+    SetFee { fee_basis_points: u16 },
+    /// Changes the account receiving the protocol fees
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin
+    /// 1. `[writable]` The config account
+    /// 2. `[]` The new fee recipient
+    SetFeeRecipient,
+    /// Withdraws accrued protocol fees of one mint to the fee recipient
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin
+    /// 1. `[writable]` The config account
+    /// 2. `[writable]` The fee vault of the mint, or the config account for SOL
+    /// 3. `[writable]` The fee recipient's token account of the mint, or the fee recipient itself for SOL
+    /// 4. `[]` The mint, the wrapped SOL mint for SOL
+    /// 5. `[]` The token program owning the mint, unused for SOL
+    WithdrawFees { amount: u64 },
+    /// Hands the config over to a new admin, who has to sign as well so the key can't be lost to a typo
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin
+    /// 1. `[writable]` The config account
+    /// 2. `[signer]` The new admin
+    SetAdmin,
+    /// Pauses or resumes the program. While paused no escrow can be opened or taken,
+    /// only cancels and the admin instructions go through
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin
+    /// 1. `[writable]` The config account
+    SetPaused { paused: bool },
+    /// Changes the terms of an open escrow, only its initializer may. The escrow then records what is left
+    /// of the deposit and its price as its deposit and expected amounts, and logs them for takers to see.
+    /// Once timed out an escrow can only be cancelled
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The initializer, pays a SOL top up and receives a SOL withdrawal
+    /// 1. `[writable]` The escrow account
+    /// 2. `[writable]` The PDA's vault token account, or the escrow account for a SOL deposit
+    /// 3. `[writable]` The initializer's token account to top up from or withdraw to, unused for a SOL deposit
+    /// 4. `[]` Mint A, the deposited token
+    /// 5. `[]` The token program owning mint A
+    /// 6. `[]` The PDA account
+    /// 7. `[]` The system program
+    /// 8. `[]` The config account, no escrow can be updated while the program is paused
+    UpdateEscrow {
+        /// The new price of what is left of the deposit, unchanged when unset
+        expected_amount: Option<u64>,
+        /// The new slot after which the trade can no longer be taken, unchanged when unset
+        time_out: Option<u64>,
+        /// The new allowed taker, unchanged when unset. `Some(None)` opens the trade to anyone
+        allowed_taker: Option<Option<Pubkey>>,
+        /// Added to the deposit. Transfer fees of the mint are taken out of it, only what reaches the vault is added
+        top_up_amount: u64,
+        /// Taken out of the deposit, which must not be emptied. Cancel takes the whole deposit back
+        withdraw_amount: u64,
     },
 }
 
 impl EscrowInstruction {
     /// Unpacks a byte buffer into a [EscrowInstruction](enum.EscrowInstruction.html).
+    /// The buffer has to hold exactly the fields of the instruction, trailing bytes are rejected
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
         let (tag, rest) = input.split_first().ok_or(InvalidInstruction)?;
 
-        Ok(match tag {
-            0 => Self::InitEscrow {
-                amount: Self::unpack_amount(rest)?,
-            },
-            1 => Self::Exchange {
-                amount: Self::unpack_amount(rest)?,
-            },
+        let (instruction, rest) = match tag {
+            0 => {
+                let (seed, rest) = Self::unpack_amount(rest)?;
+                let (amount, rest) = Self::unpack_amount(rest)?;
+                let (deposit_amount, rest) = Self::unpack_amount(rest)?;
+                let (unlock_time, rest) = Self::unpack_u64_option(rest)?;
+                let (time_out, rest) = Self::unpack_u64_option(rest)?;
+                let (allowed_taker, rest) = Self::unpack_pubkey_option(rest)?;
+                let instruction = Self::InitEscrow {
+                    seed,
+                    amount,
+                    deposit_amount,
+                    unlock_time,
+                    time_out,
+                    allowed_taker,
+                };
+                (instruction, rest)
+            }
+            1 => {
+                let (receive_amount, rest) = Self::unpack_amount(rest)?;
+                let (max_pay_amount, rest) = Self::unpack_amount(rest)?;
+                let instruction = Self::Exchange {
+                    receive_amount,
+                    max_pay_amount,
+                };
+                (instruction, rest)
+            }
+            2 => (Self::Cancel, rest),
+            3 => {
+                let (fee_basis_points, rest) = Self::unpack_basis_points(rest)?;
+                (Self::InitConfig { fee_basis_points }, rest)
+            }
+            4 => {
+                let (fee_basis_points, rest) = Self::unpack_basis_points(rest)?;
+                (Self::SetFee { fee_basis_points }, rest)
+            }
+            5 => (Self::SetFeeRecipient, rest),
+            6 => {
+                let (amount, rest) = Self::unpack_amount(rest)?;
+                (Self::WithdrawFees { amount }, rest)
+            }
+            7 => (Self::SetAdmin, rest),
+            8 => {
+                let (paused, rest) = Self::unpack_bool(rest)?;
+                (Self::SetPaused { paused }, rest)
+            }
+            9 => {
+                let (expected_amount, rest) = Self::unpack_u64_option(rest)?;
+                let (time_out, rest) = Self::unpack_u64_option(rest)?;
+                let (allowed_taker, rest) = match rest.split_first() {
+                    Some((&0, rest)) => (None, rest),
+                    Some((&1, rest)) => {
+                        let (allowed_taker, rest) = Self::unpack_pubkey_option(rest)?;
+                        (Some(allowed_taker), rest)
+                    }
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                let (top_up_amount, rest) = Self::unpack_amount(rest)?;
+                let (withdraw_amount, rest) = Self::unpack_amount(rest)?;
+                let instruction = Self::UpdateEscrow {
+                    expected_amount,
+                    time_out,
+                    allowed_taker,
+                    top_up_amount,
+                    withdraw_amount,
+                };
+                (instruction, rest)
+            }
             _ => return Err(InvalidInstruction.into()),
-        })
+        };
+        if !rest.is_empty() {
+            return Err(InvalidInstruction.into());
+        }
+        Ok(instruction)
+    }
+
+    /// Packs a [EscrowInstruction](enum.EscrowInstruction.html) into a byte buffer, the inverse of `unpack`
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Self::InitEscrow {
+                seed,
+                amount,
+                deposit_amount,
+                unlock_time,
+                time_out,
+                allowed_taker,
+            } => {
+                buf.push(0);
+                buf.extend_from_slice(&seed.to_le_bytes());
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&deposit_amount.to_le_bytes());
+                Self::pack_u64_option(unlock_time, &mut buf);
+                Self::pack_u64_option(time_out, &mut buf);
+                Self::pack_pubkey_option(allowed_taker, &mut buf);
+            }
+            Self::Exchange {
+                receive_amount,
+                max_pay_amount,
+            } => {
+                buf.push(1);
+                buf.extend_from_slice(&receive_amount.to_le_bytes());
+                buf.extend_from_slice(&max_pay_amount.to_le_bytes());
+            }
+            Self::Cancel => buf.push(2),
+            Self::InitConfig { fee_basis_points } => {
+                buf.push(3);
+                buf.extend_from_slice(&fee_basis_points.to_le_bytes());
+            }
+            Self::SetFee { fee_basis_points } => {
+                buf.push(4);
+                buf.extend_from_slice(&fee_basis_points.to_le_bytes());
+            }
+            Self::SetFeeRecipient => buf.push(5),
+            Self::WithdrawFees { amount } => {
+                buf.push(6);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::SetAdmin => buf.push(7),
+            Self::SetPaused { paused } => {
+                buf.push(8);
+                buf.push(*paused as u8);
+            }
+            Self::UpdateEscrow {
+                expected_amount,
+                time_out,
+                allowed_taker,
+                top_up_amount,
+                withdraw_amount,
+            } => {
+                buf.push(9);
+                Self::pack_u64_option(expected_amount, &mut buf);
+                Self::pack_u64_option(time_out, &mut buf);
+                match allowed_taker {
+                    Some(allowed_taker) => {
+                        buf.push(1);
+                        Self::pack_pubkey_option(allowed_taker, &mut buf);
+                    }
+                    None => buf.push(0),
+                }
+                buf.extend_from_slice(&top_up_amount.to_le_bytes());
+                buf.extend_from_slice(&withdraw_amount.to_le_bytes());
+            }
+        }
+        buf
     }
 
-    fn unpack_amount(input: &[u8]) -> Result<u64, ProgramError> {
+    fn unpack_amount(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
         let amount = input
             .get(..8)
             .and_then(|slice| slice.try_into().ok())
             .map(u64::from_le_bytes)
             .ok_or(InvalidInstruction)?;
-        Ok(amount)
+        Ok((amount, &input[8..]))
+    }
+
+    fn unpack_basis_points(input: &[u8]) -> Result<(u16, &[u8]), ProgramError> {
+        let basis_points = input
+            .get(..2)
+            .and_then(|slice| slice.try_into().ok())
+            .map(u16::from_le_bytes)
+            .ok_or(InvalidInstruction)?;
+        Ok((basis_points, &input[2..]))
+    }
+
+    fn unpack_bool(input: &[u8]) -> Result<(bool, &[u8]), ProgramError> {
+        match input.split_first() {
+            Some((&0, rest)) => Ok((false, rest)),
+            Some((&1, rest)) => Ok((true, rest)),
+            _ => Err(InvalidInstruction.into()),
+        }
+    }
+
+    fn unpack_u64_option(input: &[u8]) -> Result<(Option<u64>, &[u8]), ProgramError> {
+        match input.split_first() {
+            Some((&0, rest)) => Ok((None, rest)),
+            Some((&1, rest)) => {
+                let (value, rest) = Self::unpack_amount(rest)?;
+                Ok((Some(value), rest))
+            }
+            _ => Err(InvalidInstruction.into()),
+        }
+    }
+
+    fn unpack_pubkey_option(input: &[u8]) -> Result<(Option<Pubkey>, &[u8]), ProgramError> {
+        match input.split_first() {
+            Some((&0, rest)) => Ok((None, rest)),
+            Some((&1, rest)) => {
+                let key = rest
+                    .get(..32)
+                    .and_then(|slice| slice.try_into().ok())
+                    .map(Pubkey::new_from_array)
+                    .ok_or(InvalidInstruction)?;
+                Ok((Some(key), &rest[32..]))
+            }
+            _ => Err(InvalidInstruction.into()),
+        }
+    }
+
+    fn pack_u64_option(value: &Option<u64>, buf: &mut Vec<u8>) {
+        match value {
+            Some(value) => {
+                buf.push(1);
+                buf.extend_from_slice(&value.to_le_bytes());
+            }
+            None => buf.push(0),
+        }
+    }
+
+    fn pack_pubkey_option(key: &Option<Pubkey>, buf: &mut Vec<u8>) {
+        match key {
+            Some(key) => {
+                buf.push(1);
+                buf.extend_from_slice(key.as_ref());
+            }
+            None => buf.push(0),
+        }
+    }
+}
+
+/// Creates an `InitEscrow` instruction, deriving the escrow, vault and config accounts.
+/// For a SOL deposit `deposit_account` goes unused, any account may be passed
+#[allow(clippy::too_many_arguments)]
+pub fn init_escrow(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    deposit_account: &Pubkey,
+    token_to_receive_account: &Pubkey,
+    mint_a: &Pubkey,
+    token_program: &Pubkey,
+    seed: u64,
+    amount: u64,
+    deposit_amount: u64,
+    unlock_time: Option<u64>,
+    time_out: Option<u64>,
+    allowed_taker: Option<Pubkey>,
+) -> Instruction {
+    let escrow = escrow_address(program_id, initializer, seed);
+    let data = EscrowInstruction::InitEscrow {
+        seed,
+        amount,
+        deposit_amount,
+        unlock_time,
+        time_out,
+        allowed_taker,
+    }
+    .pack();
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new(*deposit_account, false),
+            AccountMeta::new_readonly(*token_to_receive_account, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault_address(program_id, &escrow, mint_a), false),
+            AccountMeta::new_readonly(*mint_a, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_address(program_id), false),
+        ],
+        data,
+    }
+}
+
+/// Creates an `Exchange` instruction taking `receive_amount` of the deposit, deriving the vault,
+/// PDA, config and fee vault accounts. The taker's accounts are their wallet for a SOL side
+#[allow(clippy::too_many_arguments)]
+pub fn exchange(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    takers_sending_account: &Pubkey,
+    takers_receiving_account: &Pubkey,
+    initializer: &Pubkey,
+    initializers_receiving_account: &Pubkey,
+    escrow: &Pubkey,
+    mint_a: &Pubkey,
+    token_program_a: &Pubkey,
+    mint_b: &Pubkey,
+    token_program_b: &Pubkey,
+    receive_amount: u64,
+    max_pay_amount: u64,
+) -> Instruction {
+    let data = EscrowInstruction::Exchange {
+        receive_amount,
+        max_pay_amount,
+    }
+    .pack();
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(*takers_sending_account, false),
+            AccountMeta::new(*takers_receiving_account, false),
+            AccountMeta::new(vault_address(program_id, escrow, mint_a), false),
+            AccountMeta::new(*initializer, false),
+            AccountMeta::new(*initializers_receiving_account, false),
+            AccountMeta::new(*escrow, false),
+            AccountMeta::new_readonly(*token_program_a, false),
+            AccountMeta::new_readonly(pda_address(program_id, escrow, initializer), false),
+            AccountMeta::new(*mint_a, false),
+            AccountMeta::new_readonly(*mint_b, false),
+            AccountMeta::new_readonly(*token_program_b, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(config_address(program_id), false),
+            AccountMeta::new(fee_vault_address(program_id, mint_a), false),
+            AccountMeta::new(fee_vault_address(program_id, mint_b), false),
+        ],
+        data,
+    }
+}
+
+/// Creates a `Cancel` instruction refunding the deposit to `refund_account`, deriving the vault and PDA.
+/// For a SOL deposit `refund_account` goes unused, the lamports go back to the initializer
+pub fn cancel(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    escrow: &Pubkey,
+    refund_account: &Pubkey,
+    mint_a: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new(vault_address(program_id, escrow, mint_a), false),
+            AccountMeta::new(*refund_account, false),
+            AccountMeta::new(*escrow, false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(pda_address(program_id, escrow, initializer), false),
+            AccountMeta::new(*mint_a, false),
+        ],
+        data: EscrowInstruction::Cancel.pack(),
     }
 }
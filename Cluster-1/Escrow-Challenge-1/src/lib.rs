@@ -1,5 +1,9 @@
+#[cfg(feature = "client")]
+pub mod client;
 pub mod error;
+pub mod event;
 pub mod instruction;
+pub mod pda;
 pub mod processor;
 pub mod state;
 
@@ -0,0 +1,53 @@
+//! Seeds of the accounts the program derives, and the addresses they derive to
+
+use solana_program::pubkey::Pubkey;
+
+pub(crate) const ESCROW_PDA_SEED: &[u8] = b"escrow";
+pub(crate) const VAULT_SEED: &[u8] = b"vault";
+pub(crate) const CONFIG_SEED: &[u8] = b"config";
+pub(crate) const FEE_VAULT_SEED: &[u8] = b"fee_vault";
+
+/// The escrow of an initializer and one of their seeds
+pub fn escrow_address(program_id: &Pubkey, initializer: &Pubkey, seed: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[ESCROW_PDA_SEED, initializer.as_ref(), &seed.to_le_bytes()],
+        program_id,
+    )
+    .0
+}
+
+/// The token account holding the deposit of an escrow, the escrow account itself for a SOL deposit
+pub fn vault_address(program_id: &Pubkey, escrow: &Pubkey, mint_a: &Pubkey) -> Pubkey {
+    if is_sol(mint_a) {
+        *escrow
+    } else {
+        Pubkey::find_program_address(&[VAULT_SEED, escrow.as_ref()], program_id).0
+    }
+}
+
+/// The PDA owning the vault of an escrow
+pub fn pda_address(program_id: &Pubkey, escrow: &Pubkey, initializer: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[ESCROW_PDA_SEED, escrow.as_ref(), initializer.as_ref()],
+        program_id,
+    )
+    .0
+}
+
+/// The config account holding the protocol fee and its admin
+pub fn config_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[CONFIG_SEED], program_id).0
+}
+
+/// Where the protocol fees of a mint are held, the config account itself for SOL
+pub fn fee_vault_address(program_id: &Pubkey, mint: &Pubkey) -> Pubkey {
+    if is_sol(mint) {
+        config_address(program_id)
+    } else {
+        Pubkey::find_program_address(&[FEE_VAULT_SEED, mint.as_ref()], program_id).0
+    }
+}
+
+fn is_sol(mint: &Pubkey) -> bool {
+    *mint == spl_token::native_mint::id()
+}
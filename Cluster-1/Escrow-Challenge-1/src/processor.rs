@@ -1,17 +1,31 @@
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
     entrypoint::ProgramResult,
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
-    program_pack::{IsInitialized, Pack},
+    program_pack::Pack,
     pubkey::Pubkey,
+    system_instruction, system_program,
     sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
 
-use spl_token::state::Account as TokenAccount;
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{TransferFeeAmount, TransferFeeConfig},
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+    state::{Account as TokenAccount, Mint},
+};
 
-use crate::{error::EscrowError, instruction::EscrowInstruction, state::Escrow};
+use crate::{
+    error::EscrowError,
+    event::EscrowEvent,
+    instruction::EscrowInstruction,
+    pda::{CONFIG_SEED, ESCROW_PDA_SEED, FEE_VAULT_SEED, VAULT_SEED},
+    state::{Escrow, EscrowConfig},
+};
 
 pub struct Processor;
 impl Processor {
@@ -23,20 +37,91 @@ impl Processor {
         let instruction = EscrowInstruction::unpack(instruction_data)?;
 
         match instruction {
-            EscrowInstruction::InitEscrow { amount } => {
+            EscrowInstruction::InitEscrow {
+                seed,
+                amount,
+                deposit_amount,
+                unlock_time,
+                time_out,
+                allowed_taker,
+            } => {
                 msg!("Instruction: InitEscrow");
-                Self::process_init_escrow(accounts, amount, program_id)
+                Self::process_init_escrow(
+                    accounts,
+                    seed,
+                    amount,
+                    deposit_amount,
+                    unlock_time,
+                    time_out,
+                    allowed_taker,
+                    program_id,
+                )
             }
-            EscrowInstruction::Exchange { amount } => {
+            EscrowInstruction::Exchange {
+                receive_amount,
+                max_pay_amount,
+            } => {
                 msg!("Instruction: Exchange");
-                Self::process_exchange(accounts, amount, program_id)
+                Self::process_exchange(accounts, receive_amount, max_pay_amount, program_id)
+            }
+            EscrowInstruction::Cancel => {
+                msg!("Instruction: Cancel");
+                Self::process_cancel(accounts, program_id)
+            }
+            EscrowInstruction::InitConfig { fee_basis_points } => {
+                msg!("Instruction: InitConfig");
+                Self::process_init_config(accounts, fee_basis_points, program_id)
+            }
+            EscrowInstruction::SetFee { fee_basis_points } => {
+                msg!("Instruction: SetFee");
+                Self::process_set_fee(accounts, fee_basis_points, program_id)
+            }
+            EscrowInstruction::SetFeeRecipient => {
+                msg!("Instruction: SetFeeRecipient");
+                Self::process_set_fee_recipient(accounts, program_id)
+            }
+            EscrowInstruction::WithdrawFees { amount } => {
+                msg!("Instruction: WithdrawFees");
+                Self::process_withdraw_fees(accounts, amount, program_id)
+            }
+            EscrowInstruction::SetAdmin => {
+                msg!("Instruction: SetAdmin");
+                Self::process_set_admin(accounts, program_id)
+            }
+            EscrowInstruction::SetPaused { paused } => {
+                msg!("Instruction: SetPaused");
+                Self::process_set_paused(accounts, paused, program_id)
+            }
+            EscrowInstruction::UpdateEscrow {
+                expected_amount,
+                time_out,
+                allowed_taker,
+                top_up_amount,
+                withdraw_amount,
+            } => {
+                msg!("Instruction: UpdateEscrow");
+                Self::process_update_escrow(
+                    accounts,
+                    expected_amount,
+                    time_out,
+                    allowed_taker,
+                    top_up_amount,
+                    withdraw_amount,
+                    program_id,
+                )
             }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn process_init_escrow(
         accounts: &[AccountInfo],
+        seed: u64,
         amount: u64,
+        deposit_amount: u64,
+        unlock_time: Option<u64>,
+        time_out: Option<u64>,
+        allowed_taker: Option<Pubkey>,
         program_id: &Pubkey,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
@@ -46,59 +131,189 @@ impl Processor {
             return Err(ProgramError::MissingRequiredSignature);
         }
 
-        let temp_token_account = next_account_info(account_info_iter)?;
+        let initializers_deposit_token_account = next_account_info(account_info_iter)?;
 
         let token_to_receive_account = next_account_info(account_info_iter)?;
-        if *token_to_receive_account.owner != spl_token::id() {
-            return Err(ProgramError::IncorrectProgramId);
-        }
+        // A wallet instead of a token account means the initializer asks for SOL
+        let mint_b = if *token_to_receive_account.owner == system_program::id() {
+            spl_token::native_mint::id()
+        } else {
+            Self::unpack_token_account(token_to_receive_account)?.mint
+        };
 
         let escrow_account = next_account_info(account_info_iter)?;
+        let vault_account = next_account_info(account_info_iter)?;
+        let mint_account = next_account_info(account_info_iter)?;
 
-        // Since the sysvar account is already here, I'll use it not only for Rent, but for Clock as well.
-        let sysvar = next_account_info(account_info_iter)?;
-        let rent = &Rent::from_account_info(sysvar)?;
-        let clock = &Clock::from_account_info(sysvar)?;
-
-        if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
-            return Err(EscrowError::NotRentExempt.into());
+        // There is no point in trading a token for the same token
+        if *mint_account.key == mint_b {
+            return Err(EscrowError::MintMismatch.into());
         }
 
-        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.try_borrow_data()?)?;
-        if escrow_info.is_initialized() {
-            return Err(ProgramError::AccountAlreadyInitialized);
+        let rent_sysvar = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_sysvar)?;
+        // The rent sysvar account can't be read as Clock, so the clock comes from the runtime
+        let clock = Clock::get()?;
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program, mint_account)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        let config_account = next_account_info(account_info_iter)?;
+        Self::check_not_paused(&Self::unpack_config(config_account, program_id)?)?;
+
+        // The escrow address is derived from the initializer and their seed, so clients can find it
+        // without keeping a keypair around. Creating it fails if the initializer already uses the seed
+        let seed_bytes = seed.to_le_bytes();
+        let (escrow, escrow_bump_seed) = Pubkey::find_program_address(
+            &[ESCROW_PDA_SEED, initializer.key.as_ref(), &seed_bytes],
+            program_id,
+        );
+        if escrow != *escrow_account.key {
+            return Err(ProgramError::InvalidSeeds);
         }
 
+        let create_escrow_ix = system_instruction::create_account(
+            initializer.key,
+            escrow_account.key,
+            rent.minimum_balance(Escrow::LEN),
+            Escrow::LEN as u64,
+            program_id,
+        );
+        msg!("Calling the system program to create the escrow...");
+        invoke_signed(
+            &create_escrow_ix,
+            &[
+                initializer.clone(),
+                escrow_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[
+                ESCROW_PDA_SEED,
+                initializer.key.as_ref(),
+                &seed_bytes,
+                &[escrow_bump_seed],
+            ]],
+        )?;
+
+        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.try_borrow_data()?)?;
         escrow_info.is_initialized = true;
+        escrow_info.version = Escrow::VERSION;
         escrow_info.initializer_pubkey = *initializer.key;
-        escrow_info.temp_token_account_pubkey = *temp_token_account.key;
+        escrow_info.temp_token_account_pubkey = *vault_account.key;
         escrow_info.initializer_token_to_receive_account_pubkey = *token_to_receive_account.key;
+        escrow_info.mint_a = *mint_account.key;
+        escrow_info.mint_b = mint_b;
         escrow_info.expected_amount = amount;
-        escrow_info.unlock_time = clock.slot + 100;
-        escrow_info.time_out = clock.slot + 1100;
+        escrow_info.remaining_expected_amount = amount;
+        escrow_info.allowed_taker = allowed_taker;
 
-        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
-        let (pda, _nonce) = Pubkey::find_program_address(&[b"escrow"], program_id);
-
-        let token_program = next_account_info(account_info_iter)?;
-        let owner_change_ix = spl_token::instruction::set_authority(
-            token_program.key,
-            temp_token_account.key,
-            Some(&pda),
-            spl_token::instruction::AuthorityType::AccountOwner,
-            initializer.key,
-            &[&initializer.key],
-        )?;
+        let unlock_time = unlock_time.unwrap_or(clock.slot + 100);
+        let time_out = match time_out {
+            Some(time_out) => time_out,
+            None => unlock_time
+                .checked_add(1000)
+                .ok_or(EscrowError::InvalidTimeWindow)?,
+        };
+        // The trade must unlock before it times out, and must not be expired already
+        if unlock_time > time_out || time_out <= clock.slot {
+            return Err(EscrowError::InvalidTimeWindow.into());
+        }
+        escrow_info.unlock_time = unlock_time;
+        escrow_info.time_out = time_out;
 
-        msg!("Calling the token program to transfer token account ownership...");
-        invoke(
-            &owner_change_ix,
+        // Every escrow gets its own PDA, so one authority never holds the vaults of all trades
+        let (pda, bump_seed) = Pubkey::find_program_address(
             &[
-                temp_token_account.clone(),
-                initializer.clone(),
-                token_program.clone(),
+                ESCROW_PDA_SEED,
+                escrow_account.key.as_ref(),
+                initializer.key.as_ref(),
             ],
-        )?;
+            program_id,
+        );
+        escrow_info.bump_seed = bump_seed;
+
+        let deposited_amount = if Self::is_sol(mint_account.key) {
+            // A SOL deposit stays as lamports in the escrow account itself, there is no vault
+            if vault_account.key != escrow_account.key {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            let deposit_ix =
+                system_instruction::transfer(initializer.key, escrow_account.key, deposit_amount);
+            msg!("Calling the system program to transfer the deposit to the escrow...");
+            invoke(
+                &deposit_ix,
+                &[
+                    initializer.clone(),
+                    escrow_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+            deposit_amount
+        } else {
+            // The vault address is derived from the escrow, so nobody can hold it before the program does
+            let (vault, vault_bump_seed) = Pubkey::find_program_address(
+                &[VAULT_SEED, escrow_account.key.as_ref()],
+                program_id,
+            );
+            if vault != *vault_account.key {
+                return Err(ProgramError::InvalidSeeds);
+            }
+
+            Self::create_token_vault(
+                initializer,
+                vault_account,
+                &[VAULT_SEED, escrow_account.key.as_ref(), &[vault_bump_seed]],
+                mint_account,
+                &pda,
+                token_program,
+                system_program,
+                rent,
+            )?;
+
+            let deposit_ix = spl_token_2022::instruction::transfer_checked(
+                token_program.key,
+                initializers_deposit_token_account.key,
+                mint_account.key,
+                vault_account.key,
+                initializer.key,
+                &[initializer.key],
+                deposit_amount,
+                Self::unpack_mint(mint_account)?.decimals,
+            )?;
+            msg!("Calling the token program to transfer the deposit to the vault...");
+            invoke(
+                &deposit_ix,
+                &[
+                    initializers_deposit_token_account.clone(),
+                    mint_account.clone(),
+                    vault_account.clone(),
+                    initializer.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+
+            // A transfer fee mint withholds part of the deposit, only what reached the vault is offered
+            Self::unpack_token_account(vault_account)?.amount
+        };
+        escrow_info.deposit_amount = deposited_amount;
+        escrow_info.remaining_amount = deposited_amount;
+
+        EscrowEvent::Created {
+            escrow: *escrow_account.key,
+            initializer: escrow_info.initializer_pubkey,
+            mint_a: escrow_info.mint_a,
+            mint_b: escrow_info.mint_b,
+            deposit_amount: escrow_info.deposit_amount,
+            expected_amount: escrow_info.expected_amount,
+            unlock_time: escrow_info.unlock_time,
+            time_out: escrow_info.time_out,
+            allowed_taker: escrow_info.allowed_taker,
+            slot: clock.slot,
+        }
+        .emit()?;
+
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
 
         Ok(())
     }
@@ -106,6 +321,7 @@ impl Processor {
     fn process_exchange(
         accounts: &[AccountInfo],
         amount_expected_by_taker: u64,
+        max_pay_amount: u64,
         program_id: &Pubkey,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
@@ -120,19 +336,46 @@ impl Processor {
         let takers_token_to_receive_account = next_account_info(account_info_iter)?;
 
         let pdas_temp_token_account = next_account_info(account_info_iter)?;
-        let pdas_temp_token_account_info =
-            TokenAccount::unpack(&pdas_temp_token_account.try_borrow_data()?)?;
-        let (pda, nonce) = Pubkey::find_program_address(&[b"escrow"], program_id);
-
-        if amount_expected_by_taker != pdas_temp_token_account_info.amount {
-            return Err(EscrowError::ExpectedAmountMismatch.into());
-        }
 
         let initializers_main_account = next_account_info(account_info_iter)?;
         let initializers_token_to_receive_account = next_account_info(account_info_iter)?;
         let escrow_account = next_account_info(account_info_iter)?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
 
-        let escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
+        // A private escrow can only be taken by the counterparty it names
+        if let Some(allowed_taker) = escrow_info.allowed_taker {
+            if allowed_taker != *taker.key {
+                return Err(EscrowError::TakerNotAllowed.into());
+            }
+        }
+
+        // The token program would move tokens of any mint, so the taker's accounts are checked here.
+        // A SOL leg is paid from or to the taker's wallet instead
+        if !Self::is_sol(&escrow_info.mint_b) {
+            let takers_sending_token_account_info =
+                Self::unpack_token_account(takers_sending_token_account)?;
+            if takers_sending_token_account_info.mint != escrow_info.mint_b {
+                return Err(EscrowError::MintMismatch.into());
+            }
+        }
+
+        if !Self::is_sol(&escrow_info.mint_a) {
+            let takers_token_to_receive_account_info =
+                Self::unpack_token_account(takers_token_to_receive_account)?;
+            if takers_token_to_receive_account_info.mint != escrow_info.mint_a {
+                return Err(EscrowError::MintMismatch.into());
+            }
+        }
+
+        // The taker may fill only a part of the offer, but never more than what is left
+        if amount_expected_by_taker == 0 || amount_expected_by_taker > escrow_info.remaining_amount
+        {
+            return Err(EscrowError::ExpectedAmountMismatch.into());
+        }
+        let amount_to_pay = Self::amount_to_pay(&escrow_info, amount_expected_by_taker)?;
 
         // For education goal, this time i'll get Clock by using runtime query
         let clock = Clock::get()?;
@@ -160,69 +403,653 @@ impl Processor {
         }
 
         let token_program = next_account_info(account_info_iter)?;
+        let pda_account = next_account_info(account_info_iter)?;
 
-        let transfer_to_initializer_ix = spl_token::instruction::transfer(
-            token_program.key,
-            takers_sending_token_account.key,
-            initializers_token_to_receive_account.key,
-            taker.key,
-            &[&taker.key],
-            escrow_info.expected_amount,
-        )?;
-        msg!("Calling the token program to transfer tokens to the escrow's initializer...");
-        invoke(
-            &transfer_to_initializer_ix,
-            &[
-                takers_sending_token_account.clone(),
-                initializers_token_to_receive_account.clone(),
-                taker.clone(),
-                token_program.clone(),
-            ],
-        )?;
+        let mint_a_account = next_account_info(account_info_iter)?;
+        let mint_b_account = next_account_info(account_info_iter)?;
+        if escrow_info.mint_a != *mint_a_account.key || escrow_info.mint_b != *mint_b_account.key {
+            return Err(EscrowError::MintMismatch.into());
+        }
+        // Each side of the trade goes through the token program owning its mint
+        let token_program_b = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program, mint_a_account)?;
+        Self::check_token_program(token_program_b, mint_b_account)?;
 
-        let pda_account = next_account_info(account_info_iter)?;
+        // The bump was stored at init, so there is no need to search for it again
+        let pda_seeds: &[&[u8]] = &[
+            ESCROW_PDA_SEED,
+            escrow_account.key.as_ref(),
+            escrow_info.initializer_pubkey.as_ref(),
+            &[escrow_info.bump_seed],
+        ];
+        let pda = Pubkey::create_program_address(pda_seeds, program_id)?;
+        if pda != *pda_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        let system_program = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let config_info = Self::unpack_config(config_account, program_id)?;
+        Self::check_not_paused(&config_info)?;
+        let fee_vault_a = next_account_info(account_info_iter)?;
+        let fee_vault_b = next_account_info(account_info_iter)?;
+
+        // The protocol fee, see `EscrowInstruction::Exchange`
+        let fee_a = Self::protocol_fee(&config_info, amount_expected_by_taker);
+        let fee_b = Self::protocol_fee(&config_info, amount_to_pay);
+        let amount_to_initializer = amount_to_pay - fee_b;
+        let amount_to_taker = amount_expected_by_taker - fee_a;
+
+        // The initializer must receive the whole price, so the taker also covers the transfer fee.
+        // What the taker sends in total is bounded by them, in case the terms changed in the meantime
+        let transfer_fee = if Self::is_sol(&escrow_info.mint_b) {
+            0
+        } else {
+            Self::inverse_transfer_fee(mint_b_account, amount_to_initializer)?
+        };
+        if amount_to_pay
+            .checked_add(transfer_fee)
+            .ok_or(EscrowError::AmountOverflow)?
+            > max_pay_amount
+        {
+            return Err(EscrowError::MaxPayAmountExceeded.into());
+        }
+
+        if Self::is_sol(&escrow_info.mint_b) {
+            let transfer_to_initializer_ix = system_instruction::transfer(
+                taker.key,
+                initializers_token_to_receive_account.key,
+                amount_to_initializer,
+            );
+            msg!("Calling the system program to transfer SOL to the escrow's initializer...");
+            invoke(
+                &transfer_to_initializer_ix,
+                &[
+                    taker.clone(),
+                    initializers_token_to_receive_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+
+            if fee_b > 0 {
+                // Protocol fees in SOL are collected by the config account itself
+                if fee_vault_b.key != config_account.key {
+                    return Err(ProgramError::InvalidAccountData);
+                }
+                let transfer_fee_ix =
+                    system_instruction::transfer(taker.key, config_account.key, fee_b);
+                msg!("Calling the system program to transfer the protocol fee...");
+                invoke(
+                    &transfer_fee_ix,
+                    &[
+                        taker.clone(),
+                        config_account.clone(),
+                        system_program.clone(),
+                    ],
+                )?;
+            }
+        } else {
+            let transfer_to_initializer_ix = spl_token_2022::instruction::transfer_checked(
+                token_program_b.key,
+                takers_sending_token_account.key,
+                mint_b_account.key,
+                initializers_token_to_receive_account.key,
+                taker.key,
+                &[taker.key],
+                amount_to_initializer
+                    .checked_add(transfer_fee)
+                    .ok_or(EscrowError::AmountOverflow)?,
+                Self::unpack_mint(mint_b_account)?.decimals,
+            )?;
+            msg!("Calling the token program to transfer tokens to the escrow's initializer...");
+            invoke(
+                &transfer_to_initializer_ix,
+                &[
+                    takers_sending_token_account.clone(),
+                    mint_b_account.clone(),
+                    initializers_token_to_receive_account.clone(),
+                    taker.clone(),
+                    token_program_b.clone(),
+                ],
+            )?;
+
+            if fee_b > 0 {
+                Self::prepare_fee_vault(
+                    taker,
+                    fee_vault_b,
+                    mint_b_account,
+                    config_account,
+                    token_program_b,
+                    system_program,
+                    program_id,
+                )?;
+                msg!("Calling the token program to transfer the protocol fee...");
+                Self::transfer_tokens(
+                    token_program_b,
+                    takers_sending_token_account,
+                    mint_b_account,
+                    fee_vault_b,
+                    taker,
+                    fee_b,
+                    &[],
+                )?;
+            }
+        }
 
-        let transfer_to_taker_ix = spl_token::instruction::transfer(
+        if Self::is_sol(&escrow_info.mint_a) {
+            // The escrow holds the SOL deposit itself and, owned by the program, can be debited directly
+            msg!("Transferring SOL from the escrow to the taker...");
+            Self::move_lamports(
+                escrow_account,
+                takers_token_to_receive_account,
+                amount_to_taker,
+            )?;
+
+            if fee_a > 0 {
+                if fee_vault_a.key != config_account.key {
+                    return Err(ProgramError::InvalidAccountData);
+                }
+                msg!("Transferring the protocol fee from the escrow...");
+                Self::move_lamports(escrow_account, config_account, fee_a)?;
+            }
+        } else {
+            let transfer_to_taker_ix = spl_token_2022::instruction::transfer_checked(
+                token_program.key,
+                pdas_temp_token_account.key,
+                mint_a_account.key,
+                takers_token_to_receive_account.key,
+                &pda,
+                &[&pda],
+                amount_to_taker,
+                Self::unpack_mint(mint_a_account)?.decimals,
+            )?;
+            msg!("Calling the token program to transfer tokens to the taker...");
+            invoke_signed(
+                &transfer_to_taker_ix,
+                &[
+                    pdas_temp_token_account.clone(),
+                    mint_a_account.clone(),
+                    takers_token_to_receive_account.clone(),
+                    pda_account.clone(),
+                    token_program.clone(),
+                ],
+                &[pda_seeds],
+            )?;
+
+            if fee_a > 0 {
+                Self::prepare_fee_vault(
+                    taker,
+                    fee_vault_a,
+                    mint_a_account,
+                    config_account,
+                    token_program,
+                    system_program,
+                    program_id,
+                )?;
+                msg!("Calling the token program to transfer the protocol fee...");
+                Self::transfer_tokens(
+                    token_program,
+                    pdas_temp_token_account,
+                    mint_a_account,
+                    fee_vault_a,
+                    pda_account,
+                    fee_a,
+                    &[pda_seeds],
+                )?;
+            }
+        }
+
+        escrow_info.remaining_amount -= amount_expected_by_taker;
+        escrow_info.remaining_expected_amount = escrow_info
+            .remaining_expected_amount
+            .checked_sub(amount_to_pay)
+            .ok_or(EscrowError::AmountOverflow)?;
+        EscrowEvent::Exchanged {
+            escrow: *escrow_account.key,
+            initializer: escrow_info.initializer_pubkey,
+            taker: *taker.key,
+            mint_a: escrow_info.mint_a,
+            mint_b: escrow_info.mint_b,
+            taken_amount: amount_expected_by_taker,
+            paid_amount: amount_to_pay,
+            remaining_amount: escrow_info.remaining_amount,
+            slot: clock.slot,
+        }
+        .emit()?;
+
+        // Partially filled escrow stays open for the next taker
+        if escrow_info.remaining_amount > 0 {
+            Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+            return Ok(());
+        }
+
+        if !Self::is_sol(&escrow_info.mint_a) {
+            Self::harvest_withheld_fees(token_program, mint_a_account, pdas_temp_token_account)?;
+            let close_pdas_temp_acc_ix = spl_token_2022::instruction::close_account(
+                token_program.key,
+                pdas_temp_token_account.key,
+                initializers_main_account.key,
+                &pda,
+                &[&pda],
+            )?;
+            msg!("Calling the token program to close pda's temp account...");
+            invoke_signed(
+                &close_pdas_temp_acc_ix,
+                &[
+                    pdas_temp_token_account.clone(),
+                    initializers_main_account.clone(),
+                    pda_account.clone(),
+                    token_program.clone(),
+                ],
+                &[pda_seeds],
+            )?;
+        }
+
+        EscrowEvent::Closed {
+            escrow: *escrow_account.key,
+            initializer: escrow_info.initializer_pubkey,
+            refunded_amount: 0,
+            slot: clock.slot,
+        }
+        .emit()?;
+        msg!("Closing the escrow account...");
+        **initializers_main_account.try_borrow_mut_lamports()? = initializers_main_account
+            .lamports()
+            .checked_add(escrow_account.lamports())
+            .ok_or(EscrowError::AmountOverflow)?;
+        **escrow_account.try_borrow_mut_lamports()? = 0;
+        *escrow_account.try_borrow_mut_data()? = &mut [];
+
+        Ok(())
+    }
+
+    /// Price of a fill at the ratio of what is left in the escrow, see `escrow_math::amount_to_pay`
+    fn amount_to_pay(escrow_info: &Escrow, amount: u64) -> Result<u64, ProgramError> {
+        escrow_math::amount_to_pay(
+            amount,
+            escrow_info.remaining_amount,
+            escrow_info.remaining_expected_amount,
+        )
+        .ok_or_else(|| EscrowError::AmountOverflow.into())
+    }
+
+    fn protocol_fee(config_info: &EscrowConfig, amount: u64) -> u64 {
+        escrow_math::protocol_fee(amount, config_info.fee_basis_points)
+    }
+
+    /// Reads the program config, making sure it is the config PDA of this program
+    fn unpack_config(
+        config_account: &AccountInfo,
+        program_id: &Pubkey,
+    ) -> Result<EscrowConfig, ProgramError> {
+        if config_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let config_info = EscrowConfig::unpack(&config_account.try_borrow_data()?)?;
+        let config =
+            Pubkey::create_program_address(&[CONFIG_SEED, &[config_info.bump_seed]], program_id)?;
+        if config != *config_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(config_info)
+    }
+
+    /// While the program is paused no escrow can be opened or taken, cancels stay open
+    fn check_not_paused(config_info: &EscrowConfig) -> ProgramResult {
+        if config_info.paused {
+            return Err(EscrowError::ProgramPaused.into());
+        }
+        Ok(())
+    }
+
+    /// Reads the program config for an admin instruction, which only the admin may sign
+    fn unpack_config_as_admin(
+        admin: &AccountInfo,
+        config_account: &AccountInfo,
+        program_id: &Pubkey,
+    ) -> Result<EscrowConfig, ProgramError> {
+        if !admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let config_info = Self::unpack_config(config_account, program_id)?;
+        if config_info.admin != *admin.key {
+            return Err(EscrowError::InvalidAdmin.into());
+        }
+        Ok(config_info)
+    }
+
+    /// Checks that the signer is the upgrade authority named by the program data of this program
+    fn check_upgrade_authority(
+        authority: &AccountInfo,
+        program_data_account: &AccountInfo,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (program_data, _) =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+        if program_data != *program_data_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if *program_data_account.owner != bpf_loader_upgradeable::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        match program_data_account.deserialize_data::<UpgradeableLoaderState>() {
+            Ok(UpgradeableLoaderState::ProgramData {
+                upgrade_authority_address: Some(upgrade_authority),
+                ..
+            }) if upgrade_authority == *authority.key => Ok(()),
+            Ok(UpgradeableLoaderState::ProgramData { .. }) => Err(EscrowError::InvalidAdmin.into()),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+
+    /// Creates a token account at a PDA of this program, sized for the extensions its mint requires
+    #[allow(clippy::too_many_arguments)]
+    fn create_token_vault<'a>(
+        payer: &AccountInfo<'a>,
+        vault: &AccountInfo<'a>,
+        vault_seeds: &[&[u8]],
+        mint: &AccountInfo<'a>,
+        owner: &Pubkey,
+        token_program: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        rent: &Rent,
+    ) -> ProgramResult {
+        // Token-2022 mints may require extensions on their token accounts, which take extra space
+        let vault_len = {
+            let mint_data = mint.try_borrow_data()?;
+            let mint = StateWithExtensions::<Mint>::unpack(&mint_data)?;
+            let extension_types =
+                ExtensionType::get_required_init_account_extensions(&mint.get_extension_types()?);
+            ExtensionType::get_account_len::<TokenAccount>(&extension_types)
+        };
+        let create_vault_ix = system_instruction::create_account(
+            payer.key,
+            vault.key,
+            rent.minimum_balance(vault_len),
+            vault_len as u64,
             token_program.key,
-            pdas_temp_token_account.key,
-            takers_token_to_receive_account.key,
-            &pda,
-            &[&pda],
-            pdas_temp_token_account_info.amount,
-        )?;
-        msg!("Calling the token program to transfer tokens to the taker...");
+        );
+        msg!("Calling the system program to create the vault...");
         invoke_signed(
-            &transfer_to_taker_ix,
-            &[
-                pdas_temp_token_account.clone(),
-                takers_token_to_receive_account.clone(),
-                pda_account.clone(),
-                token_program.clone(),
-            ],
-            &[&[&b"escrow"[..], &[nonce]]],
+            &create_vault_ix,
+            &[payer.clone(), vault.clone(), system_program.clone()],
+            &[vault_seeds],
         )?;
 
-        let close_pdas_temp_acc_ix = spl_token::instruction::close_account(
+        let init_vault_ix = spl_token_2022::instruction::initialize_account3(
             token_program.key,
-            pdas_temp_token_account.key,
-            initializers_main_account.key,
-            &pda,
-            &[&pda],
+            vault.key,
+            mint.key,
+            owner,
+        )?;
+        msg!("Calling the token program to initialize the vault...");
+        invoke(
+            &init_vault_ix,
+            &[vault.clone(), mint.clone(), token_program.clone()],
+        )
+    }
+
+    /// Fee vaults are token accounts owned by the config PDA, one per mint. The first taker paying
+    /// a fee in a mint creates its vault
+    fn prepare_fee_vault<'a>(
+        taker: &AccountInfo<'a>,
+        fee_vault: &AccountInfo<'a>,
+        mint: &AccountInfo<'a>,
+        config_account: &AccountInfo<'a>,
+        token_program: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (fee_vault_address, fee_vault_bump_seed) =
+            Pubkey::find_program_address(&[FEE_VAULT_SEED, mint.key.as_ref()], program_id);
+        if fee_vault_address != *fee_vault.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if !fee_vault.data_is_empty() {
+            return Ok(());
+        }
+        Self::create_token_vault(
+            taker,
+            fee_vault,
+            &[FEE_VAULT_SEED, mint.key.as_ref(), &[fee_vault_bump_seed]],
+            mint,
+            config_account.key,
+            token_program,
+            system_program,
+            &Rent::get()?,
+        )
+    }
+
+    /// Checked transfer of either token program, signed by a PDA when seeds are given
+    fn transfer_tokens<'a>(
+        token_program: &AccountInfo<'a>,
+        source: &AccountInfo<'a>,
+        mint: &AccountInfo<'a>,
+        destination: &AccountInfo<'a>,
+        authority: &AccountInfo<'a>,
+        amount: u64,
+        signer_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let transfer_ix = spl_token_2022::instruction::transfer_checked(
+            token_program.key,
+            source.key,
+            mint.key,
+            destination.key,
+            authority.key,
+            &[authority.key],
+            amount,
+            Self::unpack_mint(mint)?.decimals,
         )?;
-        msg!("Calling the token program to close pda's temp account...");
         invoke_signed(
-            &close_pdas_temp_acc_ix,
+            &transfer_ix,
             &[
-                pdas_temp_token_account.clone(),
-                initializers_main_account.clone(),
-                pda_account.clone(),
+                source.clone(),
+                mint.clone(),
+                destination.clone(),
+                authority.clone(),
                 token_program.clone(),
             ],
-            &[&[&b"escrow"[..], &[nonce]]],
-        )?;
+            signer_seeds,
+        )
+    }
+
+    /// Moves lamports out of an account owned by this program, which needs no system program call
+    fn move_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> ProgramResult {
+        **from.try_borrow_mut_lamports()? = from
+            .lamports()
+            .checked_sub(amount)
+            .ok_or(EscrowError::AmountOverflow)?;
+        **to.try_borrow_mut_lamports()? = to
+            .lamports()
+            .checked_add(amount)
+            .ok_or(EscrowError::AmountOverflow)?;
+        Ok(())
+    }
+
+    /// SOL legs are marked with the wrapped SOL mint, but are paid in lamports without wrapping
+    fn is_sol(mint: &Pubkey) -> bool {
+        *mint == spl_token::native_mint::id()
+    }
+
+    /// Both the original token program and Token-2022 are accepted, as long as it owns the mint
+    fn check_token_program(token_program: &AccountInfo, mint: &AccountInfo) -> ProgramResult {
+        spl_token_2022::check_spl_token_program_account(token_program.key)?;
+        if mint.owner != token_program.key {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        Ok(())
+    }
 
+    /// Reads the base state of a token account of either token program, skipping its extensions
+    fn unpack_token_account(account: &AccountInfo) -> Result<TokenAccount, ProgramError> {
+        spl_token_2022::check_spl_token_program_account(account.owner)?;
+        let data = account.try_borrow_data()?;
+        Ok(StateWithExtensions::<TokenAccount>::unpack(&data)?.base)
+    }
+
+    fn unpack_mint(mint: &AccountInfo) -> Result<Mint, ProgramError> {
+        spl_token_2022::check_spl_token_program_account(mint.owner)?;
+        let data = mint.try_borrow_data()?;
+        Ok(StateWithExtensions::<Mint>::unpack(&data)?.base)
+    }
+
+    /// Fee a transfer of the mint has to include on top of `amount` for `amount` to arrive,
+    /// zero for mints without a transfer fee
+    fn inverse_transfer_fee(mint: &AccountInfo, amount: u64) -> Result<u64, ProgramError> {
+        let data = mint.try_borrow_data()?;
+        let mint = StateWithExtensions::<Mint>::unpack(&data)?;
+        match mint.get_extension::<TransferFeeConfig>() {
+            Ok(transfer_fee_config) => transfer_fee_config
+                .calculate_inverse_epoch_fee(Clock::get()?.epoch, amount)
+                .ok_or_else(|| EscrowError::AmountOverflow.into()),
+            Err(_) => Ok(0),
+        }
+    }
+
+    /// A token account holding withheld transfer fees can't be closed, so they are moved to the
+    /// mint first. Anyone may harvest them, no signature is needed
+    fn harvest_withheld_fees<'a>(
+        token_program: &AccountInfo<'a>,
+        mint: &AccountInfo<'a>,
+        vault: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        let withheld_amount = {
+            let data = vault.try_borrow_data()?;
+            let vault_state = StateWithExtensions::<TokenAccount>::unpack(&data)?;
+            match vault_state.get_extension::<TransferFeeAmount>() {
+                Ok(transfer_fee_amount) => u64::from(transfer_fee_amount.withheld_amount),
+                Err(_) => 0,
+            }
+        };
+        if withheld_amount == 0 {
+            return Ok(());
+        }
+
+        let harvest_ix =
+            spl_token_2022::extension::transfer_fee::instruction::harvest_withheld_tokens_to_mint(
+                token_program.key,
+                mint.key,
+                &[vault.key],
+            )?;
+        msg!("Calling the token program to harvest the vault's withheld fees...");
+        invoke(
+            &harvest_ix,
+            &[mint.clone(), vault.clone(), token_program.clone()],
+        )
+    }
+
+    fn process_cancel(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        let initializers_refund_token_account = next_account_info(account_info_iter)?;
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
+
+        // No time checks here: the initializer may take the deposit back at any point before
+        // an exchange, and once the trade has timed out this is the only way to release it
+        if escrow_info.initializer_pubkey != *initializer.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let token_program = next_account_info(account_info_iter)?;
+        let pda_account = next_account_info(account_info_iter)?;
+
+        let mint_a_account = next_account_info(account_info_iter)?;
+        if escrow_info.mint_a != *mint_a_account.key {
+            return Err(EscrowError::MintMismatch.into());
+        }
+        Self::check_token_program(token_program, mint_a_account)?;
+
+        let pda_seeds: &[&[u8]] = &[
+            ESCROW_PDA_SEED,
+            escrow_account.key.as_ref(),
+            escrow_info.initializer_pubkey.as_ref(),
+            &[escrow_info.bump_seed],
+        ];
+        let pda = Pubkey::create_program_address(pda_seeds, program_id)?;
+        if pda != *pda_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        // A SOL deposit is held by the escrow account itself and goes back when it is closed
+        let refunded_amount = if Self::is_sol(&escrow_info.mint_a) {
+            escrow_info.remaining_amount
+        } else {
+            let pdas_temp_token_account_info = Self::unpack_token_account(pdas_temp_token_account)?;
+            let initializers_refund_token_account_info =
+                Self::unpack_token_account(initializers_refund_token_account)?;
+            if initializers_refund_token_account_info.owner != *initializer.key {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            let refund_to_initializer_ix = spl_token_2022::instruction::transfer_checked(
+                token_program.key,
+                pdas_temp_token_account.key,
+                mint_a_account.key,
+                initializers_refund_token_account.key,
+                &pda,
+                &[&pda],
+                pdas_temp_token_account_info.amount,
+                Self::unpack_mint(mint_a_account)?.decimals,
+            )?;
+            msg!("Calling the token program to refund tokens to the escrow's initializer...");
+            invoke_signed(
+                &refund_to_initializer_ix,
+                &[
+                    pdas_temp_token_account.clone(),
+                    mint_a_account.clone(),
+                    initializers_refund_token_account.clone(),
+                    pda_account.clone(),
+                    token_program.clone(),
+                ],
+                &[pda_seeds],
+            )?;
+
+            Self::harvest_withheld_fees(token_program, mint_a_account, pdas_temp_token_account)?;
+            let close_pdas_temp_acc_ix = spl_token_2022::instruction::close_account(
+                token_program.key,
+                pdas_temp_token_account.key,
+                initializer.key,
+                &pda,
+                &[&pda],
+            )?;
+            msg!("Calling the token program to close pda's temp account...");
+            invoke_signed(
+                &close_pdas_temp_acc_ix,
+                &[
+                    pdas_temp_token_account.clone(),
+                    initializer.clone(),
+                    pda_account.clone(),
+                    token_program.clone(),
+                ],
+                &[pda_seeds],
+            )?;
+            pdas_temp_token_account_info.amount
+        };
+
+        EscrowEvent::Closed {
+            escrow: *escrow_account.key,
+            initializer: escrow_info.initializer_pubkey,
+            refunded_amount,
+            slot: Clock::get()?.slot,
+        }
+        .emit()?;
         msg!("Closing the escrow account...");
-        **initializers_main_account.try_borrow_mut_lamports()? = initializers_main_account
+        **initializer.try_borrow_mut_lamports()? = initializer
             .lamports()
             .checked_add(escrow_account.lamports())
             .ok_or(EscrowError::AmountOverflow)?;
@@ -231,4 +1058,350 @@ impl Processor {
 
         Ok(())
     }
+
+    #[allow(clippy::too_many_arguments)]
+    fn process_update_escrow(
+        accounts: &[AccountInfo],
+        expected_amount: Option<u64>,
+        time_out: Option<u64>,
+        allowed_taker: Option<Option<Pubkey>>,
+        top_up_amount: u64,
+        withdraw_amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        if escrow_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut escrow_info = Escrow::unpack(&escrow_account.try_borrow_data()?)?;
+        if escrow_info.initializer_pubkey != *initializer.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // A timed out escrow can only be cancelled, not revived with new terms
+        let slot = Clock::get()?.slot;
+        if slot > escrow_info.time_out {
+            return Err(EscrowError::TradeTimeOut.into());
+        }
+
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let initializers_token_account = next_account_info(account_info_iter)?;
+
+        let mint_a_account = next_account_info(account_info_iter)?;
+        if escrow_info.mint_a != *mint_a_account.key {
+            return Err(EscrowError::MintMismatch.into());
+        }
+        let token_program = next_account_info(account_info_iter)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        let config_account = next_account_info(account_info_iter)?;
+        Self::check_not_paused(&Self::unpack_config(config_account, program_id)?)?;
+
+        let pda_seeds: &[&[u8]] = &[
+            ESCROW_PDA_SEED,
+            escrow_account.key.as_ref(),
+            escrow_info.initializer_pubkey.as_ref(),
+            &[escrow_info.bump_seed],
+        ];
+        let pda = Pubkey::create_program_address(pda_seeds, program_id)?;
+        if pda != *pda_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        let sol_deposit = Self::is_sol(&escrow_info.mint_a);
+        if !sol_deposit && (top_up_amount > 0 || withdraw_amount > 0) {
+            Self::check_token_program(token_program, mint_a_account)?;
+            if Self::unpack_token_account(initializers_token_account)?.owner != *initializer.key {
+                return Err(ProgramError::InvalidAccountData);
+            }
+        }
+
+        if top_up_amount > 0 {
+            let topped_up_amount = if sol_deposit {
+                let top_up_ix = system_instruction::transfer(
+                    initializer.key,
+                    escrow_account.key,
+                    top_up_amount,
+                );
+                msg!("Calling the system program to top up the deposit...");
+                invoke(
+                    &top_up_ix,
+                    &[
+                        initializer.clone(),
+                        escrow_account.clone(),
+                        system_program.clone(),
+                    ],
+                )?;
+                top_up_amount
+            } else {
+                // Net of any transfer fee, like the deposit
+                let vault_amount = Self::unpack_token_account(pdas_temp_token_account)?.amount;
+                msg!("Calling the token program to top up the deposit...");
+                Self::transfer_tokens(
+                    token_program,
+                    initializers_token_account,
+                    mint_a_account,
+                    pdas_temp_token_account,
+                    initializer,
+                    top_up_amount,
+                    &[],
+                )?;
+                Self::unpack_token_account(pdas_temp_token_account)?.amount - vault_amount
+            };
+            escrow_info.remaining_amount = escrow_info
+                .remaining_amount
+                .checked_add(topped_up_amount)
+                .ok_or(EscrowError::AmountOverflow)?;
+        }
+
+        if withdraw_amount > 0 {
+            // Something has to be left to trade, cancel takes the whole deposit back
+            if withdraw_amount >= escrow_info.remaining_amount {
+                return Err(EscrowError::InvalidUpdate.into());
+            }
+            if sol_deposit {
+                msg!("Transferring SOL from the escrow to the initializer...");
+                Self::move_lamports(escrow_account, initializer, withdraw_amount)?;
+            } else {
+                msg!("Calling the token program to withdraw from the deposit...");
+                Self::transfer_tokens(
+                    token_program,
+                    pdas_temp_token_account,
+                    mint_a_account,
+                    initializers_token_account,
+                    pda_account,
+                    withdraw_amount,
+                    &[pda_seeds],
+                )?;
+            }
+            escrow_info.remaining_amount -= withdraw_amount;
+        }
+
+        if let Some(expected_amount) = expected_amount {
+            if expected_amount == 0 {
+                return Err(EscrowError::InvalidUpdate.into());
+            }
+            escrow_info.remaining_expected_amount = expected_amount;
+        }
+
+        if let Some(time_out) = time_out {
+            // Like at init, the trade must unlock before it times out and must not be expired already
+            if escrow_info.unlock_time > time_out || time_out <= slot {
+                return Err(EscrowError::InvalidTimeWindow.into());
+            }
+            escrow_info.time_out = time_out;
+        }
+
+        if let Some(allowed_taker) = allowed_taker {
+            escrow_info.allowed_taker = allowed_taker;
+        }
+
+        // What is left is offered under the new terms, as if the escrow was opened with them
+        escrow_info.deposit_amount = escrow_info.remaining_amount;
+        escrow_info.expected_amount = escrow_info.remaining_expected_amount;
+        EscrowEvent::Updated {
+            escrow: *escrow_account.key,
+            initializer: escrow_info.initializer_pubkey,
+            mint_a: escrow_info.mint_a,
+            mint_b: escrow_info.mint_b,
+            deposit_amount: escrow_info.deposit_amount,
+            expected_amount: escrow_info.expected_amount,
+            time_out: escrow_info.time_out,
+            allowed_taker: escrow_info.allowed_taker,
+            slot,
+        }
+        .emit()?;
+        Escrow::pack(escrow_info, &mut escrow_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_init_config(
+        accounts: &[AccountInfo],
+        fee_basis_points: u16,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin = next_account_info(account_info_iter)?;
+
+        if !admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account_info(account_info_iter)?;
+        let fee_recipient = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+        let program_data_account = next_account_info(account_info_iter)?;
+
+        // Anyone could create the config between the deployment and the admin, so only the
+        // upgrade authority of the program may
+        Self::check_upgrade_authority(admin, program_data_account, program_id)?;
+
+        if fee_basis_points > EscrowConfig::MAX_FEE_BASIS_POINTS {
+            return Err(EscrowError::FeeTooHigh.into());
+        }
+
+        // There is a single config per program, creating it again fails as the account is in use
+        let (config, bump_seed) = Pubkey::find_program_address(&[CONFIG_SEED], program_id);
+        if config != *config_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        let create_config_ix = system_instruction::create_account(
+            admin.key,
+            config_account.key,
+            Rent::get()?.minimum_balance(EscrowConfig::LEN),
+            EscrowConfig::LEN as u64,
+            program_id,
+        );
+        msg!("Calling the system program to create the config...");
+        invoke_signed(
+            &create_config_ix,
+            &[
+                admin.clone(),
+                config_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[CONFIG_SEED, &[bump_seed]]],
+        )?;
+
+        let config_info = EscrowConfig {
+            is_initialized: true,
+            version: EscrowConfig::VERSION,
+            admin: *admin.key,
+            fee_recipient: *fee_recipient.key,
+            fee_basis_points,
+            paused: false,
+            bump_seed,
+        };
+        EscrowConfig::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_fee(
+        accounts: &[AccountInfo],
+        fee_basis_points: u16,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let mut config_info = Self::unpack_config_as_admin(admin, config_account, program_id)?;
+
+        if fee_basis_points > EscrowConfig::MAX_FEE_BASIS_POINTS {
+            return Err(EscrowError::FeeTooHigh.into());
+        }
+        config_info.fee_basis_points = fee_basis_points;
+        EscrowConfig::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_fee_recipient(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let mut config_info = Self::unpack_config_as_admin(admin, config_account, program_id)?;
+
+        let fee_recipient = next_account_info(account_info_iter)?;
+        config_info.fee_recipient = *fee_recipient.key;
+        EscrowConfig::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_withdraw_fees(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let config_info = Self::unpack_config_as_admin(admin, config_account, program_id)?;
+
+        let fee_vault = next_account_info(account_info_iter)?;
+        let destination = next_account_info(account_info_iter)?;
+        let mint_account = next_account_info(account_info_iter)?;
+
+        if Self::is_sol(mint_account.key) {
+            if fee_vault.key != config_account.key || *destination.key != config_info.fee_recipient
+            {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            // The config account has to stay rent exempt, only what is above that are fees
+            let rent_exempt_balance = Rent::get()?.minimum_balance(EscrowConfig::LEN);
+            if amount
+                > config_account
+                    .lamports()
+                    .saturating_sub(rent_exempt_balance)
+            {
+                return Err(ProgramError::InsufficientFunds);
+            }
+            msg!("Transferring SOL fees to the fee recipient...");
+            return Self::move_lamports(config_account, destination, amount);
+        }
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program, mint_account)?;
+        if Self::unpack_token_account(destination)?.owner != config_info.fee_recipient {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // Fee vaults are owned by the config PDA, so the token program only lets it sign
+        msg!("Calling the token program to transfer fees to the fee recipient...");
+        Self::transfer_tokens(
+            token_program,
+            fee_vault,
+            mint_account,
+            destination,
+            config_account,
+            amount,
+            &[&[CONFIG_SEED, &[config_info.bump_seed]]],
+        )
+    }
+
+    fn process_set_admin(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let mut config_info = Self::unpack_config_as_admin(admin, config_account, program_id)?;
+
+        let new_admin = next_account_info(account_info_iter)?;
+        if !new_admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        config_info.admin = *new_admin.key;
+        EscrowConfig::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_paused(
+        accounts: &[AccountInfo],
+        paused: bool,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let mut config_info = Self::unpack_config_as_admin(admin, config_account, program_id)?;
+
+        config_info.paused = paused;
+        EscrowConfig::pack(config_info, &mut config_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
 }
@@ -6,14 +6,28 @@ use solana_program::{
 
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Escrow {
     pub is_initialized: bool,
+    pub version: u8,
     pub initializer_pubkey: Pubkey,
     pub temp_token_account_pubkey: Pubkey,
     pub initializer_token_to_receive_account_pubkey: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub deposit_amount: u64,
     pub expected_amount: u64,
     pub unlock_time: u64,
     pub time_out: u64,
+    pub bump_seed: u8,
+    pub remaining_amount: u64,
+    pub remaining_expected_amount: u64,
+    pub allowed_taker: Option<Pubkey>,
+}
+
+impl Escrow {
+    /// Version of the account layout, bumped whenever fields are added or moved
+    pub const VERSION: u8 = 2;
 }
 
 impl Sealed for Escrow {}
@@ -25,34 +39,55 @@ impl IsInitialized for Escrow {
 }
 
 impl Pack for Escrow {
-    const LEN: usize = 1 + 32 + 32 + 32 + 8 + 8 + 8;
+    const LEN: usize = 1 + 1 + 32 + 32 + 32 + 32 + 32 + 8 + 8 + 8 + 8 + 1 + 8 + 8 + 33;
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
         let src = array_ref![src, 0, Escrow::LEN];
         let (
             is_initialized,
+            version,
             initializer_pubkey,
             temp_token_account_pubkey,
             initializer_token_to_receive_account_pubkey,
+            mint_a,
+            mint_b,
+            deposit_amount,
             expected_amount,
             unlock_time,
             time_out,
-        ) = array_refs![src, 1, 32, 32, 32, 8, 8, 8];
+            bump_seed,
+            remaining_amount,
+            remaining_expected_amount,
+            allowed_taker,
+        ) = array_refs![src, 1, 1, 32, 32, 32, 32, 32, 8, 8, 8, 8, 1, 8, 8, 33];
         let is_initialized = match is_initialized {
             [0] => false,
             [1] => true,
             _ => return Err(ProgramError::InvalidAccountData),
         };
+        let version = version[0];
+        // Accounts of another layout version can't be read as this one
+        if is_initialized && version != Escrow::VERSION {
+            return Err(ProgramError::InvalidAccountData);
+        }
 
         Ok(Escrow {
             is_initialized,
+            version,
             initializer_pubkey: Pubkey::new_from_array(*initializer_pubkey),
             temp_token_account_pubkey: Pubkey::new_from_array(*temp_token_account_pubkey),
             initializer_token_to_receive_account_pubkey: Pubkey::new_from_array(
                 *initializer_token_to_receive_account_pubkey,
             ),
+            mint_a: Pubkey::new_from_array(*mint_a),
+            mint_b: Pubkey::new_from_array(*mint_b),
+            deposit_amount: u64::from_le_bytes(*deposit_amount),
             expected_amount: u64::from_le_bytes(*expected_amount),
             unlock_time: u64::from_le_bytes(*unlock_time),
             time_out: u64::from_le_bytes(*time_out),
+            bump_seed: bump_seed[0],
+            remaining_amount: u64::from_le_bytes(*remaining_amount),
+            remaining_expected_amount: u64::from_le_bytes(*remaining_expected_amount),
+            allowed_taker: unpack_pubkey_option(allowed_taker)?,
         })
     }
 
@@ -60,31 +95,170 @@ impl Pack for Escrow {
         let dst = array_mut_ref![dst, 0, Escrow::LEN];
         let (
             is_initialized_dst,
+            version_dst,
             initializer_pubkey_dst,
             temp_token_account_pubkey_dst,
             initializer_token_to_receive_account_pubkey_dst,
+            mint_a_dst,
+            mint_b_dst,
+            deposit_amount_dst,
             expected_amount_dst,
             unlock_time_dst,
             time_out_dst,
-        ) = mut_array_refs![dst, 1, 32, 32, 32, 8, 8, 8];
+            bump_seed_dst,
+            remaining_amount_dst,
+            remaining_expected_amount_dst,
+            allowed_taker_dst,
+        ) = mut_array_refs![dst, 1, 1, 32, 32, 32, 32, 32, 8, 8, 8, 8, 1, 8, 8, 33];
 
         let Escrow {
             is_initialized,
+            version,
             initializer_pubkey,
             temp_token_account_pubkey,
             initializer_token_to_receive_account_pubkey,
+            mint_a,
+            mint_b,
+            deposit_amount,
             expected_amount,
             unlock_time,
             time_out,
+            bump_seed,
+            remaining_amount,
+            remaining_expected_amount,
+            allowed_taker,
         } = self;
 
         is_initialized_dst[0] = *is_initialized as u8;
+        version_dst[0] = *version;
         initializer_pubkey_dst.copy_from_slice(initializer_pubkey.as_ref());
         temp_token_account_pubkey_dst.copy_from_slice(temp_token_account_pubkey.as_ref());
         initializer_token_to_receive_account_pubkey_dst
             .copy_from_slice(initializer_token_to_receive_account_pubkey.as_ref());
+        mint_a_dst.copy_from_slice(mint_a.as_ref());
+        mint_b_dst.copy_from_slice(mint_b.as_ref());
+        *deposit_amount_dst = deposit_amount.to_le_bytes();
         *expected_amount_dst = expected_amount.to_le_bytes();
         *unlock_time_dst = unlock_time.to_le_bytes();
         *time_out_dst = time_out.to_le_bytes();
+        bump_seed_dst[0] = *bump_seed;
+        *remaining_amount_dst = remaining_amount.to_le_bytes();
+        *remaining_expected_amount_dst = remaining_expected_amount.to_le_bytes();
+        pack_pubkey_option(allowed_taker, allowed_taker_dst);
+    }
+}
+
+// An optional pubkey takes a tag byte and the key, zeroed when there is none
+fn unpack_pubkey_option(src: &[u8; 33]) -> Result<Option<Pubkey>, ProgramError> {
+    let (tag, key) = array_refs![src, 1, 32];
+    match tag {
+        [0] => Ok(None),
+        [1] => Ok(Some(Pubkey::new_from_array(*key))),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+fn pack_pubkey_option(src: &Option<Pubkey>, dst: &mut [u8; 33]) {
+    let (tag, key) = mut_array_refs![dst, 1, 32];
+    match src {
+        Some(pubkey) => {
+            tag[0] = 1;
+            key.copy_from_slice(pubkey.as_ref());
+        }
+        None => {
+            tag[0] = 0;
+            *key = [0; 32];
+        }
+    }
+}
+
+/// Program-wide settings, a single PDA derived from `[b"config"]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EscrowConfig {
+    pub is_initialized: bool,
+    pub version: u8,
+    pub admin: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub fee_basis_points: u16,
+    pub paused: bool,
+    pub bump_seed: u8,
+}
+
+impl EscrowConfig {
+    /// Version of the account layout, bumped whenever fields are added or moved
+    pub const VERSION: u8 = 2;
+    /// The protocol fee can never be set above 10% of a leg
+    pub const MAX_FEE_BASIS_POINTS: u16 = 1_000;
+}
+
+impl Sealed for EscrowConfig {}
+
+impl IsInitialized for EscrowConfig {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for EscrowConfig {
+    const LEN: usize = 1 + 1 + 32 + 32 + 2 + 1 + 1;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, EscrowConfig::LEN];
+        let (is_initialized, version, admin, fee_recipient, fee_basis_points, paused, bump_seed) =
+            array_refs![src, 1, 1, 32, 32, 2, 1, 1];
+        let is_initialized = match is_initialized {
+            [0] => false,
+            [1] => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let paused = match paused {
+            [0] => false,
+            [1] => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let version = version[0];
+        if is_initialized && version != EscrowConfig::VERSION {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(EscrowConfig {
+            is_initialized,
+            version,
+            admin: Pubkey::new_from_array(*admin),
+            fee_recipient: Pubkey::new_from_array(*fee_recipient),
+            fee_basis_points: u16::from_le_bytes(*fee_basis_points),
+            paused,
+            bump_seed: bump_seed[0],
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, EscrowConfig::LEN];
+        let (
+            is_initialized_dst,
+            version_dst,
+            admin_dst,
+            fee_recipient_dst,
+            fee_basis_points_dst,
+            paused_dst,
+            bump_seed_dst,
+        ) = mut_array_refs![dst, 1, 1, 32, 32, 2, 1, 1];
+
+        let EscrowConfig {
+            is_initialized,
+            version,
+            admin,
+            fee_recipient,
+            fee_basis_points,
+            paused,
+            bump_seed,
+        } = self;
+
+        is_initialized_dst[0] = *is_initialized as u8;
+        version_dst[0] = *version;
+        admin_dst.copy_from_slice(admin.as_ref());
+        fee_recipient_dst.copy_from_slice(fee_recipient.as_ref());
+        *fee_basis_points_dst = fee_basis_points.to_le_bytes();
+        paused_dst[0] = *paused as u8;
+        bump_seed_dst[0] = *bump_seed;
     }
 }
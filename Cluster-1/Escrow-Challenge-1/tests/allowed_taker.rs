@@ -0,0 +1,32 @@
+mod common;
+
+use bpf_program_template::error::EscrowError;
+use common::{assert_error, token_balance, Trade};
+use solana_sdk::signature::{Keypair, Signer};
+
+#[tokio::test]
+async fn allowed_taker_can_take_the_trade() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 50, 30).await;
+    trade.allowed_taker = Some(trade.bob.pubkey());
+    trade.open(&mut context).await.unwrap();
+
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 50);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 30);
+}
+
+#[tokio::test]
+async fn other_takers_are_rejected() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 50, 30).await;
+    trade.allowed_taker = Some(Keypair::new().pubkey());
+    trade.open(&mut context).await.unwrap();
+
+    assert_error(
+        trade.exchange(&mut context).await,
+        EscrowError::TakerNotAllowed,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use bpf_program_template::error::EscrowError;
+use common::{
+    account_exists, assert_error, assert_instruction_error, create_token_account, current_slot,
+    process, set_owner, token_balance, warp_to_slot, Trade,
+};
+use solana_sdk::{
+    instruction::{AccountMeta, InstructionError},
+    pubkey::Pubkey,
+    signature::Signer,
+};
+use spl_token_2022::error::TokenError;
+
+#[tokio::test]
+async fn cancel_refunds_the_whole_deposit() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    trade.cancel(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 50);
+    assert!(!account_exists(&mut context, &trade.vault).await);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn cancel_refunds_what_is_left_after_a_fill() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+    trade.fill(&mut context, 20).await.unwrap();
+
+    trade.cancel(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 30);
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 20);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn cancel_is_the_only_way_out_after_the_time_out() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let slot = current_slot(&mut context).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+    warp_to_slot(&mut context, slot + 1101).await;
+
+    assert_error(
+        trade.exchange(&mut context).await,
+        EscrowError::TradeTimeOut,
+    );
+    trade.cancel(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 50);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn only_the_initializer_can_cancel() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    // Bob signs in Alice's place and asks for the refund
+    let mut ix = trade.cancel_instruction();
+    ix.accounts[0] = AccountMeta::new(trade.bob.pubkey(), true);
+    ix.accounts[2] = AccountMeta::new(trade.bob_x, false);
+    assert_instruction_error(
+        process(&mut context, &[ix], &[&trade.bob]).await,
+        InstructionError::InvalidAccountData,
+    );
+
+    // Alice's refund account, without her signature
+    let mut ix = trade.cancel_instruction();
+    ix.accounts[0].is_signer = false;
+    assert_instruction_error(
+        process(&mut context, &[ix], &[]).await,
+        InstructionError::MissingRequiredSignature,
+    );
+
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+}
+
+#[tokio::test]
+async fn cancel_rejects_a_refund_account_of_someone_else() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    let mut ix = trade.cancel_instruction();
+    ix.accounts[2] = AccountMeta::new(trade.bob_x, false);
+    assert_instruction_error(
+        process(&mut context, &[ix], &[&trade.alice]).await,
+        InstructionError::InvalidAccountData,
+    );
+}
+
+#[tokio::test]
+async fn cancel_rejects_a_refund_account_of_another_mint() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    // The token program refuses to move the deposit into an account of another mint
+    let alice_other_y =
+        create_token_account(&mut context, &trade.mint_y, &trade.alice.pubkey()).await;
+    let mut ix = trade.cancel_instruction();
+    ix.accounts[2] = AccountMeta::new(alice_other_y, false);
+    assert_instruction_error(
+        process(&mut context, &[ix], &[&trade.alice]).await,
+        InstructionError::Custom(TokenError::MintMismatch as u32),
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+}
+
+#[tokio::test]
+async fn cancel_rejects_an_escrow_owned_by_another_program() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    set_owner(&mut context, &trade.escrow, &Pubkey::new_unique()).await;
+    assert_instruction_error(
+        trade.cancel(&mut context).await,
+        InstructionError::IncorrectProgramId,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+}
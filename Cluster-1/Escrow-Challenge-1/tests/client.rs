@@ -0,0 +1,72 @@
+mod common;
+
+use bpf_program_template::client::{decode_escrow, open_escrow_filters, ClientError};
+use common::{config_address, Trade, PROGRAM_ID};
+use solana_client::rpc_filter::RpcFilterType;
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    pubkey::Pubkey,
+    signature::Signer,
+};
+
+async fn account(context: &mut ProgramTestContext, address: &Pubkey) -> Account {
+    context
+        .banks_client
+        .get_account(*address)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+fn matches(filters: &[RpcFilterType], account: &Account) -> bool {
+    let account = AccountSharedData::from(account.clone());
+    filters.iter().all(|filter| filter.allows(&account))
+}
+
+#[tokio::test]
+async fn open_escrow_filters_match_escrows_only() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+
+    let escrow = account(&mut context, &trade.escrow).await;
+    assert!(matches(&open_escrow_filters(None), &escrow));
+    assert!(matches(
+        &open_escrow_filters(Some(&trade.alice.pubkey())),
+        &escrow
+    ));
+    assert!(!matches(
+        &open_escrow_filters(Some(&trade.bob.pubkey())),
+        &escrow
+    ));
+
+    // Other accounts of the program and the vault it owns through the PDA
+    for other in [config_address(), trade.vault] {
+        let other = account(&mut context, &other).await;
+        assert!(!matches(&open_escrow_filters(None), &other));
+    }
+}
+
+#[tokio::test]
+async fn escrows_are_decoded_from_their_accounts() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+
+    let escrow = account(&mut context, &trade.escrow).await;
+    let state = decode_escrow(&PROGRAM_ID, &trade.escrow, &escrow).unwrap();
+    assert_eq!(state, trade.state(&mut context).await);
+    assert_eq!(state.initializer_pubkey, trade.alice.pubkey());
+
+    // The same data owned by another program isn't one of its escrows
+    assert!(matches!(
+        decode_escrow(&Pubkey::new_unique(), &trade.escrow, &escrow),
+        Err(ClientError::NotAnEscrow(address)) if address == trade.escrow
+    ));
+    let config = account(&mut context, &config_address()).await;
+    assert!(matches!(
+        decode_escrow(&PROGRAM_ID, &config_address(), &config),
+        Err(ClientError::NotAnEscrow(_))
+    ));
+}
@@ -0,0 +1,696 @@
+#![allow(dead_code)]
+
+use borsh::BorshDeserialize;
+use bpf_program_template::{
+    error::EscrowError, event::EscrowEvent, instruction, processor::Processor, state::Escrow,
+};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    account_info::AccountInfo,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
+    program_stubs::{self, SyscallStubs},
+    pubkey,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+    transaction::{Transaction, TransactionError},
+};
+use spl_token::native_mint;
+use spl_token_2022::extension::{
+    transfer_fee::instruction as transfer_fee_instruction, BaseStateWithExtensions, ExtensionType,
+    StateWithExtensions,
+};
+use std::sync::{Mutex, Once};
+
+// Any address works, the program runs in-process under it
+pub const PROGRAM_ID: Pubkey = pubkey!("DnPESwBtnTBwDifUXFVC4R2geUH8Wszjee8RFHG6tJSs");
+
+// Every event emitted by the program, in order, across all the tests of the binary
+static EVENTS: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+// The program runs natively in the tests, where the data written with `sol_log_data` doesn't reach
+// the transaction logs. The syscall stubs of program-test are wrapped to keep it aside
+struct EventCapture {
+    inner: Box<dyn SyscallStubs>,
+}
+
+impl SyscallStubs for EventCapture {
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        EVENTS.lock().unwrap().push(fields.concat());
+    }
+
+    fn sol_log(&self, message: &str) {
+        self.inner.sol_log(message)
+    }
+    fn sol_log_compute_units(&self) {
+        self.inner.sol_log_compute_units()
+    }
+    fn sol_remaining_compute_units(&self) -> u64 {
+        self.inner.sol_remaining_compute_units()
+    }
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        self.inner
+            .sol_invoke_signed(instruction, account_infos, signers_seeds)
+    }
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_clock_sysvar(var_addr)
+    }
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_epoch_schedule_sysvar(var_addr)
+    }
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_fees_sysvar(var_addr)
+    }
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_rent_sysvar(var_addr)
+    }
+    fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_epoch_rewards_sysvar(var_addr)
+    }
+    fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_last_restart_slot(var_addr)
+    }
+    unsafe fn sol_memcpy(&self, dst: *mut u8, src: *const u8, n: usize) {
+        self.inner.sol_memcpy(dst, src, n)
+    }
+    unsafe fn sol_memmove(&self, dst: *mut u8, src: *const u8, n: usize) {
+        self.inner.sol_memmove(dst, src, n)
+    }
+    unsafe fn sol_memcmp(&self, s1: *const u8, s2: *const u8, n: usize, result: *mut i32) {
+        self.inner.sol_memcmp(s1, s2, n, result)
+    }
+    unsafe fn sol_memset(&self, s: *mut u8, c: u8, n: usize) {
+        self.inner.sol_memset(s, c, n)
+    }
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        self.inner.sol_get_return_data()
+    }
+    fn sol_set_return_data(&self, data: &[u8]) {
+        self.inner.sol_set_return_data(data)
+    }
+    fn sol_get_processed_sibling_instruction(&self, index: usize) -> Option<Instruction> {
+        self.inner.sol_get_processed_sibling_instruction(index)
+    }
+    fn sol_get_stack_height(&self) -> u64 {
+        self.inner.sol_get_stack_height()
+    }
+}
+
+// Program-test installs its stubs when the first test starts. Every test waits here before
+// running a transaction, so none runs while the stubs are swapped
+fn capture_events() {
+    struct Swapping;
+    impl SyscallStubs for Swapping {}
+
+    static CAPTURE: Once = Once::new();
+    CAPTURE.call_once(|| {
+        let inner = program_stubs::set_syscall_stubs(Box::new(Swapping));
+        program_stubs::set_syscall_stubs(Box::new(EventCapture { inner }));
+    });
+}
+
+/// The events emitted so far by any test, in order
+pub fn events() -> Vec<EscrowEvent> {
+    EVENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|data| EscrowEvent::try_from_slice(data).unwrap())
+        .collect()
+}
+
+/// Starts the program with its config created, the payer being the admin and fee recipient
+/// and no protocol fee
+pub async fn start() -> ProgramTestContext {
+    let mut context = start_without_config().await;
+    let admin = context.payer.insecure_clone();
+    init_config(&mut context, &admin).await.unwrap();
+    context
+}
+
+/// Starts the program as deployed by the payer, its upgrade authority, before any config
+pub async fn start_without_config() -> ProgramTestContext {
+    let mut context = ProgramTest::new(
+        "bpf_program_template",
+        PROGRAM_ID,
+        processor!(Processor::process),
+    )
+    .start_with_context()
+    .await;
+    capture_events();
+    // The program runs natively, the program data the loader would keep for it is written here.
+    // Its lamports come from the payer, lamports out of nowhere fail the bank checks on warps
+    let lamports = 1_000_000_000;
+    let ix =
+        system_instruction::transfer(&context.payer.pubkey(), &program_data_address(), lamports);
+    process(&mut context, &[ix], &[]).await.unwrap();
+    let program_data = UpgradeableLoaderState::ProgramData {
+        slot: 0,
+        upgrade_authority_address: Some(context.payer.pubkey()),
+    };
+    context.set_account(
+        &program_data_address(),
+        &Account::new_data(lamports, &program_data, &bpf_loader_upgradeable::id())
+            .unwrap()
+            .into(),
+    );
+    context
+}
+
+/// Creates the config with `admin` as the admin and fee recipient and no protocol fee
+pub async fn init_config(
+    context: &mut ProgramTestContext,
+    admin: &Keypair,
+) -> Result<(), BanksClientError> {
+    let mut data = vec![3];
+    data.extend_from_slice(&0u16.to_le_bytes());
+    let ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(admin.pubkey(), true),
+            AccountMeta::new(config_address(), false),
+            AccountMeta::new_readonly(admin.pubkey(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(program_data_address(), false),
+        ],
+        data,
+    };
+    process(context, &[ix], &[admin]).await
+}
+
+pub fn program_data_address() -> Pubkey {
+    Pubkey::find_program_address(&[PROGRAM_ID.as_ref()], &bpf_loader_upgradeable::id()).0
+}
+
+pub async fn process(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        context.last_blockhash,
+    );
+    context.banks_client.process_transaction(transaction).await
+}
+
+pub async fn warp_to_slot(context: &mut ProgramTestContext, slot: u64) {
+    context.warp_to_slot(slot).unwrap();
+    context.last_blockhash = context.get_new_latest_blockhash().await.unwrap();
+}
+
+pub async fn current_slot(context: &mut ProgramTestContext) -> u64 {
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    clock.slot
+}
+
+pub async fn create_account(context: &mut ProgramTestContext, owner: &Keypair) {
+    let ix = system_instruction::transfer(&context.payer.pubkey(), &owner.pubkey(), 1_000_000_000);
+    process(context, &[ix], &[]).await.unwrap();
+}
+
+pub async fn create_mint(context: &mut ProgramTestContext) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &context.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &context.payer.pubkey(),
+            None,
+            0,
+        )
+        .unwrap(),
+    ];
+    process(context, &instructions, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+/// Token-2022 mint withholding `transfer_fee_basis_points` of every transfer
+pub async fn create_mint_2022(
+    context: &mut ProgramTestContext,
+    transfer_fee_basis_points: u16,
+) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let space = ExtensionType::get_account_len::<spl_token_2022::state::Mint>(&[
+        ExtensionType::TransferFeeConfig,
+    ]);
+    let instructions = [
+        system_instruction::create_account(
+            &context.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(space),
+            space as u64,
+            &spl_token_2022::id(),
+        ),
+        transfer_fee_instruction::initialize_transfer_fee_config(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            None,
+            None,
+            transfer_fee_basis_points,
+            u64::MAX,
+        )
+        .unwrap(),
+        spl_token_2022::instruction::initialize_mint(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            &context.payer.pubkey(),
+            None,
+            0,
+        )
+        .unwrap(),
+    ];
+    process(context, &instructions, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+/// The token program owning the mint, the original one or Token-2022
+pub async fn token_program_of(context: &mut ProgramTestContext, mint: &Pubkey) -> Pubkey {
+    context
+        .banks_client
+        .get_account(*mint)
+        .await
+        .unwrap()
+        .unwrap()
+        .owner
+}
+
+pub async fn create_token_account(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    let account = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let mint_account = context
+        .banks_client
+        .get_account(*mint)
+        .await
+        .unwrap()
+        .unwrap();
+    let token_program = mint_account.owner;
+    // Token-2022 mints may require extensions on their token accounts, which take extra space
+    let mint_extensions =
+        StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_account.data)
+            .unwrap()
+            .get_extension_types()
+            .unwrap();
+    let space = ExtensionType::get_account_len::<spl_token_2022::state::Account>(
+        &ExtensionType::get_required_init_account_extensions(&mint_extensions),
+    );
+    let instructions = [
+        system_instruction::create_account(
+            &context.payer.pubkey(),
+            &account.pubkey(),
+            rent.minimum_balance(space),
+            space as u64,
+            &token_program,
+        ),
+        spl_token_2022::instruction::initialize_account(
+            &token_program,
+            &account.pubkey(),
+            mint,
+            owner,
+        )
+        .unwrap(),
+    ];
+    process(context, &instructions, &[&account]).await.unwrap();
+    account.pubkey()
+}
+
+/// Token account of `owner` for the mint, or the wallet itself for SOL
+pub async fn create_leg_account(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Keypair,
+) -> Pubkey {
+    if *mint == native_mint::id() {
+        owner.pubkey()
+    } else {
+        create_token_account(context, mint, &owner.pubkey()).await
+    }
+}
+
+pub async fn mint_to(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    account: &Pubkey,
+    amount: u64,
+) {
+    let token_program = token_program_of(context, mint).await;
+    let ix = spl_token_2022::instruction::mint_to(
+        &token_program,
+        mint,
+        account,
+        &context.payer.pubkey(),
+        &[],
+        amount,
+    )
+    .unwrap();
+    process(context, &[ix], &[]).await.unwrap();
+}
+
+pub async fn lamports(context: &mut ProgramTestContext, account: &Pubkey) -> u64 {
+    context.banks_client.get_balance(*account).await.unwrap()
+}
+
+pub async fn account_exists(context: &mut ProgramTestContext, account: &Pubkey) -> bool {
+    context
+        .banks_client
+        .get_account(*account)
+        .await
+        .unwrap()
+        .is_some()
+}
+
+/// Hands the account over to another program, keeping its data
+pub async fn set_owner(context: &mut ProgramTestContext, account: &Pubkey, owner: &Pubkey) {
+    let mut data = context
+        .banks_client
+        .get_account(*account)
+        .await
+        .unwrap()
+        .unwrap();
+    data.owner = *owner;
+    context.set_account(account, &AccountSharedData::from(data));
+}
+
+pub async fn token_account(
+    context: &mut ProgramTestContext,
+    account: &Pubkey,
+) -> spl_token_2022::state::Account {
+    let account = context
+        .banks_client
+        .get_account(*account)
+        .await
+        .unwrap()
+        .unwrap();
+    StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+        .unwrap()
+        .base
+}
+
+pub async fn token_balance(context: &mut ProgramTestContext, account: &Pubkey) -> u64 {
+    token_account(context, account).await.amount
+}
+
+pub fn assert_instruction_error(result: Result<(), BanksClientError>, error: InstructionError) {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(_, other) => assert_eq!(other, error),
+        other => panic!("expected {:?}, got {:?}", error, other),
+    }
+}
+
+pub fn assert_error(result: Result<(), BanksClientError>, error: EscrowError) {
+    assert_instruction_error(result, InstructionError::Custom(error as u32));
+}
+
+pub fn config_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"config"], &PROGRAM_ID).0
+}
+
+pub fn fee_vault_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"fee_vault", mint.as_ref()], &PROGRAM_ID).0
+}
+
+/// Where fees of the mint are held, the config account itself for SOL
+pub fn fee_account(mint: &Pubkey) -> Pubkey {
+    if *mint == native_mint::id() {
+        config_address()
+    } else {
+        fee_vault_address(mint)
+    }
+}
+
+pub fn escrow_address(initializer: &Pubkey, seed: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"escrow", initializer.as_ref(), &seed.to_le_bytes()],
+        &PROGRAM_ID,
+    )
+    .0
+}
+
+pub fn vault_address(escrow: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"vault", escrow.as_ref()], &PROGRAM_ID).0
+}
+
+/// Alice offers `deposit` X tokens for `expected` Y tokens, Bob is the taker. Either side may be
+/// SOL, its accounts are then the wallets
+pub struct Trade {
+    pub alice: Keypair,
+    pub bob: Keypair,
+    pub mint_x: Pubkey,
+    pub mint_y: Pubkey,
+    pub token_program_x: Pubkey,
+    pub token_program_y: Pubkey,
+    pub alice_x: Pubkey,
+    pub alice_y: Pubkey,
+    pub bob_x: Pubkey,
+    pub bob_y: Pubkey,
+    pub seed: u64,
+    pub escrow: Pubkey,
+    pub vault: Pubkey,
+    pub deposit: u64,
+    pub expected: u64,
+    pub allowed_taker: Option<Pubkey>,
+    /// The most Bob agrees to pay for a fill
+    pub max_pay_amount: u64,
+}
+
+impl Trade {
+    pub async fn setup(context: &mut ProgramTestContext, deposit: u64, expected: u64) -> Self {
+        let mint_x = create_mint(context).await;
+        let mint_y = create_mint(context).await;
+        Self::setup_with_mints(context, mint_x, mint_y, deposit, expected).await
+    }
+
+    pub async fn setup_with_mints(
+        context: &mut ProgramTestContext,
+        mint_x: Pubkey,
+        mint_y: Pubkey,
+        deposit: u64,
+        expected: u64,
+    ) -> Self {
+        let alice = Keypair::new();
+        let bob = Keypair::new();
+        create_account(context, &alice).await;
+        create_account(context, &bob).await;
+
+        let token_program_x = token_program_of(context, &mint_x).await;
+        let token_program_y = token_program_of(context, &mint_y).await;
+        let alice_x = create_leg_account(context, &mint_x, &alice).await;
+        let alice_y = create_leg_account(context, &mint_y, &alice).await;
+        let bob_x = create_leg_account(context, &mint_x, &bob).await;
+        let bob_y = create_leg_account(context, &mint_y, &bob).await;
+        // SOL sides are paid from the lamports the wallets were created with
+        if mint_x != native_mint::id() {
+            mint_to(context, &mint_x, &alice_x, deposit).await;
+        }
+        if mint_y != native_mint::id() {
+            mint_to(context, &mint_y, &bob_y, expected).await;
+        }
+
+        let seed = 0;
+        let escrow = escrow_address(&alice.pubkey(), seed);
+        Trade {
+            alice,
+            bob,
+            mint_x,
+            mint_y,
+            token_program_x,
+            token_program_y,
+            alice_x,
+            alice_y,
+            bob_x,
+            bob_y,
+            seed,
+            escrow,
+            vault: vault_address(&escrow),
+            deposit,
+            expected,
+            allowed_taker: None,
+            max_pay_amount: u64::MAX,
+        }
+    }
+
+    /// Points the trade at the escrow and vault of another seed of the same initializer
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.escrow = escrow_address(&self.alice.pubkey(), seed);
+        self.vault = vault_address(&self.escrow);
+    }
+
+    /// The vault, or the escrow itself holding a SOL deposit
+    pub fn vault_account(&self) -> Pubkey {
+        if self.mint_x == native_mint::id() {
+            self.escrow
+        } else {
+            self.vault
+        }
+    }
+
+    pub fn pda(&self) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                b"escrow",
+                self.escrow.as_ref(),
+                self.alice.pubkey().as_ref(),
+            ],
+            &PROGRAM_ID,
+        )
+        .0
+    }
+
+    pub async fn initialize(
+        &self,
+        context: &mut ProgramTestContext,
+        unlock_time: Option<u64>,
+        time_out: Option<u64>,
+    ) -> Result<(), BanksClientError> {
+        let ix = self.initialize_instruction(unlock_time, time_out);
+        process(context, &[ix], &[&self.alice]).await
+    }
+
+    pub fn initialize_instruction(
+        &self,
+        unlock_time: Option<u64>,
+        time_out: Option<u64>,
+    ) -> Instruction {
+        instruction::init_escrow(
+            &PROGRAM_ID,
+            &self.alice.pubkey(),
+            &self.alice_x,
+            &self.alice_y,
+            &self.mint_x,
+            &self.token_program_x,
+            self.seed,
+            self.expected,
+            self.deposit,
+            unlock_time,
+            time_out,
+            self.allowed_taker,
+        )
+    }
+
+    /// Initializes the trade open from the current slot, timing out 100 slots later
+    pub async fn open(&self, context: &mut ProgramTestContext) -> Result<(), BanksClientError> {
+        let slot = current_slot(context).await;
+        self.initialize(context, Some(slot), Some(slot + 100)).await
+    }
+
+    pub async fn exchange(&self, context: &mut ProgramTestContext) -> Result<(), BanksClientError> {
+        self.fill(context, self.deposit).await
+    }
+
+    pub async fn fill(
+        &self,
+        context: &mut ProgramTestContext,
+        amount: u64,
+    ) -> Result<(), BanksClientError> {
+        let ix = self.fill_instruction(amount);
+        process(context, &[ix], &[&self.bob]).await
+    }
+
+    /// Bob takes `amount` X tokens, paying whatever share of the price it costs
+    pub fn fill_instruction(&self, amount: u64) -> Instruction {
+        instruction::exchange(
+            &PROGRAM_ID,
+            &self.bob.pubkey(),
+            &self.bob_y,
+            &self.bob_x,
+            &self.alice.pubkey(),
+            &self.alice_y,
+            &self.escrow,
+            &self.mint_x,
+            &self.token_program_x,
+            &self.mint_y,
+            &self.token_program_y,
+            amount,
+            self.max_pay_amount,
+        )
+    }
+
+    pub async fn cancel(&self, context: &mut ProgramTestContext) -> Result<(), BanksClientError> {
+        let ix = self.cancel_instruction();
+        process(context, &[ix], &[&self.alice]).await
+    }
+
+    /// Alice closes the escrow, the deposit going back to her X account
+    pub fn cancel_instruction(&self) -> Instruction {
+        instruction::cancel(
+            &PROGRAM_ID,
+            &self.alice.pubkey(),
+            &self.escrow,
+            &self.alice_x,
+            &self.mint_x,
+            &self.token_program_x,
+        )
+    }
+
+    pub async fn state(&self, context: &mut ProgramTestContext) -> Escrow {
+        let account = context
+            .banks_client
+            .get_account(self.escrow)
+            .await
+            .unwrap()
+            .unwrap();
+        Escrow::unpack(&account.data).unwrap()
+    }
+}
+
+pub async fn set_fee(
+    context: &mut ProgramTestContext,
+    admin: &Keypair,
+    fee_basis_points: u16,
+) -> Result<(), BanksClientError> {
+    let mut data = vec![4];
+    data.extend_from_slice(&fee_basis_points.to_le_bytes());
+    let ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(admin.pubkey(), true),
+            AccountMeta::new(config_address(), false),
+        ],
+        data,
+    };
+    process(context, &[ix], &[admin]).await
+}
+
+pub async fn set_paused(
+    context: &mut ProgramTestContext,
+    admin: &Keypair,
+    paused: bool,
+) -> Result<(), BanksClientError> {
+    let ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(admin.pubkey(), true),
+            AccountMeta::new(config_address(), false),
+        ],
+        data: vec![8, paused as u8],
+    };
+    process(context, &[ix], &[admin]).await
+}
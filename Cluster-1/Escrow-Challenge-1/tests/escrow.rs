@@ -0,0 +1,346 @@
+mod common;
+
+use bpf_program_template::{error::EscrowError, state::Escrow};
+use common::{
+    account_exists, assert_error, assert_instruction_error, current_slot, mint_to, process,
+    set_owner, token_account, token_balance, warp_to_slot, Trade, PROGRAM_ID,
+};
+use solana_sdk::{
+    instruction::{AccountMeta, InstructionError},
+    pubkey::Pubkey,
+    signature::Signer,
+};
+
+#[tokio::test]
+async fn init_escrow_moves_the_deposit_into_the_vault() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 0);
+    let vault = token_account(&mut context, &trade.vault).await;
+    assert_eq!(vault.owner, trade.pda());
+    assert_eq!(vault.mint, trade.mint_x);
+    assert_eq!(vault.amount, 50);
+    let escrow = trade.state(&mut context).await;
+    assert_eq!(escrow.initializer_pubkey, trade.alice.pubkey());
+    assert_eq!(escrow.temp_token_account_pubkey, trade.vault);
+    assert_eq!(
+        escrow.initializer_token_to_receive_account_pubkey,
+        trade.alice_y
+    );
+    assert_eq!(escrow.expected_amount, 30);
+    assert_eq!(escrow.version, Escrow::VERSION);
+    assert_eq!(escrow.mint_a, trade.mint_x);
+    assert_eq!(escrow.mint_b, trade.mint_y);
+    assert_eq!(escrow.deposit_amount, 50);
+    let (_, bump_seed) = Pubkey::find_program_address(
+        &[
+            b"escrow",
+            trade.escrow.as_ref(),
+            trade.alice.pubkey().as_ref(),
+        ],
+        &PROGRAM_ID,
+    );
+    assert_eq!(escrow.bump_seed, bump_seed);
+}
+
+#[tokio::test]
+async fn init_escrow_rejects_receiving_the_deposited_mint() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    let mut ix = trade.initialize_instruction(None, None);
+    ix.accounts[2] = AccountMeta::new_readonly(trade.alice_x, false);
+    assert_error(
+        process(&mut context, &[ix], &[&trade.alice]).await,
+        EscrowError::MintMismatch,
+    );
+}
+
+#[tokio::test]
+async fn init_escrow_rejects_a_vault_not_derived_from_the_escrow() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    let mut ix = trade.initialize_instruction(None, None);
+    ix.accounts[4] = AccountMeta::new(Pubkey::new_unique(), false);
+    assert_instruction_error(
+        process(&mut context, &[ix], &[&trade.alice]).await,
+        InstructionError::InvalidSeeds,
+    );
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 50);
+}
+
+#[tokio::test]
+async fn every_escrow_gets_its_own_pda() {
+    let mut context = common::start().await;
+    let first = Trade::setup(&mut context, 50, 30).await;
+    let second = Trade::setup(&mut context, 20, 10).await;
+
+    first.initialize(&mut context, None, None).await.unwrap();
+    second.initialize(&mut context, None, None).await.unwrap();
+
+    assert_ne!(first.pda(), second.pda());
+    assert_eq!(
+        token_account(&mut context, &first.vault).await.owner,
+        first.pda()
+    );
+    assert_eq!(
+        token_account(&mut context, &second.vault).await.owner,
+        second.pda()
+    );
+}
+
+#[tokio::test]
+async fn one_initializer_runs_an_escrow_per_seed() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+    let (first_escrow, first_vault) = (trade.escrow, trade.vault);
+
+    // A new blockhash, minting the deposit again is the same transaction as in the setup
+    let slot = current_slot(&mut context).await;
+    warp_to_slot(&mut context, slot + 1).await;
+    mint_to(&mut context, &trade.mint_x, &trade.alice_x, 50).await;
+    trade.set_seed(7);
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    assert_ne!(trade.escrow, first_escrow);
+    assert_eq!(token_balance(&mut context, &first_vault).await, 50);
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+    assert_eq!(trade.state(&mut context).await.remaining_amount, 50);
+}
+
+#[tokio::test]
+async fn escrow_cannot_be_initialized_twice() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    // A new blockhash, the same initialize transaction was already processed
+    let slot = current_slot(&mut context).await;
+    warp_to_slot(&mut context, slot + 1).await;
+    // The system program refuses to create the escrow account again
+    assert_instruction_error(
+        trade.initialize(&mut context, None, None).await,
+        InstructionError::Custom(0),
+    );
+
+    // Another seed can't be pointed at the existing escrow either
+    let mut ix = trade.initialize_instruction(None, None);
+    ix.data[1..9].copy_from_slice(&1u64.to_le_bytes());
+    assert_instruction_error(
+        process(&mut context, &[ix], &[&trade.alice]).await,
+        InstructionError::InvalidSeeds,
+    );
+
+    let escrow = trade.state(&mut context).await;
+    assert_eq!(escrow.remaining_amount, 50);
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+}
+
+#[tokio::test]
+async fn exchange_settles_both_sides_and_closes_the_escrow() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 50);
+    assert_eq!(token_balance(&mut context, &trade.bob_y).await, 0);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 30);
+    assert!(!account_exists(&mut context, &trade.vault).await);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn exchange_rejects_an_escrow_owned_by_another_program() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+
+    // The same escrow data under another owner, which could have written anything in it
+    set_owner(&mut context, &trade.escrow, &Pubkey::new_unique()).await;
+    assert_instruction_error(
+        trade.exchange(&mut context).await,
+        InstructionError::IncorrectProgramId,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+}
+
+#[tokio::test]
+async fn trade_unlocks_after_100_slots_and_times_out_1000_slots_later_by_default() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let slot = current_slot(&mut context).await;
+
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    let escrow = trade.state(&mut context).await;
+    assert_eq!(escrow.unlock_time, slot + 100);
+    assert_eq!(escrow.time_out, slot + 1100);
+}
+
+#[tokio::test]
+async fn trade_is_locked_until_the_unlock_slot() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot + 10), Some(slot + 20))
+        .await
+        .unwrap();
+
+    assert_error(trade.exchange(&mut context).await, EscrowError::TradeLock);
+    warp_to_slot(&mut context, slot + 9).await;
+    assert_error(trade.exchange(&mut context).await, EscrowError::TradeLock);
+
+    // The unlock slot itself is open
+    warp_to_slot(&mut context, slot + 10).await;
+    trade.exchange(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 50);
+}
+
+#[tokio::test]
+async fn trade_times_out_after_the_time_out_slot() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), Some(slot + 20))
+        .await
+        .unwrap();
+
+    warp_to_slot(&mut context, slot + 21).await;
+    assert_error(
+        trade.exchange(&mut context).await,
+        EscrowError::TradeTimeOut,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+}
+
+#[tokio::test]
+async fn trade_can_be_taken_on_its_time_out_slot() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), Some(slot + 20))
+        .await
+        .unwrap();
+
+    warp_to_slot(&mut context, slot + 20).await;
+    trade.exchange(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 30);
+}
+
+#[tokio::test]
+async fn init_escrow_rejects_inverted_or_expired_windows() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    warp_to_slot(&mut context, 50).await;
+
+    // Unlocking after the time out
+    assert_error(
+        trade.initialize(&mut context, Some(70), Some(60)).await,
+        EscrowError::InvalidTimeWindow,
+    );
+    // Timing out before now
+    assert_error(
+        trade.initialize(&mut context, Some(10), Some(40)).await,
+        EscrowError::InvalidTimeWindow,
+    );
+    // The default time out is 1000 slots after the unlock, which can overflow
+    assert_error(
+        trade.initialize(&mut context, Some(u64::MAX), None).await,
+        EscrowError::InvalidTimeWindow,
+    );
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn exchange_rejects_amounts_the_escrow_does_not_hold() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+
+    assert_error(
+        trade.fill(&mut context, 0).await,
+        EscrowError::ExpectedAmountMismatch,
+    );
+    assert_error(
+        trade.fill(&mut context, 51).await,
+        EscrowError::ExpectedAmountMismatch,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+}
+
+#[tokio::test]
+async fn exchange_rejects_taker_accounts_of_other_mints() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+
+    // Paying with the deposited mint
+    let mut ix = trade.fill_instruction(trade.deposit);
+    ix.accounts[1] = AccountMeta::new(trade.bob_x, false);
+    assert_error(
+        process(&mut context, &[ix], &[&trade.bob]).await,
+        EscrowError::MintMismatch,
+    );
+    // Receiving the deposit in an account of the other mint
+    let mut ix = trade.fill_instruction(trade.deposit);
+    ix.accounts[2] = AccountMeta::new(trade.bob_y, false);
+    assert_error(
+        process(&mut context, &[ix], &[&trade.bob]).await,
+        EscrowError::MintMismatch,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+}
+
+#[tokio::test]
+async fn exchange_rejects_the_pda_of_another_escrow() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let other = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+    other.open(&mut context).await.unwrap();
+
+    let mut ix = trade.fill_instruction(trade.deposit);
+    ix.accounts[8] = AccountMeta::new_readonly(other.pda(), false);
+    assert_instruction_error(
+        process(&mut context, &[ix], &[&trade.bob]).await,
+        InstructionError::InvalidSeeds,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+}
+
+#[tokio::test]
+async fn exchange_rejects_accounts_the_escrow_does_not_name() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let other = Trade::setup_with_mints(&mut context, trade.mint_x, trade.mint_y, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+    other.open(&mut context).await.unwrap();
+
+    // The vault of another escrow of the same mints
+    let mut other_vault = trade.fill_instruction(trade.deposit);
+    other_vault.accounts[3] = AccountMeta::new(other.vault, false);
+    // Another initializer to send the rent to
+    let mut other_initializer = trade.fill_instruction(trade.deposit);
+    other_initializer.accounts[4] = AccountMeta::new(other.alice.pubkey(), false);
+    // Another account to pay the price into
+    let mut other_receiver = trade.fill_instruction(trade.deposit);
+    other_receiver.accounts[5] = AccountMeta::new(other.alice_y, false);
+    for ix in [other_vault, other_initializer, other_receiver] {
+        assert_instruction_error(
+            process(&mut context, &[ix], &[&trade.bob]).await,
+            InstructionError::InvalidAccountData,
+        );
+    }
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+    assert_eq!(token_balance(&mut context, &other.vault).await, 50);
+}
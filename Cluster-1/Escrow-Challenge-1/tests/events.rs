@@ -0,0 +1,142 @@
+mod common;
+
+use base64::{engine::general_purpose, Engine};
+use bpf_program_template::event::EscrowEvent;
+use common::{create_mint, current_slot, events, Trade, PROGRAM_ID};
+use solana_sdk::{pubkey::Pubkey, signature::Signer};
+use spl_token::native_mint;
+
+// Events are collected across the tests running alongside, so each test looks at its own escrow
+fn events_of(escrow: &Pubkey) -> Vec<EscrowEvent> {
+    events()
+        .into_iter()
+        .filter(|event| match event {
+            EscrowEvent::Created { escrow: e, .. }
+            | EscrowEvent::Exchanged { escrow: e, .. }
+            | EscrowEvent::Updated { escrow: e, .. }
+            | EscrowEvent::Closed { escrow: e, .. } => e == escrow,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn escrow_lifecycle_is_logged() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let slot = current_slot(&mut context).await;
+    trade.open(&mut context).await.unwrap();
+    trade.fill(&mut context, 20).await.unwrap();
+    trade.fill(&mut context, 30).await.unwrap();
+
+    let alice = trade.alice.pubkey();
+    let exchanged = |taken_amount, paid_amount, remaining_amount| EscrowEvent::Exchanged {
+        escrow: trade.escrow,
+        initializer: alice,
+        taker: trade.bob.pubkey(),
+        mint_a: trade.mint_x,
+        mint_b: trade.mint_y,
+        taken_amount,
+        paid_amount,
+        remaining_amount,
+        slot,
+    };
+    assert_eq!(
+        events_of(&trade.escrow),
+        vec![
+            EscrowEvent::Created {
+                escrow: trade.escrow,
+                initializer: alice,
+                mint_a: trade.mint_x,
+                mint_b: trade.mint_y,
+                deposit_amount: 50,
+                expected_amount: 30,
+                unlock_time: slot,
+                time_out: slot + 100,
+                allowed_taker: None,
+                slot,
+            },
+            exchanged(20, 12, 30),
+            exchanged(30, 18, 0),
+            EscrowEvent::Closed {
+                escrow: trade.escrow,
+                initializer: alice,
+                refunded_amount: 0,
+                slot,
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn cancel_logs_the_refund() {
+    let mut context = common::start().await;
+    let mint_y = create_mint(&mut context).await;
+    let trade = Trade::setup_with_mints(&mut context, native_mint::id(), mint_y, 5_000, 30).await;
+    let slot = current_slot(&mut context).await;
+    trade.open(&mut context).await.unwrap();
+    trade.fill(&mut context, 2_000).await.unwrap();
+    trade.cancel(&mut context).await.unwrap();
+
+    assert_eq!(
+        events_of(&trade.escrow).last(),
+        Some(&EscrowEvent::Closed {
+            escrow: trade.escrow,
+            initializer: trade.alice.pubkey(),
+            refunded_amount: 3_000,
+            slot,
+        })
+    );
+}
+
+#[test]
+fn events_are_decoded_from_the_logs_of_the_program_only() {
+    let event = EscrowEvent::Closed {
+        escrow: Pubkey::new_unique(),
+        initializer: Pubkey::new_unique(),
+        refunded_amount: 7,
+        slot: 42,
+    };
+    let data = general_purpose::STANDARD.encode(borsh::to_vec(&event).unwrap());
+    let other_program = Pubkey::new_unique();
+    let logs: Vec<String> = [
+        format!("Program {} invoke [1]", other_program),
+        format!("Program data: {}", data),
+        format!("Program {} invoke [2]", PROGRAM_ID),
+        "Program log: Instruction: Cancel".to_string(),
+        format!("Program {} invoke [3]", spl_token::id()),
+        format!("Program data: {}", data),
+        format!("Program {} success", spl_token::id()),
+        format!("Program data: {}", data),
+        "Program data: bm90IGFuIGV2ZW50".to_string(),
+        format!("Program {} success", PROGRAM_ID),
+        format!("Program data: {}", data),
+        format!("Program {} success", other_program),
+    ]
+    .to_vec();
+
+    assert_eq!(EscrowEvent::from_logs(&PROGRAM_ID, &logs), vec![event]);
+}
+
+#[test]
+fn updated_event_round_trips_through_the_logs() {
+    let event = EscrowEvent::Updated {
+        escrow: Pubkey::new_unique(),
+        initializer: Pubkey::new_unique(),
+        mint_a: Pubkey::new_unique(),
+        mint_b: Pubkey::new_unique(),
+        deposit_amount: 40,
+        expected_amount: 25,
+        time_out: 500,
+        allowed_taker: Some(Pubkey::new_unique()),
+        slot: 42,
+    };
+    let data = general_purpose::STANDARD.encode(borsh::to_vec(&event).unwrap());
+    assert_eq!(EscrowEvent::decode(&data), Some(event.clone()));
+
+    let logs = vec![
+        format!("Program {} invoke [1]", PROGRAM_ID),
+        format!("Program data: {}", data),
+        format!("Program {} success", PROGRAM_ID),
+    ];
+    assert_eq!(EscrowEvent::from_logs(&PROGRAM_ID, &logs), vec![event]);
+}
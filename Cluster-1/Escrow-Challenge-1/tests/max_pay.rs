@@ -0,0 +1,59 @@
+mod common;
+
+use bpf_program_template::error::EscrowError;
+use common::{assert_error, create_mint, create_mint_2022, mint_to, token_balance, Trade};
+
+#[tokio::test]
+async fn exchange_at_exactly_the_max_pay_amount_goes_through() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 50, 30).await;
+    trade.max_pay_amount = 30;
+    trade.open(&mut context).await.unwrap();
+
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 50);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 30);
+}
+
+#[tokio::test]
+async fn price_above_the_max_pay_amount_is_rejected() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 50, 30).await;
+    trade.max_pay_amount = 29;
+    trade.open(&mut context).await.unwrap();
+
+    assert_error(
+        trade.exchange(&mut context).await,
+        EscrowError::MaxPayAmountExceeded,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+    assert_eq!(token_balance(&mut context, &trade.bob_y).await, 30);
+
+    // A partial fill is priced pro rata, 20 of 50 cost 12
+    trade.max_pay_amount = 12;
+    trade.fill(&mut context, 20).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 12);
+}
+
+#[tokio::test]
+async fn transfer_fee_of_the_price_counts_towards_the_max_pay_amount() {
+    let mut context = common::start().await;
+    let mint_x = create_mint(&mut context).await;
+    // 1% transfer fee
+    let mint_y = create_mint_2022(&mut context, 100).await;
+    let mut trade = Trade::setup_with_mints(&mut context, mint_x, mint_y, 50, 1000).await;
+    mint_to(&mut context, &trade.mint_y, &trade.bob_y, 100).await;
+    trade.max_pay_amount = 1000;
+    trade.open(&mut context).await.unwrap();
+
+    // 1011 would have to be sent for 1000 to arrive
+    assert_error(
+        trade.exchange(&mut context).await,
+        EscrowError::MaxPayAmountExceeded,
+    );
+
+    trade.max_pay_amount = 1011;
+    trade.exchange(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 1000);
+}
@@ -0,0 +1,285 @@
+use bpf_program_template::{error::EscrowError, instruction::EscrowInstruction, state::Escrow};
+use proptest::{collection::vec, option, prelude::*};
+use solana_program::{program_error::ProgramError, program_pack::Pack, pubkey::Pubkey};
+
+fn pubkey() -> impl Strategy<Value = Pubkey> {
+    any::<[u8; 32]>().prop_map(Pubkey::new_from_array)
+}
+
+fn instruction() -> impl Strategy<Value = EscrowInstruction> {
+    prop_oneof![
+        (
+            any::<u64>(),
+            any::<u64>(),
+            any::<u64>(),
+            option::of(any::<u64>()),
+            option::of(any::<u64>()),
+            option::of(pubkey()),
+        )
+            .prop_map(
+                |(seed, amount, deposit_amount, unlock_time, time_out, allowed_taker)| {
+                    EscrowInstruction::InitEscrow {
+                        seed,
+                        amount,
+                        deposit_amount,
+                        unlock_time,
+                        time_out,
+                        allowed_taker,
+                    }
+                }
+            ),
+        (any::<u64>(), any::<u64>()).prop_map(|(receive_amount, max_pay_amount)| {
+            EscrowInstruction::Exchange {
+                receive_amount,
+                max_pay_amount,
+            }
+        }),
+        Just(EscrowInstruction::Cancel),
+        any::<u16>()
+            .prop_map(|fee_basis_points| EscrowInstruction::InitConfig { fee_basis_points }),
+        any::<u16>().prop_map(|fee_basis_points| EscrowInstruction::SetFee { fee_basis_points }),
+        Just(EscrowInstruction::SetFeeRecipient),
+        any::<u64>().prop_map(|amount| EscrowInstruction::WithdrawFees { amount }),
+        Just(EscrowInstruction::SetAdmin),
+        any::<bool>().prop_map(|paused| EscrowInstruction::SetPaused { paused }),
+        (
+            option::of(any::<u64>()),
+            option::of(any::<u64>()),
+            option::of(option::of(pubkey())),
+            any::<u64>(),
+            any::<u64>(),
+        )
+            .prop_map(
+                |(expected_amount, time_out, allowed_taker, top_up_amount, withdraw_amount)| {
+                    EscrowInstruction::UpdateEscrow {
+                        expected_amount,
+                        time_out,
+                        allowed_taker,
+                        top_up_amount,
+                        withdraw_amount,
+                    }
+                }
+            ),
+    ]
+}
+
+// The wire format the program expects: a tag byte, then the fields in order, little endian,
+// options as a 0 or 1 byte followed by the value when there is one
+fn encode(instruction: &EscrowInstruction) -> Vec<u8> {
+    fn u64_option(data: &mut Vec<u8>, value: &Option<u64>) {
+        match value {
+            Some(value) => {
+                data.push(1);
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            None => data.push(0),
+        }
+    }
+    fn pubkey_option(data: &mut Vec<u8>, key: &Option<Pubkey>) {
+        match key {
+            Some(key) => {
+                data.push(1);
+                data.extend_from_slice(key.as_ref());
+            }
+            None => data.push(0),
+        }
+    }
+
+    let mut data = vec![];
+    match instruction {
+        EscrowInstruction::InitEscrow {
+            seed,
+            amount,
+            deposit_amount,
+            unlock_time,
+            time_out,
+            allowed_taker,
+        } => {
+            data.push(0);
+            data.extend_from_slice(&seed.to_le_bytes());
+            data.extend_from_slice(&amount.to_le_bytes());
+            data.extend_from_slice(&deposit_amount.to_le_bytes());
+            u64_option(&mut data, unlock_time);
+            u64_option(&mut data, time_out);
+            pubkey_option(&mut data, allowed_taker);
+        }
+        EscrowInstruction::Exchange {
+            receive_amount,
+            max_pay_amount,
+        } => {
+            data.push(1);
+            data.extend_from_slice(&receive_amount.to_le_bytes());
+            data.extend_from_slice(&max_pay_amount.to_le_bytes());
+        }
+        EscrowInstruction::Cancel => data.push(2),
+        EscrowInstruction::InitConfig { fee_basis_points } => {
+            data.push(3);
+            data.extend_from_slice(&fee_basis_points.to_le_bytes());
+        }
+        EscrowInstruction::SetFee { fee_basis_points } => {
+            data.push(4);
+            data.extend_from_slice(&fee_basis_points.to_le_bytes());
+        }
+        EscrowInstruction::SetFeeRecipient => data.push(5),
+        EscrowInstruction::WithdrawFees { amount } => {
+            data.push(6);
+            data.extend_from_slice(&amount.to_le_bytes());
+        }
+        EscrowInstruction::SetAdmin => data.push(7),
+        EscrowInstruction::SetPaused { paused } => data.extend_from_slice(&[8, *paused as u8]),
+        EscrowInstruction::UpdateEscrow {
+            expected_amount,
+            time_out,
+            allowed_taker,
+            top_up_amount,
+            withdraw_amount,
+        } => {
+            data.push(9);
+            u64_option(&mut data, expected_amount);
+            u64_option(&mut data, time_out);
+            match allowed_taker {
+                Some(allowed_taker) => {
+                    data.push(1);
+                    pubkey_option(&mut data, allowed_taker);
+                }
+                None => data.push(0),
+            }
+            data.extend_from_slice(&top_up_amount.to_le_bytes());
+            data.extend_from_slice(&withdraw_amount.to_le_bytes());
+        }
+    }
+    data
+}
+
+fn escrow() -> impl Strategy<Value = Escrow> {
+    (
+        (any::<bool>(), any::<u8>(), pubkey(), pubkey(), pubkey()),
+        (pubkey(), pubkey(), any::<u64>(), any::<u64>(), any::<u64>()),
+        (
+            any::<u64>(),
+            any::<u8>(),
+            any::<u64>(),
+            any::<u64>(),
+            option::of(pubkey()),
+        ),
+    )
+        .prop_map(
+            |(
+                (is_initialized, version, initializer_pubkey, temp_token_account_pubkey, receive),
+                (mint_a, mint_b, deposit_amount, expected_amount, unlock_time),
+                (time_out, bump_seed, remaining_amount, remaining_expected_amount, allowed_taker),
+            )| Escrow {
+                is_initialized,
+                // Only accounts of the current layout can be read once initialized
+                version: if is_initialized {
+                    Escrow::VERSION
+                } else {
+                    version
+                },
+                initializer_pubkey,
+                temp_token_account_pubkey,
+                initializer_token_to_receive_account_pubkey: receive,
+                mint_a,
+                mint_b,
+                deposit_amount,
+                expected_amount,
+                unlock_time,
+                time_out,
+                bump_seed,
+                remaining_amount,
+                remaining_expected_amount,
+                allowed_taker,
+            },
+        )
+}
+
+fn packed(escrow: &Escrow) -> Vec<u8> {
+    let mut data = vec![0; Escrow::LEN];
+    Escrow::pack(escrow.clone(), &mut data).unwrap();
+    data
+}
+
+proptest! {
+    #[test]
+    fn instructions_round_trip(instruction in instruction()) {
+        prop_assert_eq!(EscrowInstruction::unpack(&encode(&instruction)), Ok(instruction));
+    }
+
+    #[test]
+    fn instructions_pack_into_the_wire_format(instruction in instruction()) {
+        prop_assert_eq!(instruction.pack(), encode(&instruction));
+    }
+
+    #[test]
+    fn any_instruction_data_is_unpacked_or_rejected_as_invalid(
+        // Mostly the tags of actual instructions, so the fields behind them get parsed
+        tag in 0u8..12,
+        fields in vec(any::<u8>(), 0..128),
+    ) {
+        let mut data = vec![tag];
+        data.extend_from_slice(&fields);
+        if let Err(error) = EscrowInstruction::unpack(&data) {
+            prop_assert_eq!(error, EscrowError::InvalidInstruction.into());
+        }
+    }
+
+    #[test]
+    fn instructions_with_trailing_bytes_are_rejected(
+        instruction in instruction(),
+        trailing in vec(any::<u8>(), 1..40),
+    ) {
+        let mut data = instruction.pack();
+        data.extend_from_slice(&trailing);
+        prop_assert_eq!(
+            EscrowInstruction::unpack(&data),
+            Err(EscrowError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn truncated_instructions_are_rejected(instruction in instruction(), cut in any::<prop::sample::Index>()) {
+        let data = instruction.pack();
+        let len = cut.index(data.len());
+        prop_assert_eq!(
+            EscrowInstruction::unpack(&data[..len]),
+            Err(EscrowError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn escrows_round_trip(escrow in escrow()) {
+        prop_assert_eq!(Escrow::unpack_unchecked(&packed(&escrow)), Ok(escrow));
+    }
+
+    #[test]
+    fn any_escrow_data_is_unpacked_or_rejected_as_invalid(data in vec(any::<u8>(), Escrow::LEN)) {
+        match Escrow::unpack_unchecked(&data) {
+            // What could be read packs back into the same state
+            Ok(escrow) => prop_assert_eq!(Escrow::unpack_unchecked(&packed(&escrow)), Ok(escrow)),
+            Err(error) => prop_assert_eq!(error, ProgramError::InvalidAccountData),
+        }
+    }
+
+    #[test]
+    fn escrow_data_of_another_length_is_rejected(
+        escrow in escrow(),
+        len in (0..Escrow::LEN * 2).prop_filter("the escrow length", |len| *len != Escrow::LEN),
+    ) {
+        let mut data = packed(&escrow);
+        data.resize(len, 0);
+        prop_assert_eq!(Escrow::unpack_unchecked(&data), Err(ProgramError::InvalidAccountData));
+    }
+
+    #[test]
+    fn is_initialized_only_accepts_0_or_1(escrow in escrow(), is_initialized in any::<u8>()) {
+        let mut escrow = escrow;
+        escrow.version = Escrow::VERSION;
+        let mut data = packed(&escrow);
+        data[0] = is_initialized;
+        let unpacked = Escrow::unpack_unchecked(&data);
+        match is_initialized {
+            0 | 1 => prop_assert_eq!(unpacked.map(|escrow| escrow.is_initialized), Ok(is_initialized == 1)),
+            _ => prop_assert_eq!(unpacked.err(), Some(ProgramError::InvalidAccountData)),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use bpf_program_template::error::EscrowError;
+use common::{account_exists, assert_error, current_slot, token_balance, warp_to_slot, Trade};
+
+#[tokio::test]
+async fn partial_fill_pays_pro_rata_and_keeps_escrow_open() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+
+    trade.fill(&mut context, 20).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 20);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 12);
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 30);
+
+    let escrow = trade.state(&mut context).await;
+    assert_eq!(escrow.remaining_amount, 30);
+    assert_eq!(escrow.remaining_expected_amount, 18);
+}
+
+#[tokio::test]
+async fn last_fill_pays_the_rest_and_closes_escrow() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 3, 10).await;
+    trade.open(&mut context).await.unwrap();
+
+    // 1 of 3 at 10/3 rounds up to 4, the initializer never gets less than their ratio
+    trade.fill(&mut context, 1).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 4);
+
+    let slot = current_slot(&mut context).await;
+    warp_to_slot(&mut context, slot + 1).await;
+    trade.fill(&mut context, 2).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 3);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 10);
+    assert!(!account_exists(&mut context, &trade.vault).await);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn fill_rejects_zero_or_more_than_remaining() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+    trade.fill(&mut context, 20).await.unwrap();
+
+    assert_error(
+        trade.fill(&mut context, 0).await,
+        EscrowError::ExpectedAmountMismatch,
+    );
+    assert_error(
+        trade.fill(&mut context, 31).await,
+        EscrowError::ExpectedAmountMismatch,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 30);
+}
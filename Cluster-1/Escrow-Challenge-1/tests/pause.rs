@@ -0,0 +1,100 @@
+mod common;
+
+use bpf_program_template::error::EscrowError;
+use common::{
+    account_exists, assert_error, config_address, current_slot, process, set_fee, set_paused,
+    token_balance, warp_to_slot, Trade, PROGRAM_ID,
+};
+use solana_program_test::{BanksClientError, ProgramTestContext};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    signature::{Keypair, Signer},
+};
+
+async fn set_admin(
+    context: &mut ProgramTestContext,
+    admin: &Keypair,
+    new_admin: &Keypair,
+) -> Result<(), BanksClientError> {
+    let ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new_readonly(admin.pubkey(), true),
+            AccountMeta::new(config_address(), false),
+            AccountMeta::new_readonly(new_admin.pubkey(), true),
+        ],
+        data: vec![7],
+    };
+    process(context, &[ix], &[admin, new_admin]).await
+}
+
+#[tokio::test]
+async fn paused_program_rejects_new_escrows() {
+    let mut context = common::start().await;
+    let admin = context.payer.insecure_clone();
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    set_paused(&mut context, &admin, true).await.unwrap();
+
+    assert_error(trade.open(&mut context).await, EscrowError::ProgramPaused);
+}
+
+#[tokio::test]
+async fn paused_program_rejects_exchanges_until_resumed() {
+    let mut context = common::start().await;
+    let admin = context.payer.insecure_clone();
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+    set_paused(&mut context, &admin, true).await.unwrap();
+
+    assert_error(
+        trade.exchange(&mut context).await,
+        EscrowError::ProgramPaused,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+
+    set_paused(&mut context, &admin, false).await.unwrap();
+    // A new blockhash, the same exchange transaction was already processed
+    let slot = current_slot(&mut context).await;
+    warp_to_slot(&mut context, slot + 1).await;
+    trade.exchange(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 50);
+}
+
+#[tokio::test]
+async fn cancel_stays_open_while_paused() {
+    let mut context = common::start().await;
+    let admin = context.payer.insecure_clone();
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+    set_paused(&mut context, &admin, true).await.unwrap();
+
+    trade.cancel(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 50);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn only_the_admin_pauses() {
+    let mut context = common::start().await;
+
+    assert_error(
+        set_paused(&mut context, &Keypair::new(), true).await,
+        EscrowError::InvalidAdmin,
+    );
+}
+
+#[tokio::test]
+async fn admin_hands_the_config_over() {
+    let mut context = common::start().await;
+    let admin = context.payer.insecure_clone();
+    let new_admin = Keypair::new();
+
+    set_admin(&mut context, &admin, &new_admin).await.unwrap();
+
+    assert_error(
+        set_fee(&mut context, &admin, 100).await,
+        EscrowError::InvalidAdmin,
+    );
+    set_paused(&mut context, &new_admin, true).await.unwrap();
+}
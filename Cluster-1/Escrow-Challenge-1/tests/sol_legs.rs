@@ -0,0 +1,91 @@
+mod common;
+
+use bpf_program_template::error::EscrowError;
+use common::{account_exists, assert_error, create_mint, lamports, token_balance, Trade};
+use solana_sdk::signature::Signer;
+use spl_token::native_mint;
+
+#[tokio::test]
+async fn sol_deposit_is_held_by_the_escrow_and_paid_to_the_taker() {
+    let mut context = common::start().await;
+    let mint_y = create_mint(&mut context).await;
+    let trade = Trade::setup_with_mints(&mut context, native_mint::id(), mint_y, 5_000, 30).await;
+    trade.open(&mut context).await.unwrap();
+
+    let escrow_lamports = lamports(&mut context, &trade.escrow).await;
+    assert!(!account_exists(&mut context, &trade.vault).await);
+
+    let bob_lamports = lamports(&mut context, &trade.bob.pubkey()).await;
+    trade.fill(&mut context, 2_000).await.unwrap();
+    assert_eq!(
+        lamports(&mut context, &trade.bob.pubkey()).await,
+        bob_lamports + 2_000
+    );
+    assert_eq!(
+        lamports(&mut context, &trade.escrow).await,
+        escrow_lamports - 2_000
+    );
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 12);
+
+    trade.fill(&mut context, 3_000).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 30);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn sol_price_is_paid_from_the_taker_wallet() {
+    let mut context = common::start().await;
+    let mint_x = create_mint(&mut context).await;
+    let trade = Trade::setup_with_mints(&mut context, mint_x, native_mint::id(), 50, 7_000).await;
+    trade.open(&mut context).await.unwrap();
+
+    let alice_lamports = lamports(&mut context, &trade.alice.pubkey()).await;
+    let bob_lamports = lamports(&mut context, &trade.bob.pubkey()).await;
+    let escrow_lamports = lamports(&mut context, &trade.escrow).await;
+    let vault_lamports = lamports(&mut context, &trade.vault).await;
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 50);
+    assert_eq!(
+        lamports(&mut context, &trade.bob.pubkey()).await,
+        bob_lamports - 7_000
+    );
+    // The price plus the rent of the closed escrow and vault
+    assert_eq!(
+        lamports(&mut context, &trade.alice.pubkey()).await,
+        alice_lamports + 7_000 + escrow_lamports + vault_lamports
+    );
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn cancel_returns_a_sol_deposit() {
+    let mut context = common::start().await;
+    let mint_y = create_mint(&mut context).await;
+    let trade = Trade::setup_with_mints(&mut context, native_mint::id(), mint_y, 5_000, 30).await;
+    let alice_lamports = lamports(&mut context, &trade.alice.pubkey()).await;
+    trade.open(&mut context).await.unwrap();
+
+    trade.cancel(&mut context).await.unwrap();
+
+    assert_eq!(
+        lamports(&mut context, &trade.alice.pubkey()).await,
+        alice_lamports
+    );
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn both_sides_can_not_be_sol() {
+    let mut context = common::start().await;
+    let trade = Trade::setup_with_mints(
+        &mut context,
+        native_mint::id(),
+        native_mint::id(),
+        5_000,
+        7_000,
+    )
+    .await;
+
+    assert_error(trade.open(&mut context).await, EscrowError::MintMismatch);
+}
@@ -0,0 +1,271 @@
+mod common;
+
+use bpf_program_template::error::EscrowError;
+use common::{
+    assert_error, assert_instruction_error, config_address, create_mint, current_slot, lamports,
+    mint_to, process, token_balance, warp_to_slot, Trade, PROGRAM_ID,
+};
+use solana_program_test::{BanksClientError, ProgramTestContext};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_program,
+};
+use spl_token::native_mint;
+
+/// Changes to the terms of the trade, nothing changes by default
+#[derive(Default)]
+struct Update {
+    expected_amount: Option<u64>,
+    time_out: Option<u64>,
+    allowed_taker: Option<Option<Pubkey>>,
+    top_up_amount: u64,
+    withdraw_amount: u64,
+}
+
+async fn update(
+    context: &mut ProgramTestContext,
+    trade: &Trade,
+    initializer: &Keypair,
+    update: Update,
+) -> Result<(), BanksClientError> {
+    let mut data = vec![9];
+    for value in [update.expected_amount, update.time_out] {
+        match value {
+            Some(value) => {
+                data.push(1);
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            None => data.push(0),
+        }
+    }
+    match update.allowed_taker {
+        Some(Some(taker)) => {
+            data.extend_from_slice(&[1, 1]);
+            data.extend_from_slice(taker.as_ref());
+        }
+        Some(None) => data.extend_from_slice(&[1, 0]),
+        None => data.push(0),
+    }
+    data.extend_from_slice(&update.top_up_amount.to_le_bytes());
+    data.extend_from_slice(&update.withdraw_amount.to_le_bytes());
+    let ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(initializer.pubkey(), true),
+            AccountMeta::new(trade.escrow, false),
+            AccountMeta::new(trade.vault_account(), false),
+            AccountMeta::new(trade.alice_x, false),
+            AccountMeta::new_readonly(trade.mint_x, false),
+            AccountMeta::new_readonly(trade.token_program_x, false),
+            AccountMeta::new_readonly(trade.pda(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_address(), false),
+        ],
+        data,
+    };
+    process(context, &[ix], &[initializer]).await
+}
+
+#[tokio::test]
+async fn initializer_changes_price_expiry_and_taker() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+    let slot = current_slot(&mut context).await;
+    let alice = trade.alice.insecure_clone();
+    update(
+        &mut context,
+        &trade,
+        &alice,
+        Update {
+            expected_amount: Some(45),
+            time_out: Some(slot + 500),
+            allowed_taker: Some(Some(trade.bob.pubkey())),
+            ..Update::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let escrow_info = trade.state(&mut context).await;
+    assert_eq!(escrow_info.expected_amount, 45);
+    assert_eq!(escrow_info.remaining_expected_amount, 45);
+    assert_eq!(escrow_info.time_out, slot + 500);
+    assert_eq!(escrow_info.allowed_taker, Some(trade.bob.pubkey()));
+
+    mint_to(&mut context, &trade.mint_y, &trade.bob_y, 15).await;
+    trade.exchange(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 45);
+}
+
+#[tokio::test]
+async fn timed_out_escrow_cannot_be_updated() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+    let slot = current_slot(&mut context).await;
+    warp_to_slot(&mut context, slot + 101).await;
+    let alice = trade.alice.insecure_clone();
+
+    // Pushing the time out back would otherwise reopen the trade
+    assert_error(
+        update(
+            &mut context,
+            &trade,
+            &alice,
+            Update {
+                time_out: Some(slot + 500),
+                ..Update::default()
+            },
+        )
+        .await,
+        EscrowError::TradeTimeOut,
+    );
+    assert_error(
+        update(
+            &mut context,
+            &trade,
+            &alice,
+            Update {
+                expected_amount: Some(45),
+                ..Update::default()
+            },
+        )
+        .await,
+        EscrowError::TradeTimeOut,
+    );
+    let escrow_info = trade.state(&mut context).await;
+    assert_eq!(escrow_info.time_out, slot + 100);
+    assert_eq!(escrow_info.expected_amount, 30);
+}
+
+#[tokio::test]
+async fn top_up_and_withdrawal_change_the_deposit() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    mint_to(&mut context, &trade.mint_x, &trade.alice_x, 20).await;
+    trade.open(&mut context).await.unwrap();
+    let alice = trade.alice.insecure_clone();
+
+    update(
+        &mut context,
+        &trade,
+        &alice,
+        Update {
+            top_up_amount: 20,
+            ..Update::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 70);
+    assert_eq!(trade.state(&mut context).await.deposit_amount, 70);
+
+    update(
+        &mut context,
+        &trade,
+        &alice,
+        Update {
+            withdraw_amount: 30,
+            ..Update::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 40);
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 30);
+    let escrow_info = trade.state(&mut context).await;
+    assert_eq!(escrow_info.deposit_amount, 40);
+    assert_eq!(escrow_info.remaining_amount, 40);
+
+    trade.fill(&mut context, 40).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 40);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 30);
+}
+
+#[tokio::test]
+async fn sol_deposit_is_withdrawn_to_the_wallet() {
+    let mut context = common::start().await;
+    let mint_y = create_mint(&mut context).await;
+    let trade = Trade::setup_with_mints(&mut context, native_mint::id(), mint_y, 5_000, 30).await;
+    trade.open(&mut context).await.unwrap();
+    let alice = trade.alice.insecure_clone();
+    let escrow_lamports = lamports(&mut context, &trade.escrow).await;
+
+    update(
+        &mut context,
+        &trade,
+        &alice,
+        Update {
+            withdraw_amount: 2_000,
+            ..Update::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        lamports(&mut context, &trade.escrow).await,
+        escrow_lamports - 2_000
+    );
+    assert_eq!(trade.state(&mut context).await.remaining_amount, 3_000);
+}
+
+#[tokio::test]
+async fn only_the_initializer_updates() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+    let bob = trade.bob.insecure_clone();
+
+    assert_instruction_error(
+        update(
+            &mut context,
+            &trade,
+            &bob,
+            Update {
+                expected_amount: Some(1),
+                ..Update::default()
+            },
+        )
+        .await,
+        InstructionError::InvalidAccountData,
+    );
+}
+
+#[tokio::test]
+async fn deposit_and_price_cannot_be_emptied() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.open(&mut context).await.unwrap();
+    let alice = trade.alice.insecure_clone();
+
+    assert_error(
+        update(
+            &mut context,
+            &trade,
+            &alice,
+            Update {
+                withdraw_amount: 50,
+                ..Update::default()
+            },
+        )
+        .await,
+        EscrowError::InvalidUpdate,
+    );
+    assert_error(
+        update(
+            &mut context,
+            &trade,
+            &alice,
+            Update {
+                expected_amount: Some(0),
+                ..Update::default()
+            },
+        )
+        .await,
+        EscrowError::InvalidUpdate,
+    );
+}
@@ -0,0 +1,326 @@
+//! Off-chain access to the escrow program over RPC, built with the `client` feature.
+//! Transactions are built from the escrow and config state on chain, so callers only name
+//! the escrow and their own accounts
+
+use anchor_lang::{
+    prelude::Pubkey, system_program, AccountDeserialize, Discriminator, InstructionData,
+    ToAccountMetas,
+};
+use anchor_spl::associated_token::{self, get_associated_token_address_with_program_id};
+use anchor_spl::metadata::mpl_token_metadata;
+use anchor_spl::token_interface::TokenAccount;
+pub use escrow_client::ClientError;
+use escrow_client::{program_accounts, send, sign, token_program_of};
+use solana_client::{rpc_client::RpcClient, rpc_filter::RpcFilterType};
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    signature::{Keypair, Signature, Signer},
+    sysvar,
+    transaction::Transaction,
+};
+
+use crate::{
+    accounts, instruction, is_sol, Escrow, EscrowConfig, NftTerms, CONFIG_SEED, ESCROW_PDA_SEED,
+    FEE_VAULT_SEED, VAULT_SEED,
+};
+
+/// The escrow of an initializer and one of their seeds
+pub fn escrow_address(initializer: &Pubkey, seed: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[ESCROW_PDA_SEED, initializer.as_ref(), &seed.to_le_bytes()],
+        &crate::id(),
+    )
+    .0
+}
+
+/// The token account holding the deposit of an escrow, there is none for a SOL deposit
+pub fn vault_address(escrow: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[VAULT_SEED, escrow.as_ref()], &crate::id()).0
+}
+
+/// The PDA of an escrow or basket, owning its vaults
+pub fn pda_address(escrow: &Pubkey, initializer: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[ESCROW_PDA_SEED, escrow.as_ref(), initializer.as_ref()],
+        &crate::id(),
+    )
+    .0
+}
+
+pub fn config_address() -> Pubkey {
+    Pubkey::find_program_address(&[CONFIG_SEED], &crate::id()).0
+}
+
+/// Where the protocol fees of a token mint are held, the config account collects SOL fees
+pub fn fee_vault_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[FEE_VAULT_SEED, mint.as_ref()], &crate::id()).0
+}
+
+/// The Metaplex metadata of a mint
+pub fn metadata_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"metadata", mpl_token_metadata::ID.as_ref(), mint.as_ref()],
+        &mpl_token_metadata::ID,
+    )
+    .0
+}
+
+/// Decodes an escrow account as fetched from the cluster
+pub fn decode_escrow(escrow: &Pubkey, account: &Account) -> Result<Escrow, ClientError> {
+    if account.owner != crate::id() {
+        return Err(ClientError::NotAnEscrow(*escrow));
+    }
+    Escrow::try_deserialize(&mut account.data.as_slice())
+        .ok()
+        .filter(|state| state.is_initialized && state.version == Escrow::VERSION)
+        .ok_or(ClientError::NotAnEscrow(*escrow))
+}
+
+/// The `getProgramAccounts` filters matching the open escrows, those of `initializer` only when given
+pub fn open_escrow_filters(initializer: Option<&Pubkey>) -> Vec<RpcFilterType> {
+    escrow_client::open_escrow_filters(
+        Escrow::LEN,
+        &Escrow::discriminator(),
+        Escrow::VERSION,
+        initializer,
+    )
+}
+
+// The token account of a side of the trade, left out when that side is SOL
+fn leg(mint: &Pubkey, account: &Pubkey) -> Option<Pubkey> {
+    if is_sol(mint) {
+        None
+    } else {
+        Some(*account)
+    }
+}
+
+// The metadata of an NFT side, only passed when its terms name a collection or creator
+fn metadata(terms: &Option<NftTerms>, mint: &Pubkey) -> Option<Pubkey> {
+    terms
+        .as_ref()
+        .filter(|terms| terms.collection.is_some() || terms.creator.is_some())
+        .map(|_| metadata_address(mint))
+}
+
+// Creates the associated token account of `wallet` for the mint unless it exists already
+fn create_associated_token_account_idempotent(
+    payer: &Pubkey,
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: associated_token::ID,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(
+                get_associated_token_address_with_program_id(wallet, mint, token_program),
+                false,
+            ),
+            AccountMeta::new_readonly(*wallet, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        // The `CreateIdempotent` instruction of the associated token program
+        data: vec![1],
+    }
+}
+
+pub struct EscrowClient {
+    rpc: RpcClient,
+}
+
+impl EscrowClient {
+    pub fn new(rpc: RpcClient) -> Self {
+        EscrowClient { rpc }
+    }
+
+    pub fn rpc(&self) -> &RpcClient {
+        &self.rpc
+    }
+
+    pub fn get_escrow(&self, escrow: &Pubkey) -> Result<Escrow, ClientError> {
+        let account = self.rpc.get_account(escrow)?;
+        decode_escrow(escrow, &account)
+    }
+
+    pub fn get_config(&self) -> Result<EscrowConfig, ClientError> {
+        let config = config_address();
+        let account = self.rpc.get_account(&config)?;
+        EscrowConfig::try_deserialize(&mut account.data.as_slice())
+            .map_err(|_| ClientError::NotTheConfig(config))
+    }
+
+    /// Every open escrow of the program, with its address
+    pub fn open_escrows(&self) -> Result<Vec<(Pubkey, Escrow)>, ClientError> {
+        self.find_escrows(open_escrow_filters(None))
+    }
+
+    /// The open escrows of one initializer, with their addresses
+    pub fn open_escrows_of(
+        &self,
+        initializer: &Pubkey,
+    ) -> Result<Vec<(Pubkey, Escrow)>, ClientError> {
+        self.find_escrows(open_escrow_filters(Some(initializer)))
+    }
+
+    fn find_escrows(
+        &self,
+        filters: Vec<RpcFilterType>,
+    ) -> Result<Vec<(Pubkey, Escrow)>, ClientError> {
+        program_accounts(&self.rpc, &crate::id(), filters)?
+            .into_iter()
+            .map(|(escrow, account)| decode_escrow(&escrow, &account).map(|state| (escrow, state)))
+            .collect()
+    }
+
+    /// The mint of a token account of either token program
+    fn token_account_mint(&self, account: &Pubkey) -> Result<Pubkey, ClientError> {
+        let data = self.rpc.get_account_data(account)?;
+        TokenAccount::try_deserialize(&mut data.as_slice())
+            .map(|token_account| token_account.mint)
+            .map_err(|_| ClientError::NotATokenAccount(*account))
+    }
+
+    /// Opens an escrow of `initializer` on the terms of `args`, under the seed they name.
+    /// `deposit_account` is left out for a SOL deposit, `token_to_receive_account` to be paid in SOL
+    /// or to take any NFT of the expected collection or creator
+    pub fn init_escrow_transaction(
+        &self,
+        initializer: &Keypair,
+        mint_a: &Pubkey,
+        deposit_account: Option<Pubkey>,
+        token_to_receive_account: Option<Pubkey>,
+        args: instruction::Initialize,
+    ) -> Result<Transaction, ClientError> {
+        let escrow = escrow_address(&initializer.pubkey(), args.seed);
+        let instruction = Instruction {
+            program_id: crate::id(),
+            accounts: accounts::Initialize {
+                initializer: initializer.pubkey(),
+                mint_a: *mint_a,
+                initializers_deposit_token_account: deposit_account,
+                token_to_receive_account,
+                escrow_account: escrow,
+                vault: leg(mint_a, &vault_address(&escrow)),
+                pda_account: pda_address(&escrow, &initializer.pubkey()),
+                token_program: token_program_of(&self.rpc, mint_a)?,
+                system_program: system_program::ID,
+                rent: sysvar::rent::id(),
+                config: config_address(),
+                deposit_metadata: metadata(&args.deposit_nft, mint_a),
+            }
+            .to_account_metas(None),
+            data: args.data(),
+        };
+        sign(&self.rpc, &[instruction], initializer)
+    }
+
+    /// Takes `amount_expected_by_taker` of the deposit of `escrow`, paying at most `max_pay_amount`
+    /// for it. The taker's account of a SOL side is left out, their wallet is used instead.
+    /// An NFT left unpinned at init is the one in `takers_sending_account`, it goes to the
+    /// associated token account of the initializer, created by the taker when missing
+    pub fn exchange_transaction(
+        &self,
+        taker: &Keypair,
+        escrow: &Pubkey,
+        takers_sending_account: &Pubkey,
+        takers_receiving_account: &Pubkey,
+        amount_expected_by_taker: u64,
+        max_pay_amount: u64,
+    ) -> Result<Transaction, ClientError> {
+        let state = self.get_escrow(escrow)?;
+        // Fee vaults are only needed while a fee is taken, otherwise the taker would pay their rent
+        let fee_taken = self.get_config()?.fee_basis_points > 0;
+        let fee_vault = |mint: &Pubkey| leg(mint, &fee_vault_address(mint)).filter(|_| fee_taken);
+        let mut instructions = Vec::new();
+        let (mint_b, token_program_b, initializers_receiving_account) = if state.expects_any_nft() {
+            let mint_b = self.token_account_mint(takers_sending_account)?;
+            let token_program_b = token_program_of(&self.rpc, &mint_b)?;
+            instructions.push(create_associated_token_account_idempotent(
+                &taker.pubkey(),
+                &state.initializer_pubkey,
+                &mint_b,
+                &token_program_b,
+            ));
+            let account = get_associated_token_address_with_program_id(
+                &state.initializer_pubkey,
+                &mint_b,
+                &token_program_b,
+            );
+            (mint_b, token_program_b, account)
+        } else {
+            (
+                state.mint_b,
+                token_program_of(&self.rpc, &state.mint_b)?,
+                state.initializer_token_to_receive_account_pubkey,
+            )
+        };
+        instructions.push(Instruction {
+            program_id: crate::id(),
+            accounts: accounts::Exchange {
+                taker: taker.pubkey(),
+                takers_sending_token_account: leg(&mint_b, takers_sending_account),
+                takers_token_to_receive_account: leg(&state.mint_a, takers_receiving_account),
+                pdas_temp_token_account: leg(&state.mint_a, &state.temp_token_account_pubkey),
+                initializers_main_account: state.initializer_pubkey,
+                initializers_token_to_receive_account: leg(
+                    &mint_b,
+                    &initializers_receiving_account,
+                ),
+                escrow_account: *escrow,
+                token_program: token_program_of(&self.rpc, &state.mint_a)?,
+                pda_account: pda_address(escrow, &state.initializer_pubkey),
+                mint_a: state.mint_a,
+                mint_b,
+                token_program_b,
+                system_program: system_program::ID,
+                config: config_address(),
+                fee_vault_a: fee_vault(&state.mint_a),
+                fee_vault_b: fee_vault(&mint_b),
+                expected_metadata: metadata(&state.expected_nft, &mint_b),
+            }
+            .to_account_metas(None),
+            data: instruction::Exchange {
+                amount_expected_by_taker,
+                max_pay_amount,
+            }
+            .data(),
+        });
+        sign(&self.rpc, &instructions, taker)
+    }
+
+    /// Cancels `escrow`, refunding what is left of the deposit to `refund_account`.
+    /// It is left out for a SOL deposit, the lamports go back to the initializer
+    pub fn cancel_transaction(
+        &self,
+        initializer: &Keypair,
+        escrow: &Pubkey,
+        refund_account: &Pubkey,
+    ) -> Result<Transaction, ClientError> {
+        let state = self.get_escrow(escrow)?;
+        let instruction = Instruction {
+            program_id: crate::id(),
+            accounts: accounts::Cancel {
+                initializer: initializer.pubkey(),
+                pdas_temp_token_account: leg(&state.mint_a, &state.temp_token_account_pubkey),
+                initializers_refund_token_account: leg(&state.mint_a, refund_account),
+                escrow_account: *escrow,
+                token_program: token_program_of(&self.rpc, &state.mint_a)?,
+                pda_account: pda_address(escrow, &state.initializer_pubkey),
+                mint_a: state.mint_a,
+            }
+            .to_account_metas(None),
+            data: instruction::CancelEscrow {}.data(),
+        };
+        sign(&self.rpc, &[instruction], initializer)
+    }
+
+    /// Sends a signed transaction and waits for it to be confirmed
+    pub fn send(&self, transaction: &Transaction) -> Result<Signature, ClientError> {
+        send(&self.rpc, transaction)
+    }
+}
@@ -1,177 +1,1069 @@
+// The CPI builders `#[program]` generates next to the program module repeat the arguments of the
+// instructions, and take no attributes from them
+#![cfg_attr(feature = "cpi", allow(clippy::too_many_arguments))]
+
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, CloseAccount, SetAuthority, TokenAccount, Transfer};
+use anchor_lang::solana_program::{bpf_loader_upgradeable, program::invoke};
+use anchor_lang::system_program::{self, CreateAccount, Transfer};
+use anchor_spl::metadata::MetadataAccount;
+use anchor_spl::token::spl_token::native_mint;
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{
+        transfer_fee::{TransferFeeAmount, TransferFeeConfig},
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+};
+use anchor_spl::token_interface::{
+    self, CloseAccount, InitializeAccount3, Mint, TokenAccount, TokenInterface, TransferChecked,
+};
+
+#[cfg(feature = "client")]
+pub mod client;
 
 declare_id!("ECh7FQHy1hDxkiYjPVi8tYhmZ2oHE1zJqsyxbP4vS3nd");
 
+const ESCROW_PDA_SEED: &[u8] = b"escrow";
+const VAULT_SEED: &[u8] = b"vault";
+const CONFIG_SEED: &[u8] = b"config";
+const FEE_VAULT_SEED: &[u8] = b"fee_vault";
+const BASKET_SEED: &[u8] = b"basket";
+const BASKET_VAULT_SEED: &[u8] = b"basket_vault";
+
+// Remaining accounts each basket leg takes, see `initialize_basket`
+const INIT_DEPOSIT_LEG_ACCOUNTS: usize = 4;
+const INIT_EXPECTED_LEG_ACCOUNTS: usize = 1;
+const EXCHANGE_LEG_ACCOUNTS: usize = 5;
+const CANCEL_LEG_ACCOUNTS: usize = 4;
+
 #[program]
 pub mod solana_escrow_anchor {
     use super::*;
-    use spl_token::instruction::AuthorityType;
 
-    const ESCROW_PDA_SEED: &[u8] = b"escrow";
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        seed: u64,
+        amount: u64,
+        deposit_amount: u64,
+        unlock_time: Option<u64>,
+        time_out: Option<u64>,
+        allowed_taker: Option<Pubkey>,
+        deposit_nft: Option<NftTerms>,
+        expected_nft: Option<NftTerms>,
+    ) -> Result<()> {
+        // Either side may be SOL, marked by the wrapped SOL mint. A SOL deposit is held as lamports
+        // by the escrow account itself, so there is no deposit token account and no vault
+        let sol_deposit = is_sol(&ctx.accounts.mint_a.key());
+        if sol_deposit && ctx.accounts.vault.is_some() {
+            return Err(ErrorCode::SolLegMismatch.into());
+        }
+        // An NFT side is traded whole. The deposited NFT is checked now. The expected one is pinned
+        // by the account it is received into, or left open to any NFT of a collection or creator
+        if let Some(terms) = &deposit_nft {
+            if deposit_amount != 1 {
+                return Err(ErrorCode::NotAnNft.into());
+            }
+            check_nft(&ctx.accounts.mint_a, terms, &ctx.accounts.deposit_metadata)?;
+        }
+        if let Some(terms) = &expected_nft {
+            if amount != 1 {
+                return Err(ErrorCode::NotAnNft.into());
+            }
+            if ctx.accounts.token_to_receive_account.is_none()
+                && terms.collection.is_none()
+                && terms.creator.is_none()
+            {
+                return Err(ErrorCode::UnpinnedNft.into());
+            }
+        }
+
+        // Without a token account to receive into, the initializer is paid in SOL
+        let sol_price = ctx.accounts.token_to_receive_account.is_none() && expected_nft.is_none();
+        if sol_deposit && sol_price {
+            return Err(ErrorCode::MintMismatch.into());
+        }
+
+        let deposited_amount = if sol_deposit {
+            system_program::transfer(ctx.accounts.deposit_sol_context(), deposit_amount)?;
+            deposit_amount
+        } else {
+            // The vault is created by the program and owned by the PDA, so the deposit goes straight in
+            let decimals = ctx.accounts.mint_a.decimals;
+            token_interface::transfer_checked(
+                ctx.accounts.deposit_context()?,
+                deposit_amount,
+                decimals,
+            )?;
+
+            // Net of any transfer fee withheld by mint A
+            let vault = ctx
+                .accounts
+                .vault
+                .as_mut()
+                .ok_or(ErrorCode::SolLegMismatch)?;
+            vault.reload()?;
+            vault.amount
+        };
 
-    pub fn initialize(ctx: Context<Initialize>, amount: u64) -> Result<()> {
         // Store data in escrow account
+        let escrow_key = ctx.accounts.escrow_account.key();
         let escrow_account = &mut ctx.accounts.escrow_account;
         escrow_account.is_initialized = true;
+        escrow_account.version = Escrow::VERSION;
         escrow_account.initializer_pubkey = *ctx.accounts.initializer.to_account_info().key;
-        escrow_account.temp_token_account_pubkey =
-            *ctx.accounts.temp_token_account.to_account_info().key;
-        escrow_account.initializer_token_to_receive_account_pubkey =
-            *ctx.accounts.token_to_receive_account.to_account_info().key;
+        escrow_account.temp_token_account_pubkey = match &ctx.accounts.vault {
+            Some(vault) => vault.key(),
+            None => escrow_key,
+        };
+        match &ctx.accounts.token_to_receive_account {
+            Some(token_to_receive_account) => {
+                escrow_account.initializer_token_to_receive_account_pubkey =
+                    token_to_receive_account.key();
+                escrow_account.mint_b = token_to_receive_account.mint;
+            }
+            // The initializer's wallet, receiving SOL or owning the account the NFT goes to
+            None => {
+                escrow_account.initializer_token_to_receive_account_pubkey =
+                    ctx.accounts.initializer.key();
+                escrow_account.mint_b = if sol_price {
+                    native_mint::ID
+                } else {
+                    Pubkey::default()
+                };
+            }
+        }
+        escrow_account.mint_a = ctx.accounts.mint_a.key();
+        escrow_account.deposit_amount = deposited_amount;
         escrow_account.expected_amount = amount;
+        escrow_account.remaining_amount = deposited_amount;
+        escrow_account.remaining_expected_amount = amount;
+        escrow_account.allowed_taker = allowed_taker;
+        escrow_account.pda_bump = ctx.bumps.pda_account;
+        escrow_account.seed = seed;
+        escrow_account.deposit_nft = deposit_nft;
+        escrow_account.expected_nft = expected_nft;
 
         // Here is a features to make program better -
         // we can set up tade unlock time and timeout period after which taking trade is impossible
         let clock = Clock::get()?;
-        let slot = clock.slot;
-        let time_out = slot + 100;
-        let unlock_time = time_out + 1000;
+        escrow_account.set_time_window(clock.slot, unlock_time, time_out)?;
 
-        escrow_account.time_out = time_out;
-        escrow_account.unlock_time = unlock_time;
-
-        // Create PDA, which will own the temp token account
-        let (pda, _bump_seed) = Pubkey::find_program_address(&[ESCROW_PDA_SEED], ctx.program_id);
-        token::set_authority(ctx.accounts.into(), AuthorityType::AccountOwner, Some(pda))?;
+        emit!(EscrowCreated {
+            escrow: escrow_key,
+            initializer: escrow_account.initializer_pubkey,
+            mint_a: escrow_account.mint_a,
+            mint_b: escrow_account.mint_b,
+            deposit_amount: escrow_account.deposit_amount,
+            expected_amount: escrow_account.expected_amount,
+            unlock_time: escrow_account.unlock_time,
+            time_out: escrow_account.time_out,
+            allowed_taker: escrow_account.allowed_taker,
+            slot: clock.slot,
+        });
 
         Ok(())
     }
 
-    pub fn exchange(ctx: Context<Exchange>, amount_expected_by_taker: u64) -> Result<()> {
+    // The taker names both what they take and the most they send for it, the price plus any transfer
+    // fee of mint B, so terms changed before the exchange lands can't charge them more
+    pub fn exchange(
+        ctx: Context<Exchange>,
+        amount_expected_by_taker: u64,
+        max_pay_amount: u64,
+    ) -> Result<()> {
         let escrow_account = &ctx.accounts.escrow_account;
 
+        // Check if trade is unlocked and not yet timed out
         let clock = Clock::get()?;
-        let slot = clock.slot;
+        escrow_account.check_time_window(clock.slot)?;
 
-        // Check if trade is allowed
-        if slot < ctx.accounts.escrow_account.unlock_time {
-            return Err(ErrorCode::EscrowTradeLocked.into());
-        }
-        // Check for timeout
-        if slot > ctx.accounts.escrow_account.time_out {
-            return Err(ErrorCode::EscrowTimedOut.into());
+        if let Some(terms) = &escrow_account.expected_nft {
+            check_nft(&ctx.accounts.mint_b, terms, &ctx.accounts.expected_metadata)?;
         }
 
-        // Ensure that expected and deposited amount match
-        if amount_expected_by_taker != ctx.accounts.pdas_temp_token_account.amount {
+        // The taker may fill only a part of the offer, but never more than what is left
+        if amount_expected_by_taker == 0
+            || amount_expected_by_taker > escrow_account.remaining_amount
+        {
             return Err(ErrorCode::ExpectedAmountMismatch.into());
         }
+        let amount_to_pay = escrow_account.amount_to_pay(amount_expected_by_taker)?;
+        let sol_deposit = is_sol(&escrow_account.mint_a);
+        let sol_price = is_sol(&escrow_account.mint_b);
 
-        // Get PDA
-        let (_pda, bump_seed) = Pubkey::find_program_address(&[ESCROW_PDA_SEED], ctx.program_id);
-        let seeds = &[&ESCROW_PDA_SEED[..], &[bump_seed]];
+        // The protocol fee comes out of both legs
+        let fee_a = ctx.accounts.config.protocol_fee(amount_expected_by_taker);
+        let fee_b = ctx.accounts.config.protocol_fee(amount_to_pay);
+        let amount_to_initializer = amount_to_pay - fee_b;
+        let amount_to_taker = amount_expected_by_taker - fee_a;
 
-        // Transfer tokens from taker to initializer
-        token::transfer(
-            ctx.accounts.into_transfer_to_initializer_context(),
-            escrow_account.expected_amount,
-        )?;
+        // The initializer must receive the whole price, so the taker also covers the transfer fee of the mint
+        let transfer_fee = if sol_price {
+            0
+        } else {
+            inverse_transfer_fee(
+                &ctx.accounts.mint_b.to_account_info(),
+                amount_to_initializer,
+            )?
+        };
+        if amount_to_pay
+            .checked_add(transfer_fee)
+            .ok_or(ErrorCode::AmountOverflow)?
+            > max_pay_amount
+        {
+            return Err(ErrorCode::MaxPayAmountExceeded.into());
+        }
 
-        // Transfer tokens from initializer to taker
-        token::transfer(
-            ctx.accounts
-                .into_transfer_to_taker_context()
-                .with_signer(&[&seeds[..]]),
-            amount_expected_by_taker,
-        )?;
+        // The vault authority is the PDA of this escrow, its bump was stored at init
+        let escrow_key = escrow_account.key();
+        let initializer_key = escrow_account.initializer_pubkey;
+        let pda_bump = [escrow_account.pda_bump];
+        let seeds: &[&[u8]] = &[
+            ESCROW_PDA_SEED,
+            escrow_key.as_ref(),
+            initializer_key.as_ref(),
+            &pda_bump,
+        ];
+
+        if sol_price {
+            // A SOL price goes straight to the initializer's wallet, SOL fees to the config account
+            system_program::transfer(ctx.accounts.pay_sol_context(), amount_to_initializer)?;
+            if fee_b > 0 {
+                system_program::transfer(ctx.accounts.pay_sol_fee_context(), fee_b)?;
+            }
+        } else {
+            // Transfer tokens from taker to initializer
+            token_interface::transfer_checked(
+                ctx.accounts.transfer_to_initializer_context()?,
+                amount_to_initializer
+                    .checked_add(transfer_fee)
+                    .ok_or(ErrorCode::AmountOverflow)?,
+                ctx.accounts.mint_b.decimals,
+            )?;
+            if fee_b > 0 {
+                token_interface::transfer_checked(
+                    ctx.accounts.pay_fee_context()?,
+                    fee_b,
+                    ctx.accounts.mint_b.decimals,
+                )?;
+            }
+        }
+
+        if sol_deposit {
+            // The escrow account is owned by the program, so its lamports are moved directly
+            move_lamports(
+                &ctx.accounts.escrow_account.to_account_info(),
+                &ctx.accounts.taker.to_account_info(),
+                amount_to_taker,
+            )?;
+            move_lamports(
+                &ctx.accounts.escrow_account.to_account_info(),
+                &ctx.accounts.config.to_account_info(),
+                fee_a,
+            )?;
+        } else {
+            // Transfer tokens from initializer to taker
+            token_interface::transfer_checked(
+                ctx.accounts
+                    .transfer_to_taker_context()?
+                    .with_signer(&[seeds]),
+                amount_to_taker,
+                ctx.accounts.mint_a.decimals,
+            )?;
+            if fee_a > 0 {
+                token_interface::transfer_checked(
+                    ctx.accounts
+                        .transfer_fee_to_vault_context()?
+                        .with_signer(&[seeds]),
+                    fee_a,
+                    ctx.accounts.mint_a.decimals,
+                )?;
+            }
+        }
+
+        let escrow_account = &mut ctx.accounts.escrow_account;
+        escrow_account.remaining_amount -= amount_expected_by_taker;
+        escrow_account.remaining_expected_amount = escrow_account
+            .remaining_expected_amount
+            .checked_sub(amount_to_pay)
+            .ok_or(ErrorCode::AmountOverflow)?;
+        emit!(EscrowExchanged {
+            escrow: escrow_key,
+            initializer: escrow_account.initializer_pubkey,
+            taker: ctx.accounts.taker.key(),
+            mint_a: escrow_account.mint_a,
+            mint_b: ctx.accounts.mint_b.key(),
+            taken_amount: amount_expected_by_taker,
+            paid_amount: amount_to_pay,
+            remaining_amount: escrow_account.remaining_amount,
+            slot: clock.slot,
+        });
+
+        // Partially filled escrow stays open for the next taker
+        if escrow_account.remaining_amount > 0 {
+            return Ok(());
+        }
 
         // Close temp token account
-        token::close_account(
-            ctx.accounts
-                .into_close_temp_token_context()
-                .with_signer(&[&seeds[..]]),
-        )?;
+        if !sol_deposit {
+            harvest_withheld_fees(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.mint_a.to_account_info(),
+                &token_leg(&ctx.accounts.pdas_temp_token_account)?.to_account_info(),
+            )?;
+            token_interface::close_account(
+                ctx.accounts
+                    .close_temp_token_context()?
+                    .with_signer(&[seeds]),
+            )?;
+        }
+
+        // Close escrow account
+        ctx.accounts
+            .escrow_account
+            .close(ctx.accounts.initializers_main_account.clone())?;
 
         Ok(())
     }
 
     // Additional feature to reset time variables to initials value
-    // So we simple give the taker abbility to trade if there is a timeout/lock
+    // So we simple give the taker abbility to trade if there is a lock, not once it timed out
     pub fn reset_time_lock(ctx: Context<Reset>) -> Result<()> {
         let escrow_account = &mut ctx.accounts.escrow_account;
 
         let clock = Clock::get()?;
-        let slot = clock.slot;
-        let time_out = slot + 100;
-        let unlock_time = time_out + 1000;
+        // Like an update, a timed out escrow can only be cancelled
+        if clock.slot > escrow_account.time_out {
+            return Err(ErrorCode::EscrowTimedOut.into());
+        }
+        escrow_account.set_time_window(clock.slot, None, None)?;
+
+        emit!(EscrowTimeReset {
+            escrow: escrow_account.key(),
+            initializer: escrow_account.initializer_pubkey,
+            unlock_time: escrow_account.unlock_time,
+            time_out: escrow_account.time_out,
+            slot: clock.slot,
+        });
+
+        Ok(())
+    }
+
+    // Changes the terms of an open escrow. What is left of the deposit and its price then become
+    // the deposit and expected amounts of the escrow, announced to takers with an `EscrowUpdated` event.
+    // `allowed_taker` is left unchanged when unset, `Some(None)` opens the trade to anyone
+    pub fn update_escrow(
+        ctx: Context<UpdateEscrow>,
+        expected_amount: Option<u64>,
+        time_out: Option<u64>,
+        allowed_taker: Option<Option<Pubkey>>,
+        top_up_amount: u64,
+        withdraw_amount: u64,
+    ) -> Result<()> {
+        // A timed out escrow can only be cancelled, not revived with new terms
+        let slot = Clock::get()?.slot;
+        if slot > ctx.accounts.escrow_account.time_out {
+            return Err(ErrorCode::EscrowTimedOut.into());
+        }
+
+        let sol_deposit = is_sol(&ctx.accounts.escrow_account.mint_a);
+        // An NFT side stays a single whole token
+        if (ctx.accounts.escrow_account.deposit_nft.is_some() && top_up_amount > 0)
+            || (ctx.accounts.escrow_account.expected_nft.is_some()
+                && matches!(expected_amount, Some(amount) if amount != 1))
+        {
+            return Err(ErrorCode::NotAnNft.into());
+        }
+
+        if top_up_amount > 0 {
+            let topped_up_amount = if sol_deposit {
+                system_program::transfer(ctx.accounts.top_up_sol_context(), top_up_amount)?;
+                top_up_amount
+            } else {
+                // Net of any transfer fee, like the deposit
+                let vault_amount = token_leg(&ctx.accounts.pdas_temp_token_account)?.amount;
+                token_interface::transfer_checked(
+                    ctx.accounts.top_up_context()?,
+                    top_up_amount,
+                    ctx.accounts.mint_a.decimals,
+                )?;
+                let vault = ctx
+                    .accounts
+                    .pdas_temp_token_account
+                    .as_mut()
+                    .ok_or(ErrorCode::SolLegMismatch)?;
+                vault.reload()?;
+                vault.amount - vault_amount
+            };
+            let escrow_account = &mut ctx.accounts.escrow_account;
+            escrow_account.remaining_amount = escrow_account
+                .remaining_amount
+                .checked_add(topped_up_amount)
+                .ok_or(ErrorCode::AmountOverflow)?;
+        }
+
+        if withdraw_amount > 0 {
+            // Withdrawing everything is a cancel
+            if withdraw_amount >= ctx.accounts.escrow_account.remaining_amount {
+                return Err(ErrorCode::InvalidUpdate.into());
+            }
+            if sol_deposit {
+                move_lamports(
+                    &ctx.accounts.escrow_account.to_account_info(),
+                    &ctx.accounts.initializer.to_account_info(),
+                    withdraw_amount,
+                )?;
+            } else {
+                let escrow_key = ctx.accounts.escrow_account.key();
+                let seeds: &[&[u8]] = &[
+                    ESCROW_PDA_SEED,
+                    escrow_key.as_ref(),
+                    ctx.accounts.escrow_account.initializer_pubkey.as_ref(),
+                    &[ctx.accounts.escrow_account.pda_bump],
+                ];
+                token_interface::transfer_checked(
+                    ctx.accounts.withdraw_context()?.with_signer(&[seeds]),
+                    withdraw_amount,
+                    ctx.accounts.mint_a.decimals,
+                )?;
+            }
+            ctx.accounts.escrow_account.remaining_amount -= withdraw_amount;
+        }
+
+        let escrow_key = ctx.accounts.escrow_account.key();
+        let escrow_account = &mut ctx.accounts.escrow_account;
+        if let Some(expected_amount) = expected_amount {
+            if expected_amount == 0 {
+                return Err(ErrorCode::InvalidUpdate.into());
+            }
+            escrow_account.remaining_expected_amount = expected_amount;
+        }
+        if let Some(time_out) = time_out {
+            let (_, time_out) =
+                time_window(slot, Some(escrow_account.unlock_time), Some(time_out))?;
+            escrow_account.time_out = time_out;
+        }
+        if let Some(allowed_taker) = allowed_taker {
+            escrow_account.allowed_taker = allowed_taker;
+        }
 
-        escrow_account.time_out = time_out;
-        escrow_account.unlock_time = unlock_time;
+        // What is left is offered under the new terms, as if the escrow was opened with them
+        escrow_account.deposit_amount = escrow_account.remaining_amount;
+        escrow_account.expected_amount = escrow_account.remaining_expected_amount;
+        emit!(EscrowUpdated {
+            escrow: escrow_key,
+            initializer: escrow_account.initializer_pubkey,
+            mint_a: escrow_account.mint_a,
+            mint_b: escrow_account.mint_b,
+            deposit_amount: escrow_account.deposit_amount,
+            expected_amount: escrow_account.expected_amount,
+            time_out: escrow_account.time_out,
+            allowed_taker: escrow_account.allowed_taker,
+            slot,
+        });
 
         Ok(())
     }
 
     pub fn cancel_escrow(ctx: Context<Cancel>) -> Result<()> {
-        //let _escrow_account = &ctx.accounts.escrow_account;
+        let escrow_account = &ctx.accounts.escrow_account;
+        let sol_deposit = is_sol(&escrow_account.mint_a);
+        emit!(EscrowCancelled {
+            escrow: escrow_account.key(),
+            initializer: escrow_account.initializer_pubkey,
+            mint_a: escrow_account.mint_a,
+            refunded_amount: if sol_deposit {
+                escrow_account.remaining_amount
+            } else {
+                token_leg(&ctx.accounts.pdas_temp_token_account)?.amount
+            },
+            slot: Clock::get()?.slot,
+        });
 
-        // Get PDA
-        let (_pda, bump_seed) = Pubkey::find_program_address(&[ESCROW_PDA_SEED], ctx.program_id);
-        let seeds = &[&ESCROW_PDA_SEED[..], &[bump_seed]];
+        // A SOL deposit is returned with the escrow lamports when the escrow account is closed
+        if sol_deposit {
+            return Ok(());
+        }
+
+        let escrow_key = escrow_account.key();
+        let seeds: &[&[u8]] = &[
+            ESCROW_PDA_SEED,
+            escrow_key.as_ref(),
+            escrow_account.initializer_pubkey.as_ref(),
+            &[escrow_account.pda_bump],
+        ];
 
-        // Transfet tokens to initializer
-        token::transfer(
+        // Refund the whole deposit to initializer
+        let vault = token_leg(&ctx.accounts.pdas_temp_token_account)?;
+        token_interface::transfer_checked(
             ctx.accounts
-                .into_transfer_to_initializer_context()
-                .with_signer(&[&seeds[..]]),
-            ctx.accounts.escrow_account.expected_amount,
+                .transfer_to_initializer_context()?
+                .with_signer(&[seeds]),
+            vault.amount,
+            ctx.accounts.mint_a.decimals,
         )?;
 
         // Close temp token account
-        token::close_account(
+        harvest_withheld_fees(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.mint_a.to_account_info(),
+            &vault.to_account_info(),
+        )?;
+        token_interface::close_account(
             ctx.accounts
-                .into_close_temp_token_context()
-                .with_signer(&[&seeds[..]]),
+                .close_temp_token_context()?
+                .with_signer(&[seeds]),
         )?;
 
         Ok(())
     }
 
+    // There is a single config per program. Anyone could create it between the deployment and
+    // the admin, so only the upgrade authority of the program may, becoming its admin
+    pub fn init_config(ctx: Context<InitConfig>, fee_basis_points: u16) -> Result<()> {
+        if fee_basis_points > EscrowConfig::MAX_FEE_BASIS_POINTS {
+            return Err(ErrorCode::FeeTooHigh.into());
+        }
+
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.fee_recipient = ctx.accounts.fee_recipient.key();
+        config.fee_basis_points = fee_basis_points;
+        config.paused = false;
+        config.bump = ctx.bumps.config;
+
+        Ok(())
+    }
+
+    pub fn set_fee(ctx: Context<UpdateConfig>, fee_basis_points: u16) -> Result<()> {
+        if fee_basis_points > EscrowConfig::MAX_FEE_BASIS_POINTS {
+            return Err(ErrorCode::FeeTooHigh.into());
+        }
+        ctx.accounts.config.fee_basis_points = fee_basis_points;
+
+        Ok(())
+    }
+
+    pub fn set_fee_recipient(ctx: Context<SetFeeRecipient>) -> Result<()> {
+        ctx.accounts.config.fee_recipient = ctx.accounts.fee_recipient.key();
+
+        Ok(())
+    }
+
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: u64) -> Result<()> {
+        if is_sol(&ctx.accounts.mint.key()) {
+            // The config account has to stay rent exempt, only what is above that are fees
+            let config = ctx.accounts.config.to_account_info();
+            let rent_exempt_balance = Rent::get()?.minimum_balance(EscrowConfig::LEN);
+            if amount > config.lamports().saturating_sub(rent_exempt_balance) {
+                return Err(ProgramError::InsufficientFunds.into());
+            }
+            return move_lamports(&config, &ctx.accounts.fee_recipient, amount);
+        }
+
+        // Fee vaults are owned by the config PDA, so the token program only lets it sign
+        let seeds = &[CONFIG_SEED, &[ctx.accounts.config.bump]];
+        token_interface::transfer_checked(
+            ctx.accounts.withdraw_context()?.with_signer(&[&seeds[..]]),
+            amount,
+            ctx.accounts.mint.decimals,
+        )
+    }
+
+    // The new admin signs as well, so the config can't be handed to a mistyped key
+    pub fn set_admin(ctx: Context<SetAdmin>) -> Result<()> {
+        ctx.accounts.config.admin = ctx.accounts.new_admin.key();
+
+        Ok(())
+    }
+
+    // While paused no escrow can be opened, taken or have its time lock reset,
+    // only cancels and the admin instructions go through
+    pub fn set_paused(ctx: Context<UpdateConfig>, paused: bool) -> Result<()> {
+        ctx.accounts.config.paused = paused;
+
+        Ok(())
+    }
+
+    // A basket trades several tokens for several others at once, e.g. 3 tokens for 2.
+    // The token accounts of its legs come as remaining accounts, for each deposited leg:
+    //   the mint, the token program owning it, the initializer's token account to deposit from
+    //   and the vault, the PDA derived from `[b"basket_vault", basket, mint]`,
+    // then for each expected leg the initializer's token account to receive it
+    pub fn initialize_basket<'info>(
+        ctx: Context<'_, '_, '_, 'info, InitializeBasket<'info>>,
+        seed: u64,
+        deposit_amounts: Vec<u64>,
+        expected_amounts: Vec<u64>,
+        unlock_time: Option<u64>,
+        time_out: Option<u64>,
+        allowed_taker: Option<Pubkey>,
+    ) -> Result<()> {
+        if deposit_amounts.is_empty()
+            || deposit_amounts.len() > BasketEscrow::MAX_LEGS
+            || expected_amounts.is_empty()
+            || expected_amounts.len() > BasketEscrow::MAX_LEGS
+        {
+            return Err(ErrorCode::InvalidBasket.into());
+        }
+        let (deposit_accounts, expected_accounts) = split_basket_accounts(
+            ctx.remaining_accounts,
+            deposit_amounts.len() * INIT_DEPOSIT_LEG_ACCOUNTS,
+            expected_amounts.len() * INIT_EXPECTED_LEG_ACCOUNTS,
+        )?;
+
+        let basket_key = ctx.accounts.basket.key();
+        let initializer = ctx.accounts.initializer.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let mut deposits: Vec<BasketLeg> = Vec::with_capacity(deposit_amounts.len());
+        for (accounts, amount) in deposit_accounts
+            .chunks(INIT_DEPOSIT_LEG_ACCOUNTS)
+            .zip(deposit_amounts)
+        {
+            let (mint, token_program, from, vault) =
+                (&accounts[0], &accounts[1], &accounts[2], &accounts[3]);
+            check_token_program(token_program, mint)?;
+            // Baskets hold tokens only, and each mint once
+            if amount == 0 || is_sol(mint.key) || deposits.iter().any(|leg| leg.mint == *mint.key) {
+                return Err(ErrorCode::InvalidBasket.into());
+            }
+
+            // The vault is created by the program and owned by the PDA, one per mint of the basket
+            let (vault_address, vault_bump) = Pubkey::find_program_address(
+                &[BASKET_VAULT_SEED, basket_key.as_ref(), mint.key.as_ref()],
+                ctx.program_id,
+            );
+            if vault_address != *vault.key {
+                return Err(ProgramError::InvalidSeeds.into());
+            }
+            create_token_account(
+                &initializer,
+                vault,
+                &[
+                    BASKET_VAULT_SEED,
+                    basket_key.as_ref(),
+                    mint.key.as_ref(),
+                    &[vault_bump],
+                ],
+                mint,
+                &ctx.accounts.pda_account,
+                token_program,
+                &system_program,
+            )?;
+
+            token_interface::transfer_checked(
+                CpiContext::new(
+                    token_program.clone(),
+                    TransferChecked {
+                        from: from.clone(),
+                        mint: mint.clone(),
+                        to: vault.clone(),
+                        authority: initializer.clone(),
+                    },
+                ),
+                amount,
+                mint_decimals(mint)?,
+            )?;
+
+            // Net of any transfer fee, like a single deposit
+            deposits.push(BasketLeg {
+                mint: mint.key(),
+                amount: unpack_token_account(vault)?.amount,
+                token_account: vault.key(),
+            });
+        }
+
+        let mut expected: Vec<BasketLeg> = Vec::with_capacity(expected_amounts.len());
+        for (accounts, amount) in expected_accounts
+            .chunks(INIT_EXPECTED_LEG_ACCOUNTS)
+            .zip(expected_amounts)
+        {
+            let token_to_receive_account = &accounts[0];
+            let mint = unpack_token_account(token_to_receive_account)?.mint;
+            // There is no point in trading a token for the same token
+            if deposits.iter().any(|leg| leg.mint == mint) {
+                return Err(ErrorCode::MintMismatch.into());
+            }
+            if amount == 0 || is_sol(&mint) || expected.iter().any(|leg| leg.mint == mint) {
+                return Err(ErrorCode::InvalidBasket.into());
+            }
+            expected.push(BasketLeg {
+                mint,
+                amount,
+                token_account: token_to_receive_account.key(),
+            });
+        }
+
+        let basket = &mut ctx.accounts.basket;
+        basket.initializer_pubkey = initializer.key();
+        basket.deposits = deposits;
+        basket.expected = expected;
+        basket.allowed_taker = allowed_taker;
+        basket.pda_bump = ctx.bumps.pda_account;
+        basket.seed = seed;
+        let (unlock_time, time_out) = time_window(Clock::get()?.slot, unlock_time, time_out)?;
+        basket.unlock_time = unlock_time;
+        basket.time_out = time_out;
+
+        Ok(())
+    }
+
+    // The taker settles the whole basket at once. The remaining accounts are, for each deposited leg:
+    //   the mint, its token program, the vault, the taker's token account to receive it and the fee vault,
+    // then for each expected leg:
+    //   the mint, its token program, the taker's token account to pay from, the initializer's token
+    //   account to receive it and the fee vault.
+    // Like a single escrow, the protocol fee comes out of every leg
+    pub fn exchange_basket<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExchangeBasket<'info>>,
+    ) -> Result<()> {
+        let basket = &ctx.accounts.basket;
+        check_time_window(Clock::get()?.slot, basket.unlock_time, basket.time_out)?;
+        let (deposit_accounts, expected_accounts) = split_basket_accounts(
+            ctx.remaining_accounts,
+            basket.deposits.len() * EXCHANGE_LEG_ACCOUNTS,
+            basket.expected.len() * EXCHANGE_LEG_ACCOUNTS,
+        )?;
+
+        let config = &ctx.accounts.config;
+        let taker = ctx.accounts.taker.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let basket_key = basket.key();
+        let seeds: &[&[u8]] = &[
+            ESCROW_PDA_SEED,
+            basket_key.as_ref(),
+            basket.initializer_pubkey.as_ref(),
+            &[basket.pda_bump],
+        ];
+
+        for (accounts, leg) in expected_accounts
+            .chunks(EXCHANGE_LEG_ACCOUNTS)
+            .zip(&basket.expected)
+        {
+            let (mint, token_program, from, to, fee_vault) = (
+                &accounts[0],
+                &accounts[1],
+                &accounts[2],
+                &accounts[3],
+                &accounts[4],
+            );
+            check_basket_leg(leg, mint, token_program, to)?;
+            if unpack_token_account(from)?.mint != leg.mint {
+                return Err(ErrorCode::MintMismatch.into());
+            }
+
+            // The initializer must receive the whole price less the protocol fee,
+            // so the taker also covers the transfer fee of the mint
+            let fee = config.protocol_fee(leg.amount);
+            let amount_to_initializer = leg.amount - fee;
+            let transfer_fee = inverse_transfer_fee(mint, amount_to_initializer)?;
+            let decimals = mint_decimals(mint)?;
+            token_interface::transfer_checked(
+                CpiContext::new(
+                    token_program.clone(),
+                    TransferChecked {
+                        from: from.clone(),
+                        mint: mint.clone(),
+                        to: to.clone(),
+                        authority: taker.clone(),
+                    },
+                ),
+                amount_to_initializer
+                    .checked_add(transfer_fee)
+                    .ok_or(ErrorCode::AmountOverflow)?,
+                decimals,
+            )?;
+            if fee > 0 {
+                prepare_fee_vault(
+                    &taker,
+                    fee_vault,
+                    mint,
+                    &config.to_account_info(),
+                    token_program,
+                    &system_program,
+                    ctx.program_id,
+                )?;
+                token_interface::transfer_checked(
+                    CpiContext::new(
+                        token_program.clone(),
+                        TransferChecked {
+                            from: from.clone(),
+                            mint: mint.clone(),
+                            to: fee_vault.clone(),
+                            authority: taker.clone(),
+                        },
+                    ),
+                    fee,
+                    decimals,
+                )?;
+            }
+        }
+
+        for (accounts, leg) in deposit_accounts
+            .chunks(EXCHANGE_LEG_ACCOUNTS)
+            .zip(&basket.deposits)
+        {
+            let (mint, token_program, vault, to, fee_vault) = (
+                &accounts[0],
+                &accounts[1],
+                &accounts[2],
+                &accounts[3],
+                &accounts[4],
+            );
+            check_basket_leg(leg, mint, token_program, vault)?;
+            if unpack_token_account(to)?.mint != leg.mint {
+                return Err(ErrorCode::MintMismatch.into());
+            }
+
+            let fee = config.protocol_fee(leg.amount);
+            let decimals = mint_decimals(mint)?;
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    token_program.clone(),
+                    TransferChecked {
+                        from: vault.clone(),
+                        mint: mint.clone(),
+                        to: to.clone(),
+                        authority: ctx.accounts.pda_account.clone(),
+                    },
+                    &[seeds],
+                ),
+                leg.amount - fee,
+                decimals,
+            )?;
+            if fee > 0 {
+                prepare_fee_vault(
+                    &taker,
+                    fee_vault,
+                    mint,
+                    &config.to_account_info(),
+                    token_program,
+                    &system_program,
+                    ctx.program_id,
+                )?;
+                token_interface::transfer_checked(
+                    CpiContext::new_with_signer(
+                        token_program.clone(),
+                        TransferChecked {
+                            from: vault.clone(),
+                            mint: mint.clone(),
+                            to: fee_vault.clone(),
+                            authority: ctx.accounts.pda_account.clone(),
+                        },
+                        &[seeds],
+                    ),
+                    fee,
+                    decimals,
+                )?;
+            }
+
+            // The vault is empty now, its rent goes back to the initializer
+            harvest_withheld_fees(token_program, mint, vault)?;
+            token_interface::close_account(CpiContext::new_with_signer(
+                token_program.clone(),
+                CloseAccount {
+                    account: vault.clone(),
+                    destination: ctx.accounts.initializers_main_account.clone(),
+                    authority: ctx.accounts.pda_account.clone(),
+                },
+                &[seeds],
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    // Refunds every deposited leg and closes the basket. The remaining accounts are, for each
+    // deposited leg: the mint, its token program, the vault and the initializer's token account
+    // to refund it to
+    pub fn cancel_basket<'info>(
+        ctx: Context<'_, '_, '_, 'info, CancelBasket<'info>>,
+    ) -> Result<()> {
+        let basket = &ctx.accounts.basket;
+        let (deposit_accounts, _) = split_basket_accounts(
+            ctx.remaining_accounts,
+            basket.deposits.len() * CANCEL_LEG_ACCOUNTS,
+            0,
+        )?;
+        let basket_key = basket.key();
+        let seeds: &[&[u8]] = &[
+            ESCROW_PDA_SEED,
+            basket_key.as_ref(),
+            basket.initializer_pubkey.as_ref(),
+            &[basket.pda_bump],
+        ];
+
+        for (accounts, leg) in deposit_accounts
+            .chunks(CANCEL_LEG_ACCOUNTS)
+            .zip(&basket.deposits)
+        {
+            let (mint, token_program, vault, refund_account) =
+                (&accounts[0], &accounts[1], &accounts[2], &accounts[3]);
+            check_basket_leg(leg, mint, token_program, vault)?;
+            let refund_account_info = unpack_token_account(refund_account)?;
+            if refund_account_info.owner != ctx.accounts.initializer.key()
+                || refund_account_info.mint != leg.mint
+            {
+                return Err(ProgramError::InvalidAccountData.into());
+            }
+
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    token_program.clone(),
+                    TransferChecked {
+                        from: vault.clone(),
+                        mint: mint.clone(),
+                        to: refund_account.clone(),
+                        authority: ctx.accounts.pda_account.clone(),
+                    },
+                    &[seeds],
+                ),
+                unpack_token_account(vault)?.amount,
+                mint_decimals(mint)?,
+            )?;
+
+            harvest_withheld_fees(token_program, mint, vault)?;
+            token_interface::close_account(CpiContext::new_with_signer(
+                token_program.clone(),
+                CloseAccount {
+                    account: vault.clone(),
+                    destination: ctx.accounts.initializer.to_account_info(),
+                    authority: ctx.accounts.pda_account.clone(),
+                },
+                &[seeds],
+            ))?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Accounts)]
+#[instruction(seed: u64)]
 pub struct Initialize<'info> {
     #[account(mut)]
     pub initializer: Signer<'info>,
-    #[account(mut)]
-    pub temp_token_account: Account<'info, TokenAccount>,
+    // The wrapped SOL mint for a SOL deposit
+    #[account(mint::token_program = token_program)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    // Left out for a SOL deposit
+    #[account(mut,
+        constraint = initializers_deposit_token_account.mint == mint_a.key() @ ErrorCode::MintMismatch,
+    )]
+    pub initializers_deposit_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    // Either token program will do, the account may live under another one than the deposit.
+    // Left out when the initializer asks for SOL, which is then paid to their wallet
     #[account(
-        constraint = *token_to_receive_account.to_account_info().owner == spl_token::id() @ ProgramError::IncorrectProgramId
+        constraint = token_to_receive_account.mint != mint_a.key() @ ErrorCode::MintMismatch,
     )]
-    pub token_to_receive_account: Account<'info, TokenAccount>,
+    pub token_to_receive_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
     #[account(
         init, payer = initializer, space = Escrow::LEN,
+        seeds = [ESCROW_PDA_SEED, initializer.key().as_ref(), &seed.to_le_bytes()], bump,
         constraint = !escrow_account.is_initialized @ ProgramError::AccountAlreadyInitialized
     )]
     pub escrow_account: Account<'info, Escrow>,
-    pub token_program: Program<'info, Token>,
+    // Left out for a SOL deposit
+    #[account(
+        init, payer = initializer,
+        seeds = [VAULT_SEED, escrow_account.key().as_ref()], bump,
+        token::mint = mint_a, token::authority = pda_account, token::token_program = token_program,
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// CHECK: the PDA of this escrow owning the vault, it is only used as the vault authority
+    #[account(seeds = [ESCROW_PDA_SEED, escrow_account.key().as_ref(), initializer.key().as_ref()], bump)]
+    pub pda_account: AccountInfo<'info>,
+    // The original token program or Token-2022, whichever owns mint A
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>, // needed for init escrow_init
+    pub rent: Sysvar<'info, Rent>,              // needed for init vault
+    #[account(seeds = [CONFIG_SEED], bump = config.bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused,
+    )]
+    pub config: Box<Account<'info, EscrowConfig>>,
+    // Metaplex metadata of mint A, only needed when the deposited NFT has to be of a collection or creator
+    #[account(constraint = deposit_metadata.mint == mint_a.key() @ ErrorCode::NftTermsMismatch)]
+    pub deposit_metadata: Option<Box<Account<'info, MetadataAccount>>>,
 }
 
 #[derive(Accounts)]
 pub struct Exchange<'info> {
     #[account(mut)]
     pub taker: Signer<'info>,
-    #[account(mut)]
-    pub takers_sending_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub takers_token_to_receive_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub pdas_temp_token_account: Account<'info, TokenAccount>,
-    /// CHECK: test
+    // Left out when paying SOL
+    #[account(mut,
+        constraint = takers_sending_token_account.mint == mint_b.key() @ ErrorCode::MintMismatch,
+    )]
+    pub takers_sending_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    // Left out for a SOL deposit, the taker's wallet receives it
+    #[account(mut,
+        constraint = takers_token_to_receive_account.mint == escrow_account.mint_a @ ErrorCode::MintMismatch,
+    )]
+    pub takers_token_to_receive_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    // Left out for a SOL deposit
+    #[account(mut,
+        constraint = pdas_temp_token_account.key() == escrow_account.temp_token_account_pubkey @ ProgramError::InvalidAccountData,
+    )]
+    pub pdas_temp_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// CHECK: the initializer recorded in the escrow, checked against it. It receives the rent and
+    /// any SOL price
     #[account(mut)]
     pub initializers_main_account: AccountInfo<'info>,
-    #[account(mut)]
-    pub initializers_token_to_receive_account: Account<'info, TokenAccount>,
-    #[account(mut, close = initializers_main_account,
-        constraint = escrow_account.temp_token_account_pubkey == *pdas_temp_token_account.to_account_info().key @ ProgramError::InvalidAccountData,
+    // Left out for a SOL price, the initializer's main account receives it
+    #[account(mut,
+        constraint = escrow_account.can_be_paid_into(
+            &initializers_token_to_receive_account.key(),
+            initializers_token_to_receive_account,
+            &mint_b.key(),
+        ) @ ProgramError::InvalidAccountData,
+    )]
+    pub initializers_token_to_receive_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(mut,
+        constraint = escrow_account.version == Escrow::VERSION @ ProgramError::InvalidAccountData,
         constraint = escrow_account.initializer_pubkey == *initializers_main_account.to_account_info().key @ ProgramError::InvalidAccountData,
-        constraint = escrow_account.initializer_token_to_receive_account_pubkey == *initializers_token_to_receive_account.to_account_info().key @ ProgramError::InvalidAccountData,
+        constraint = escrow_account.can_be_taken_by(&taker.key()) @ ErrorCode::TakerNotAllowed,
+        constraint = escrow_account.mint_a == mint_a.key() @ ErrorCode::MintMismatch,
+        constraint = escrow_account.expects_mint(&mint_b.key()) @ ErrorCode::MintMismatch,
     )]
     pub escrow_account: Box<Account<'info, Escrow>>,
-    pub token_program: Program<'info, Token>,
-    /// CHECK: test
+    pub token_program: Interface<'info, TokenInterface>,
+    /// CHECK: the PDA of the escrow owning the vault, it signs the transfers out of it
+    #[account(
+        seeds = [ESCROW_PDA_SEED, escrow_account.key().as_ref(), escrow_account.initializer_pubkey.as_ref()],
+        bump = escrow_account.pda_bump,
+    )]
     pub pda_account: AccountInfo<'info>,
+    // Writable so the withheld transfer fees of the vault can be harvested before closing it
+    #[account(mut, mint::token_program = token_program)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    // The mint of the taker's side, for an expected NFT the one the taker brings
+    #[account(mint::token_program = token_program_b)]
+    pub mint_b: Box<InterfaceAccount<'info, Mint>>,
+    // Each side of the trade goes through the token program owning its mint
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>, // needed to pay a SOL price and create fee vaults
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused,
+    )]
+    pub config: Box<Account<'info, EscrowConfig>>,
+    // Fee vaults of the token sides, created by the first taker paying a fee in their mint.
+    // Left out for SOL sides, the config account collects SOL fees
+    #[account(
+        init_if_needed, payer = taker,
+        seeds = [FEE_VAULT_SEED, mint_a.key().as_ref()], bump,
+        token::mint = mint_a, token::authority = config, token::token_program = token_program,
+    )]
+    pub fee_vault_a: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(
+        init_if_needed, payer = taker,
+        seeds = [FEE_VAULT_SEED, mint_b.key().as_ref()], bump,
+        token::mint = mint_b, token::authority = config, token::token_program = token_program_b,
+    )]
+    pub fee_vault_b: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    // Metaplex metadata of mint B, only needed when the expected NFT has to be of a collection or creator
+    #[account(constraint = expected_metadata.mint == mint_b.key() @ ErrorCode::NftTermsMismatch)]
+    pub expected_metadata: Option<Box<Account<'info, MetadataAccount>>>,
 }
 
 #[derive(Accounts)]
@@ -179,67 +1071,749 @@ pub struct Reset<'info> {
     #[account(mut)]
     pub initializer: Signer<'info>,
     #[account(mut,
+        constraint = escrow_account.version == Escrow::VERSION @ ProgramError::InvalidAccountData,
         constraint = escrow_account.initializer_pubkey == *initializer.to_account_info().key @ ProgramError::InvalidAccountData,
     )]
     pub escrow_account: Box<Account<'info, Escrow>>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused,
+    )]
+    pub config: Box<Account<'info, EscrowConfig>>,
 }
 
 #[derive(Accounts)]
 pub struct Cancel<'info> {
     #[account(mut)]
     pub initializer: Signer<'info>,
+    // Left out for a SOL deposit
+    #[account(mut,
+        constraint = pdas_temp_token_account.key() == escrow_account.temp_token_account_pubkey @ ProgramError::InvalidAccountData,
+    )]
+    pub pdas_temp_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    // Left out for a SOL deposit
+    #[account(mut,
+        constraint = initializers_refund_token_account.owner == *initializer.to_account_info().key @ ProgramError::InvalidAccountData,
+        constraint = initializers_refund_token_account.mint == mint_a.key() @ ProgramError::InvalidAccountData,
+    )]
+    pub initializers_refund_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(mut, close = initializer,
+        constraint = escrow_account.version == Escrow::VERSION @ ProgramError::InvalidAccountData,
+        constraint = escrow_account.initializer_pubkey == *initializer.to_account_info().key @ ProgramError::InvalidAccountData,
+        constraint = escrow_account.mint_a == mint_a.key() @ ErrorCode::MintMismatch,
+    )]
+    pub escrow_account: Box<Account<'info, Escrow>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    /// CHECK: the PDA of the escrow owning the temp token account, it only signs the refund
+    #[account(
+        seeds = [ESCROW_PDA_SEED, escrow_account.key().as_ref(), initializer.key().as_ref()],
+        bump = escrow_account.pda_bump,
+    )]
+    pub pda_account: AccountInfo<'info>,
+    // Writable so the withheld transfer fees of the vault can be harvested before closing it
+    #[account(mut, mint::token_program = token_program)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateEscrow<'info> {
     #[account(mut)]
-    pub pdas_temp_token_account: Account<'info, TokenAccount>,
-    /// CHECK: test
+    pub initializer: Signer<'info>,
+    #[account(mut,
+        constraint = escrow_account.version == Escrow::VERSION @ ProgramError::InvalidAccountData,
+        constraint = escrow_account.initializer_pubkey == initializer.key() @ ProgramError::InvalidAccountData,
+        constraint = escrow_account.mint_a == mint_a.key() @ ErrorCode::MintMismatch,
+    )]
+    pub escrow_account: Box<Account<'info, Escrow>>,
+    // Left out for a SOL deposit
+    #[account(mut,
+        constraint = pdas_temp_token_account.key() == escrow_account.temp_token_account_pubkey @ ProgramError::InvalidAccountData,
+    )]
+    pub pdas_temp_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    // The account to top up from or withdraw to, left out for a SOL deposit
+    #[account(mut,
+        constraint = initializers_token_account.owner == initializer.key() @ ProgramError::InvalidAccountData,
+        constraint = initializers_token_account.mint == mint_a.key() @ ErrorCode::MintMismatch,
+    )]
+    pub initializers_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(mint::token_program = token_program)]
+    pub mint_a: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    /// CHECK: the PDA of the escrow owning the vault, it only signs withdrawals
+    #[account(
+        seeds = [ESCROW_PDA_SEED, escrow_account.key().as_ref(), initializer.key().as_ref()],
+        bump = escrow_account.pda_bump,
+    )]
+    pub pda_account: AccountInfo<'info>,
+    pub system_program: Program<'info, System>, // needed to top up a SOL deposit
+    #[account(seeds = [CONFIG_SEED], bump = config.bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused,
+    )]
+    pub config: Box<Account<'info, EscrowConfig>>,
+}
+
+#[derive(Accounts)]
+pub struct InitConfig<'info> {
     #[account(mut)]
-    pub initializers_main_account: AccountInfo<'info>,
+    pub admin: Signer<'info>,
+    #[account(init, payer = admin, space = EscrowConfig::LEN, seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, EscrowConfig>,
+    /// CHECK: only its key is stored, it may withdraw the protocol fees
+    pub fee_recipient: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        seeds = [crate::ID.as_ref()], bump, seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(admin.key()) @ ErrorCode::InvalidAdmin,
+    )]
+    pub program_data: Account<'info, ProgramData>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    pub admin: Signer<'info>,
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump, has_one = admin @ ErrorCode::InvalidAdmin)]
+    pub config: Account<'info, EscrowConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetFeeRecipient<'info> {
+    pub admin: Signer<'info>,
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump, has_one = admin @ ErrorCode::InvalidAdmin)]
+    pub config: Account<'info, EscrowConfig>,
+    /// CHECK: only its key is stored, it may withdraw the protocol fees
+    pub fee_recipient: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetAdmin<'info> {
+    pub admin: Signer<'info>,
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump, has_one = admin @ ErrorCode::InvalidAdmin)]
+    pub config: Account<'info, EscrowConfig>,
+    pub new_admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(seed: u64)]
+pub struct InitializeBasket<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        init, payer = initializer, space = BasketEscrow::LEN,
+        seeds = [BASKET_SEED, initializer.key().as_ref(), &seed.to_le_bytes()], bump,
+    )]
+    pub basket: Box<Account<'info, BasketEscrow>>,
+    /// CHECK: the PDA of the basket owning its vaults, it is only used as their authority
+    #[account(seeds = [ESCROW_PDA_SEED, basket.key().as_ref(), initializer.key().as_ref()], bump)]
+    pub pda_account: AccountInfo<'info>,
+    pub system_program: Program<'info, System>, // needed to create the basket and its vaults
+    #[account(seeds = [CONFIG_SEED], bump = config.bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused,
+    )]
+    pub config: Box<Account<'info, EscrowConfig>>,
+}
+
+#[derive(Accounts)]
+pub struct ExchangeBasket<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+    /// CHECK: receives the rent of the basket and its vaults, checked against the basket
     #[account(mut)]
-    pub initializers_token_to_receive_account: Account<'info, TokenAccount>,
+    pub initializers_main_account: AccountInfo<'info>,
     #[account(mut, close = initializers_main_account,
-        constraint = escrow_account.temp_token_account_pubkey == *pdas_temp_token_account.to_account_info().key @ ProgramError::InvalidAccountData,
-        constraint = escrow_account.initializer_pubkey == *initializers_main_account.to_account_info().key @ ProgramError::InvalidAccountData,
-        constraint = escrow_account.initializer_token_to_receive_account_pubkey == *initializers_token_to_receive_account.to_account_info().key @ ProgramError::InvalidAccountData,
+        constraint = basket.initializer_pubkey == initializers_main_account.key() @ ProgramError::InvalidAccountData,
+        constraint = can_be_taken_by(&basket.allowed_taker, &taker.key()) @ ErrorCode::TakerNotAllowed,
+    )]
+    pub basket: Box<Account<'info, BasketEscrow>>,
+    /// CHECK: the PDA of the basket owning its vaults, it signs the transfers out of them
+    #[account(
+        seeds = [ESCROW_PDA_SEED, basket.key().as_ref(), basket.initializer_pubkey.as_ref()],
+        bump = basket.pda_bump,
     )]
-    pub escrow_account: Box<Account<'info, Escrow>>,
-    pub token_program: Program<'info, Token>,
-    /// CHECK: test
     pub pda_account: AccountInfo<'info>,
+    pub system_program: Program<'info, System>, // needed to create fee vaults
+    #[account(seeds = [CONFIG_SEED], bump = config.bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused,
+    )]
+    pub config: Box<Account<'info, EscrowConfig>>,
+}
+
+#[derive(Accounts)]
+pub struct CancelBasket<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(mut, close = initializer,
+        constraint = basket.initializer_pubkey == initializer.key() @ ProgramError::InvalidAccountData,
+    )]
+    pub basket: Box<Account<'info, BasketEscrow>>,
+    /// CHECK: the PDA of the basket owning its vaults, it only signs the refunds
+    #[account(
+        seeds = [ESCROW_PDA_SEED, basket.key().as_ref(), initializer.key().as_ref()],
+        bump = basket.pda_bump,
+    )]
+    pub pda_account: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFees<'info> {
+    pub admin: Signer<'info>,
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump,
+        has_one = admin @ ErrorCode::InvalidAdmin,
+        has_one = fee_recipient @ ProgramError::InvalidAccountData,
+    )]
+    pub config: Account<'info, EscrowConfig>,
+    // The wrapped SOL mint to withdraw the SOL fees held by the config account
+    #[account(mint::token_program = token_program)]
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+    // Left out for SOL
+    #[account(mut,
+        seeds = [FEE_VAULT_SEED, mint.key().as_ref()], bump,
+        token::mint = mint, token::authority = config, token::token_program = token_program,
+    )]
+    pub fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// CHECK: receives SOL fees, checked against the config
+    #[account(mut)]
+    pub fee_recipient: AccountInfo<'info>,
+    // Left out for SOL
+    #[account(mut,
+        token::mint = mint, token::authority = fee_recipient, token::token_program = token_program,
+    )]
+    pub fee_recipient_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[account]
 pub struct Escrow {
     pub is_initialized: bool,
+    pub version: u8,
     pub initializer_pubkey: Pubkey,
     pub temp_token_account_pubkey: Pubkey,
     pub initializer_token_to_receive_account_pubkey: Pubkey,
+    pub mint_a: Pubkey,
+    // The default key for an expected NFT, whose mint is only known at the exchange
+    pub mint_b: Pubkey,
+    pub deposit_amount: u64,
+    pub expected_amount: u64,
+    pub unlock_time: u64,
+    pub time_out: u64,
+    // Bump of the PDA of this escrow, the authority of its vault
+    pub pda_bump: u8,
+    // The escrow address is derived from the initializer and this seed
+    pub seed: u64,
+    pub remaining_amount: u64,
+    pub remaining_expected_amount: u64,
+    pub allowed_taker: Option<Pubkey>,
+    pub deposit_nft: Option<NftTerms>,
+    pub expected_nft: Option<NftTerms>,
+}
+
+// Marks a side of the escrow as an NFT. It may further have to be of a verified collection
+// or have a verified creator, as listed by its Metaplex metadata
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub struct NftTerms {
+    pub collection: Option<Pubkey>,
+    pub creator: Option<Pubkey>,
+}
+
+impl NftTerms {
+    const LEN: usize = (OPTION_TAG_LENGTH + PUBLIC_KEY_LENGTH) * 2;
+}
+
+// Events of the escrow lifecycle, for indexers to follow trades from the transaction logs.
+// Slots are those at which the instruction ran
+#[event]
+pub struct EscrowCreated {
+    pub escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    // What reached the escrow, less any transfer fee of mint A
+    pub deposit_amount: u64,
     pub expected_amount: u64,
     pub unlock_time: u64,
     pub time_out: u64,
+    pub allowed_taker: Option<Pubkey>,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EscrowExchanged {
+    pub escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub taker: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    // The part of the deposit taken and its price, before protocol and transfer fees
+    pub taken_amount: u64,
+    pub paid_amount: u64,
+    // The escrow is closed once nothing is left
+    pub remaining_amount: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EscrowCancelled {
+    pub escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub mint_a: Pubkey,
+    pub refunded_amount: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EscrowTimeReset {
+    pub escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub unlock_time: u64,
+    pub time_out: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EscrowUpdated {
+    pub escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    // The terms what is left is now offered under
+    pub deposit_amount: u64,
+    pub expected_amount: u64,
+    pub time_out: u64,
+    pub allowed_taker: Option<Pubkey>,
+    pub slot: u64,
 }
 
 const DISCRIMINATOR_LENGTH: usize = 8;
 const BOOL_LENGTH: usize = 1;
+const U8_LENGTH: usize = 1;
 const PUBLIC_KEY_LENGTH: usize = 32;
 const U64_LENGTH: usize = 8;
+const OPTION_TAG_LENGTH: usize = 1;
+
+const U16_LENGTH: usize = 2;
+const VEC_PREFIX_LENGTH: usize = 4;
+
+const DEFAULT_UNLOCK_DELAY: u64 = 100;
+const DEFAULT_TRADE_WINDOW: u64 = 1000;
+
+#[account]
+pub struct EscrowConfig {
+    pub admin: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub fee_basis_points: u16,
+    pub paused: bool,
+    pub bump: u8,
+}
+
+// One token of a basket: the vault holding it when deposited, or the initializer's token
+// account receiving it when expected
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BasketLeg {
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub token_account: Pubkey,
+}
+
+impl BasketLeg {
+    const LEN: usize = PUBLIC_KEY_LENGTH * 2 + U64_LENGTH;
+}
+
+#[account]
+pub struct BasketEscrow {
+    pub initializer_pubkey: Pubkey,
+    pub deposits: Vec<BasketLeg>,
+    pub expected: Vec<BasketLeg>,
+    pub unlock_time: u64,
+    pub time_out: u64,
+    pub allowed_taker: Option<Pubkey>,
+    // Bump of the PDA of this basket, the authority of its vaults
+    pub pda_bump: u8,
+    // The basket address is derived from the initializer and this seed
+    pub seed: u64,
+}
+
+impl BasketEscrow {
+    // Each side of a basket holds at most this many mints, so the account has a fixed size
+    pub const MAX_LEGS: usize = 4;
+
+    const LEN: usize = DISCRIMINATOR_LENGTH
+        + PUBLIC_KEY_LENGTH
+        + (VEC_PREFIX_LENGTH + BasketLeg::LEN * Self::MAX_LEGS) * 2
+        + U64_LENGTH * 2
+        + OPTION_TAG_LENGTH
+        + PUBLIC_KEY_LENGTH
+        + U8_LENGTH
+        + U64_LENGTH;
+}
+
+impl EscrowConfig {
+    const LEN: usize =
+        DISCRIMINATOR_LENGTH + PUBLIC_KEY_LENGTH * 2 + U16_LENGTH + BOOL_LENGTH + U8_LENGTH;
+
+    // The protocol fee can never be set above 10% of a leg
+    pub const MAX_FEE_BASIS_POINTS: u16 = 1_000;
+
+    fn protocol_fee(&self, amount: u64) -> u64 {
+        escrow_math::protocol_fee(amount, self.fee_basis_points)
+    }
+}
 
 impl Escrow {
-    const LEN: usize = DISCRIMINATOR_LENGTH + BOOL_LENGTH + PUBLIC_KEY_LENGTH * 3 + U64_LENGTH * 3;
+    const LEN: usize = DISCRIMINATOR_LENGTH
+        + BOOL_LENGTH
+        + U8_LENGTH * 2
+        + PUBLIC_KEY_LENGTH * 5
+        + U64_LENGTH * 7
+        + OPTION_TAG_LENGTH
+        + PUBLIC_KEY_LENGTH
+        + (OPTION_TAG_LENGTH + NftTerms::LEN) * 2;
+
+    // Version of the account layout, bumped whenever fields are added or moved
+    const VERSION: u8 = 5;
+
+    fn set_time_window(
+        &mut self,
+        slot: u64,
+        unlock_time: Option<u64>,
+        time_out: Option<u64>,
+    ) -> Result<()> {
+        let (unlock_time, time_out) = time_window(slot, unlock_time, time_out)?;
+        self.unlock_time = unlock_time;
+        self.time_out = time_out;
+        Ok(())
+    }
+
+    // Price of a fill at the ratio of what is left in the escrow, see `escrow_math::amount_to_pay`
+    fn amount_to_pay(&self, amount: u64) -> Result<u64> {
+        escrow_math::amount_to_pay(
+            amount,
+            self.remaining_amount,
+            self.remaining_expected_amount,
+        )
+        .ok_or_else(|| ErrorCode::AmountOverflow.into())
+    }
+
+    fn can_be_taken_by(&self, taker: &Pubkey) -> bool {
+        can_be_taken_by(&self.allowed_taker, taker)
+    }
+
+    // An expected NFT left unpinned at init, any one meeting the terms will do
+    fn expects_any_nft(&self) -> bool {
+        self.expected_nft.is_some() && self.mint_b == Pubkey::default()
+    }
+
+    fn expects_mint(&self, mint: &Pubkey) -> bool {
+        self.expects_any_nft() || self.mint_b == *mint
+    }
+
+    // The price goes to the account recorded at init. An unpinned NFT goes to any account of
+    // the initializer for its mint, which only exists once it is known
+    fn can_be_paid_into(&self, key: &Pubkey, account: &TokenAccount, mint_b: &Pubkey) -> bool {
+        if self.expects_any_nft() {
+            account.owner == self.initializer_pubkey && account.mint == *mint_b
+        } else {
+            *key == self.initializer_token_to_receive_account_pubkey
+        }
+    }
+
+    fn check_time_window(&self, slot: u64) -> Result<()> {
+        check_time_window(slot, self.unlock_time, self.time_out)
+    }
 }
 
-impl<'info> From<&mut Initialize<'info>> for CpiContext<'_, '_, '_, 'info, SetAuthority<'info>> {
-    fn from(accounts: &mut Initialize<'info>) -> Self {
-        let cpi_accounts = SetAuthority {
-            current_authority: accounts.initializer.to_account_info().clone(),
-            account_or_mint: accounts.temp_token_account.to_account_info().clone(),
+// Same model as the native escrow: the trade unlocks at `unlock_time` and can be taken
+// up to and including `time_out`. By default it unlocks in 100 slots and stays open for 1000
+fn time_window(slot: u64, unlock_time: Option<u64>, time_out: Option<u64>) -> Result<(u64, u64)> {
+    let unlock_time = unlock_time.unwrap_or(slot + DEFAULT_UNLOCK_DELAY);
+    let time_out = match time_out {
+        Some(time_out) => time_out,
+        None => unlock_time
+            .checked_add(DEFAULT_TRADE_WINDOW)
+            .ok_or(ErrorCode::InvalidTimeWindow)?,
+    };
+
+    // Reject inverted windows and the ones which are already expired
+    if unlock_time > time_out || time_out <= slot {
+        return Err(ErrorCode::InvalidTimeWindow.into());
+    }
+    Ok((unlock_time, time_out))
+}
+
+fn check_time_window(slot: u64, unlock_time: u64, time_out: u64) -> Result<()> {
+    if slot < unlock_time {
+        return Err(ErrorCode::EscrowTradeLocked.into());
+    }
+    if slot > time_out {
+        return Err(ErrorCode::EscrowTimedOut.into());
+    }
+    Ok(())
+}
+
+// A private escrow can only be taken by the counterparty it names
+fn can_be_taken_by(allowed_taker: &Option<Pubkey>, taker: &Pubkey) -> bool {
+    match allowed_taker {
+        Some(allowed_taker) => allowed_taker == taker,
+        None => true,
+    }
+}
+
+impl<'info> Initialize<'info> {
+    fn deposit_context(&self) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
+        let cpi_accounts = TransferChecked {
+            from: token_leg(&self.initializers_deposit_token_account)?
+                .to_account_info()
+                .clone(),
+            mint: self.mint_a.to_account_info().clone(),
+            to: token_leg(&self.vault)?.to_account_info().clone(),
+            authority: self.initializer.to_account_info().clone(),
+        };
+        let cpi_program = self.token_program.to_account_info();
+        Ok(CpiContext::new(cpi_program, cpi_accounts))
+    }
+
+    fn deposit_sol_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let cpi_accounts = Transfer {
+            from: self.initializer.to_account_info().clone(),
+            to: self.escrow_account.to_account_info().clone(),
+        };
+        CpiContext::new(self.system_program.to_account_info(), cpi_accounts)
+    }
+}
+
+// An NFT is a mint of a single token without decimals. The collection and creator the terms
+// name must be verified in its metadata, which is then required
+fn check_nft(
+    mint: &Mint,
+    terms: &NftTerms,
+    metadata: &Option<Box<Account<MetadataAccount>>>,
+) -> Result<()> {
+    if mint.supply != 1 || mint.decimals != 0 {
+        return Err(ErrorCode::NotAnNft.into());
+    }
+    if terms.collection.is_none() && terms.creator.is_none() {
+        return Ok(());
+    }
+
+    let metadata = metadata.as_ref().ok_or(ErrorCode::NftTermsMismatch)?;
+    if let Some(collection) = terms.collection {
+        let in_collection = match &metadata.collection {
+            Some(metadata_collection) => {
+                metadata_collection.verified && metadata_collection.key == collection
+            }
+            None => false,
+        };
+        if !in_collection {
+            return Err(ErrorCode::NftTermsMismatch.into());
+        }
+    }
+    if let Some(creator) = terms.creator {
+        let by_creator = match &metadata.creators {
+            Some(creators) => creators.iter().any(|metadata_creator| {
+                metadata_creator.verified && metadata_creator.address == creator
+            }),
+            None => false,
         };
-        let cpi_program = accounts.token_program.to_account_info();
-        CpiContext::new(cpi_program, cpi_accounts)
+        if !by_creator {
+            return Err(ErrorCode::NftTermsMismatch.into());
+        }
+    }
+    Ok(())
+}
+
+// As `Processor::move_lamports` of the native escrow
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> Result<()> {
+    **from.try_borrow_mut_lamports()? = from
+        .lamports()
+        .checked_sub(amount)
+        .ok_or(ErrorCode::AmountOverflow)?;
+    **to.try_borrow_mut_lamports()? = to
+        .lamports()
+        .checked_add(amount)
+        .ok_or(ErrorCode::AmountOverflow)?;
+    Ok(())
+}
+
+fn is_sol(mint: &Pubkey) -> bool {
+    *mint == native_mint::ID
+}
+
+// Token side of a trade which may also be SOL, the token accounts of a SOL side are left out
+fn token_leg<T>(account: &Option<T>) -> Result<&T> {
+    account
+        .as_ref()
+        .ok_or_else(|| ErrorCode::SolLegMismatch.into())
+}
+
+// As `Processor::inverse_transfer_fee` of the native escrow, against the spl-token-2022 release
+// of anchor-spl
+fn inverse_transfer_fee(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    let data = mint.try_borrow_data()?;
+    let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    match mint.get_extension::<TransferFeeConfig>() {
+        Ok(transfer_fee_config) => transfer_fee_config
+            .calculate_inverse_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or_else(|| ErrorCode::AmountOverflow.into()),
+        Err(_) => Ok(0),
     }
 }
 
+// As `Processor::harvest_withheld_fees` of the native escrow
+fn harvest_withheld_fees<'info>(
+    token_program: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    vault: &AccountInfo<'info>,
+) -> Result<()> {
+    let withheld_amount = {
+        let data = vault.try_borrow_data()?;
+        let vault_state = StateWithExtensions::<spl_token_2022::state::Account>::unpack(&data)?;
+        match vault_state.get_extension::<TransferFeeAmount>() {
+            Ok(transfer_fee_amount) => u64::from(transfer_fee_amount.withheld_amount),
+            Err(_) => 0,
+        }
+    };
+    if withheld_amount == 0 {
+        return Ok(());
+    }
+
+    let harvest_ix =
+        spl_token_2022::extension::transfer_fee::instruction::harvest_withheld_tokens_to_mint(
+            token_program.key,
+            mint.key,
+            &[vault.key],
+        )?;
+    invoke(
+        &harvest_ix,
+        &[mint.clone(), vault.clone(), token_program.clone()],
+    )?;
+    Ok(())
+}
+
+// Remaining accounts of a basket instruction, split into those of its deposited and expected legs
+fn split_basket_accounts<'a, 'info>(
+    accounts: &'a [AccountInfo<'info>],
+    deposit_accounts: usize,
+    expected_accounts: usize,
+) -> Result<(&'a [AccountInfo<'info>], &'a [AccountInfo<'info>])> {
+    if accounts.len() != deposit_accounts + expected_accounts {
+        return Err(ErrorCode::InvalidBasket.into());
+    }
+    Ok(accounts.split_at(deposit_accounts))
+}
+
+// The accounts passed for a leg of a basket must be the ones it was opened with
+fn check_basket_leg(
+    leg: &BasketLeg,
+    mint: &AccountInfo,
+    token_program: &AccountInfo,
+    token_account: &AccountInfo,
+) -> Result<()> {
+    if leg.mint != *mint.key {
+        return Err(ErrorCode::MintMismatch.into());
+    }
+    check_token_program(token_program, mint)?;
+    if leg.token_account != *token_account.key {
+        return Err(ProgramError::InvalidAccountData.into());
+    }
+    Ok(())
+}
+
+// Both the original token program and Token-2022 are accepted, as long as it owns the mint
+fn check_token_program(token_program: &AccountInfo, mint: &AccountInfo) -> Result<()> {
+    spl_token_2022::check_spl_token_program_account(token_program.key)?;
+    if mint.owner != token_program.key {
+        return Err(ProgramError::IncorrectProgramId.into());
+    }
+    Ok(())
+}
+
+// Reads the base state of a token account of either token program, skipping its extensions
+fn unpack_token_account(account: &AccountInfo) -> Result<spl_token_2022::state::Account> {
+    spl_token_2022::check_spl_token_program_account(account.owner)?;
+    let data = account.try_borrow_data()?;
+    Ok(StateWithExtensions::<spl_token_2022::state::Account>::unpack(&data)?.base)
+}
+
+fn mint_decimals(mint: &AccountInfo) -> Result<u8> {
+    spl_token_2022::check_spl_token_program_account(mint.owner)?;
+    let data = mint.try_borrow_data()?;
+    Ok(
+        StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?
+            .base
+            .decimals,
+    )
+}
+
+// Creates a token account at a PDA of this program, sized for the extensions its mint requires
+fn create_token_account<'info>(
+    payer: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    seeds: &[&[u8]],
+    mint: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    system_program_info: &AccountInfo<'info>,
+) -> Result<()> {
+    let space = {
+        let data = mint.try_borrow_data()?;
+        let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+        ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(
+            &ExtensionType::get_required_init_account_extensions(&mint.get_extension_types()?),
+        )?
+    };
+    system_program::create_account(
+        CpiContext::new_with_signer(
+            system_program_info.clone(),
+            CreateAccount {
+                from: payer.clone(),
+                to: account.clone(),
+            },
+            &[seeds],
+        ),
+        Rent::get()?.minimum_balance(space),
+        space as u64,
+        token_program.key,
+    )?;
+    token_interface::initialize_account3(CpiContext::new(
+        token_program.clone(),
+        InitializeAccount3 {
+            account: account.clone(),
+            mint: mint.clone(),
+            authority: authority.clone(),
+        },
+    ))
+}
+
+// Fee vaults are token accounts owned by the config PDA, one per mint. The first taker paying
+// a fee in a mint creates its vault, the same one a single escrow exchange would create
+fn prepare_fee_vault<'info>(
+    taker: &AccountInfo<'info>,
+    fee_vault: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    config: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    system_program_info: &AccountInfo<'info>,
+    program_id: &Pubkey,
+) -> Result<()> {
+    let (fee_vault_address, fee_vault_bump) =
+        Pubkey::find_program_address(&[FEE_VAULT_SEED, mint.key.as_ref()], program_id);
+    if fee_vault_address != *fee_vault.key {
+        return Err(ProgramError::InvalidSeeds.into());
+    }
+    if !fee_vault.data_is_empty() {
+        return Ok(());
+    }
+    create_token_account(
+        taker,
+        fee_vault,
+        &[FEE_VAULT_SEED, mint.key.as_ref(), &[fee_vault_bump]],
+        mint,
+        config,
+        token_program,
+        system_program_info,
+    )
+}
+
 #[error_code]
 pub enum ErrorCode {
-    #[msg("Amount expected by taker does not match the deposited amount of intitializer.")]
+    #[msg("Amount expected by taker is zero or exceeds what is left in the escrow.")]
     ExpectedAmountMismatch,
     #[msg("The Escrow has timed out.")]
     EscrowTimedOut,
@@ -247,66 +1821,222 @@ pub enum ErrorCode {
     EscrowTradeLocked,
     #[msg("The Escrow has not yet initialized.")]
     EscrowNotInitialized,
+    #[msg("The Escrow time window is inverted or already expired.")]
+    InvalidTimeWindow,
+    #[msg("Amount overflow.")]
+    AmountOverflow,
+    #[msg("Token account mint does not match the escrow.")]
+    MintMismatch,
+    #[msg("Accounts do not match the SOL or token side of the escrow.")]
+    SolLegMismatch,
+    #[msg("The Escrow can only be taken by another taker.")]
+    TakerNotAllowed,
+    #[msg("The fee is above the maximum.")]
+    FeeTooHigh,
+    #[msg("Only the admin can change the config.")]
+    InvalidAdmin,
+    #[msg("The program is paused.")]
+    ProgramPaused,
+    #[msg("The basket legs or their accounts are invalid.")]
+    InvalidBasket,
+    #[msg("The asset is not a whole NFT.")]
+    NotAnNft,
+    #[msg("The NFT is not of the expected collection or creator.")]
+    NftTermsMismatch,
+    #[msg("The update would leave the escrow without a deposit or a price.")]
+    InvalidUpdate,
+    #[msg("The price is above the most the taker agreed to pay.")]
+    MaxPayAmountExceeded,
+    #[msg("An expected NFT needs a receive account or a collection or creator to meet.")]
+    UnpinnedNft,
 }
 
 impl<'info> Exchange<'info> {
-    fn into_transfer_to_initializer_context(
-        &self,
-    ) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+    fn pay_sol_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let cpi_accounts = Transfer {
+            from: self.taker.to_account_info().clone(),
+            to: self.initializers_main_account.clone(),
+        };
+        CpiContext::new(self.system_program.to_account_info(), cpi_accounts)
+    }
+
+    fn pay_sol_fee_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
         let cpi_accounts = Transfer {
-            from: self.takers_sending_token_account.to_account_info().clone(),
-            to: self
-                .initializers_token_to_receive_account
+            from: self.taker.to_account_info().clone(),
+            to: self.config.to_account_info().clone(),
+        };
+        CpiContext::new(self.system_program.to_account_info(), cpi_accounts)
+    }
+
+    fn pay_fee_context(&self) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
+        let cpi_accounts = TransferChecked {
+            from: token_leg(&self.takers_sending_token_account)?
                 .to_account_info()
                 .clone(),
+            mint: self.mint_b.to_account_info().clone(),
+            to: token_leg(&self.fee_vault_b)?.to_account_info().clone(),
             authority: self.taker.to_account_info().clone(),
         };
+        let cpi_program = self.token_program_b.to_account_info();
+        Ok(CpiContext::new(cpi_program, cpi_accounts))
+    }
+
+    fn transfer_fee_to_vault_context(
+        &self,
+    ) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
+        let cpi_accounts = TransferChecked {
+            from: token_leg(&self.pdas_temp_token_account)?
+                .to_account_info()
+                .clone(),
+            mint: self.mint_a.to_account_info().clone(),
+            to: token_leg(&self.fee_vault_a)?.to_account_info().clone(),
+            authority: self.pda_account.clone(),
+        };
         let cpi_program = self.token_program.to_account_info();
-        CpiContext::new(cpi_program, cpi_accounts)
+        Ok(CpiContext::new(cpi_program, cpi_accounts))
     }
 
-    fn into_transfer_to_taker_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
-        let cpi_accounts = Transfer {
-            from: self.pdas_temp_token_account.to_account_info().clone(),
-            to: self
-                .takers_token_to_receive_account
+    fn transfer_to_initializer_context(
+        &self,
+    ) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
+        let cpi_accounts = TransferChecked {
+            from: token_leg(&self.takers_sending_token_account)?
+                .to_account_info()
+                .clone(),
+            mint: self.mint_b.to_account_info().clone(),
+            to: token_leg(&self.initializers_token_to_receive_account)?
+                .to_account_info()
+                .clone(),
+            authority: self.taker.to_account_info().clone(),
+        };
+        let cpi_program = self.token_program_b.to_account_info();
+        Ok(CpiContext::new(cpi_program, cpi_accounts))
+    }
+
+    fn transfer_to_taker_context(
+        &self,
+    ) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
+        let cpi_accounts = TransferChecked {
+            from: token_leg(&self.pdas_temp_token_account)?
+                .to_account_info()
+                .clone(),
+            mint: self.mint_a.to_account_info().clone(),
+            to: token_leg(&self.takers_token_to_receive_account)?
                 .to_account_info()
                 .clone(),
             authority: self.pda_account.clone(),
         };
         let cpi_program = self.token_program.to_account_info();
-        CpiContext::new(cpi_program, cpi_accounts)
+        Ok(CpiContext::new(cpi_program, cpi_accounts))
     }
 
-    fn into_close_temp_token_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+    fn close_temp_token_context(
+        &self,
+    ) -> Result<CpiContext<'_, '_, '_, 'info, CloseAccount<'info>>> {
         let cpi_accounts = CloseAccount {
-            account: self.pdas_temp_token_account.to_account_info().clone(),
+            account: token_leg(&self.pdas_temp_token_account)?
+                .to_account_info()
+                .clone(),
             destination: self.initializers_main_account.clone(),
             authority: self.pda_account.clone(),
         };
         let cpi_program = self.token_program.to_account_info();
-        CpiContext::new(cpi_program, cpi_accounts)
+        Ok(CpiContext::new(cpi_program, cpi_accounts))
     }
 }
 
 impl<'info> Cancel<'info> {
-    fn into_transfer_to_initializer_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
-        let cpi_accounts = Transfer {
-            from: self.pdas_temp_token_account.to_account_info().clone(),
-            to: self.initializer.to_account_info().clone(),
-            authority: self.initializers_main_account.to_account_info().clone(),
+    fn transfer_to_initializer_context(
+        &self,
+    ) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
+        let cpi_accounts = TransferChecked {
+            from: token_leg(&self.pdas_temp_token_account)?
+                .to_account_info()
+                .clone(),
+            mint: self.mint_a.to_account_info().clone(),
+            to: token_leg(&self.initializers_refund_token_account)?
+                .to_account_info()
+                .clone(),
+            authority: self.pda_account.clone(),
         };
-        CpiContext::new(self.token_program.to_account_info(), cpi_accounts)
+        Ok(CpiContext::new(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+        ))
     }
 
-    fn into_close_temp_token_context(&self) -> CpiContext<'_, '_, '_, 'info, CloseAccount<'info>> {
+    fn close_temp_token_context(
+        &self,
+    ) -> Result<CpiContext<'_, '_, '_, 'info, CloseAccount<'info>>> {
         let cpi_accounts = CloseAccount {
-            account: self.pdas_temp_token_account.to_account_info().clone(),
-            destination: self.initializers_main_account.clone(),
+            account: token_leg(&self.pdas_temp_token_account)?
+                .to_account_info()
+                .clone(),
+            destination: self.initializer.to_account_info().clone(),
             authority: self.pda_account.clone(),
         };
         let cpi_program = self.token_program.to_account_info();
-        CpiContext::new(cpi_program, cpi_accounts)
+        Ok(CpiContext::new(cpi_program, cpi_accounts))
+    }
+}
+
+impl<'info> UpdateEscrow<'info> {
+    fn top_up_context(&self) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
+        let cpi_accounts = TransferChecked {
+            from: token_leg(&self.initializers_token_account)?
+                .to_account_info()
+                .clone(),
+            mint: self.mint_a.to_account_info().clone(),
+            to: token_leg(&self.pdas_temp_token_account)?
+                .to_account_info()
+                .clone(),
+            authority: self.initializer.to_account_info().clone(),
+        };
+        Ok(CpiContext::new(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+        ))
+    }
+
+    fn top_up_sol_context(&self) -> CpiContext<'_, '_, '_, 'info, Transfer<'info>> {
+        let cpi_accounts = Transfer {
+            from: self.initializer.to_account_info().clone(),
+            to: self.escrow_account.to_account_info().clone(),
+        };
+        CpiContext::new(self.system_program.to_account_info(), cpi_accounts)
     }
 
+    fn withdraw_context(&self) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
+        let cpi_accounts = TransferChecked {
+            from: token_leg(&self.pdas_temp_token_account)?
+                .to_account_info()
+                .clone(),
+            mint: self.mint_a.to_account_info().clone(),
+            to: token_leg(&self.initializers_token_account)?
+                .to_account_info()
+                .clone(),
+            authority: self.pda_account.clone(),
+        };
+        Ok(CpiContext::new(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+        ))
+    }
+}
+
+impl<'info> WithdrawFees<'info> {
+    fn withdraw_context(&self) -> Result<CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>> {
+        let cpi_accounts = TransferChecked {
+            from: token_leg(&self.fee_vault)?.to_account_info().clone(),
+            mint: self.mint.to_account_info().clone(),
+            to: token_leg(&self.fee_recipient_token_account)?
+                .to_account_info()
+                .clone(),
+            authority: self.config.to_account_info().clone(),
+        };
+        Ok(CpiContext::new(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+        ))
+    }
 }
@@ -0,0 +1,39 @@
+mod common;
+
+use common::{assert_error, current_slot, token_balance, Trade};
+use solana_escrow_anchor::ErrorCode;
+use solana_sdk::signature::{Keypair, Signer};
+
+#[tokio::test]
+async fn allowed_taker_can_take_the_trade() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 50, 30).await;
+    trade.allowed_taker = Some(trade.bob.pubkey());
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), Some(slot + 100))
+        .await
+        .unwrap();
+
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 50);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 30);
+}
+
+#[tokio::test]
+async fn other_takers_are_rejected() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 50, 30).await;
+    trade.allowed_taker = Some(Keypair::new().pubkey());
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), Some(slot + 100))
+        .await
+        .unwrap();
+
+    assert_error(
+        trade.exchange(&mut context).await,
+        ErrorCode::TakerNotAllowed,
+    );
+}
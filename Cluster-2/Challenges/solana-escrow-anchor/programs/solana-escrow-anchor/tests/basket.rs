@@ -0,0 +1,321 @@
+mod common;
+
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::token::spl_token;
+use common::{
+    account_exists, assert_error, config_address, create_account, create_mint,
+    create_token_account, current_slot, fee_vault_address, mint_to, process, set_fee,
+    token_balance,
+};
+use solana_escrow_anchor::ErrorCode;
+use solana_program_test::{BanksClientError, ProgramTestContext};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_program,
+};
+
+/// A token of the basket with the accounts of both parties, Alice funded for deposits and Bob for
+/// expected legs
+struct Leg {
+    mint: Pubkey,
+    alice: Pubkey,
+    bob: Pubkey,
+    amount: u64,
+}
+
+/// Alice offers the `deposits` tokens for the `expected` ones, Bob is the taker
+struct Basket {
+    alice: Keypair,
+    bob: Keypair,
+    basket: Pubkey,
+    deposits: Vec<Leg>,
+    expected: Vec<Leg>,
+}
+
+impl Basket {
+    async fn setup(context: &mut ProgramTestContext, deposits: &[u64], expected: &[u64]) -> Self {
+        let alice = Keypair::new();
+        let bob = Keypair::new();
+        create_account(context, &alice).await;
+        create_account(context, &bob).await;
+
+        let mut basket = Basket {
+            basket: Pubkey::find_program_address(
+                &[b"basket", alice.pubkey().as_ref(), &0u64.to_le_bytes()],
+                &solana_escrow_anchor::id(),
+            )
+            .0,
+            alice,
+            bob,
+            deposits: Vec::new(),
+            expected: Vec::new(),
+        };
+        for &amount in deposits {
+            let leg = basket.create_leg(context, amount).await;
+            mint_to(context, &leg.mint, &leg.alice, amount).await;
+            basket.deposits.push(leg);
+        }
+        for &amount in expected {
+            let leg = basket.create_leg(context, amount).await;
+            mint_to(context, &leg.mint, &leg.bob, amount).await;
+            basket.expected.push(leg);
+        }
+        basket
+    }
+
+    async fn create_leg(&self, context: &mut ProgramTestContext, amount: u64) -> Leg {
+        let mint = create_mint(context).await;
+        Leg {
+            mint,
+            alice: create_token_account(context, &mint, &self.alice.pubkey()).await,
+            bob: create_token_account(context, &mint, &self.bob.pubkey()).await,
+            amount,
+        }
+    }
+
+    fn pda(&self) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                b"escrow",
+                self.basket.as_ref(),
+                self.alice.pubkey().as_ref(),
+            ],
+            &solana_escrow_anchor::id(),
+        )
+        .0
+    }
+
+    fn vault(&self, mint: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"basket_vault", self.basket.as_ref(), mint.as_ref()],
+            &solana_escrow_anchor::id(),
+        )
+        .0
+    }
+
+    async fn open(&self, context: &mut ProgramTestContext) -> Result<(), BanksClientError> {
+        let slot = current_slot(context).await;
+        let mut accounts = solana_escrow_anchor::accounts::InitializeBasket {
+            initializer: self.alice.pubkey(),
+            basket: self.basket,
+            pda_account: self.pda(),
+            system_program: system_program::id(),
+            config: config_address(),
+        }
+        .to_account_metas(None);
+        for leg in &self.deposits {
+            accounts.extend([
+                AccountMeta::new_readonly(leg.mint, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new(leg.alice, false),
+                AccountMeta::new(self.vault(&leg.mint), false),
+            ]);
+        }
+        for leg in &self.expected {
+            accounts.push(AccountMeta::new_readonly(leg.alice, false));
+        }
+        let ix = Instruction {
+            program_id: solana_escrow_anchor::id(),
+            accounts,
+            data: solana_escrow_anchor::instruction::InitializeBasket {
+                seed: 0,
+                deposit_amounts: self.deposits.iter().map(|leg| leg.amount).collect(),
+                expected_amounts: self.expected.iter().map(|leg| leg.amount).collect(),
+                unlock_time: Some(slot),
+                time_out: Some(slot + 100),
+                allowed_taker: None,
+            }
+            .data(),
+        };
+        process(context, &[ix], &[&self.alice]).await
+    }
+
+    fn exchange_instruction(&self) -> Instruction {
+        let mut accounts = solana_escrow_anchor::accounts::ExchangeBasket {
+            taker: self.bob.pubkey(),
+            initializers_main_account: self.alice.pubkey(),
+            basket: self.basket,
+            pda_account: self.pda(),
+            system_program: system_program::id(),
+            config: config_address(),
+        }
+        .to_account_metas(None);
+        for leg in &self.deposits {
+            accounts.extend([
+                AccountMeta::new(leg.mint, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new(self.vault(&leg.mint), false),
+                AccountMeta::new(leg.bob, false),
+                AccountMeta::new(fee_vault_address(&leg.mint), false),
+            ]);
+        }
+        for leg in &self.expected {
+            accounts.extend([
+                AccountMeta::new_readonly(leg.mint, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new(leg.bob, false),
+                AccountMeta::new(leg.alice, false),
+                AccountMeta::new(fee_vault_address(&leg.mint), false),
+            ]);
+        }
+        Instruction {
+            program_id: solana_escrow_anchor::id(),
+            accounts,
+            data: solana_escrow_anchor::instruction::ExchangeBasket {}.data(),
+        }
+    }
+
+    async fn exchange(&self, context: &mut ProgramTestContext) -> Result<(), BanksClientError> {
+        let ix = self.exchange_instruction();
+        process(context, &[ix], &[&self.bob]).await
+    }
+
+    async fn cancel(&self, context: &mut ProgramTestContext) -> Result<(), BanksClientError> {
+        let mut accounts = solana_escrow_anchor::accounts::CancelBasket {
+            initializer: self.alice.pubkey(),
+            basket: self.basket,
+            pda_account: self.pda(),
+        }
+        .to_account_metas(None);
+        for leg in &self.deposits {
+            accounts.extend([
+                AccountMeta::new(leg.mint, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new(self.vault(&leg.mint), false),
+                AccountMeta::new(leg.alice, false),
+            ]);
+        }
+        let ix = Instruction {
+            program_id: solana_escrow_anchor::id(),
+            accounts,
+            data: solana_escrow_anchor::instruction::CancelBasket {}.data(),
+        };
+        process(context, &[ix], &[&self.alice]).await
+    }
+}
+
+#[tokio::test]
+async fn three_tokens_are_swapped_for_two_at_once() {
+    let mut context = common::start().await;
+    let basket = Basket::setup(&mut context, &[10, 20, 30], &[40, 50]).await;
+    basket.open(&mut context).await.unwrap();
+    for leg in &basket.deposits {
+        assert_eq!(
+            token_balance(&mut context, &basket.vault(&leg.mint)).await,
+            leg.amount
+        );
+    }
+
+    basket.exchange(&mut context).await.unwrap();
+
+    for leg in &basket.deposits {
+        assert_eq!(token_balance(&mut context, &leg.bob).await, leg.amount);
+        assert!(!account_exists(&mut context, &basket.vault(&leg.mint)).await);
+    }
+    for leg in &basket.expected {
+        assert_eq!(token_balance(&mut context, &leg.alice).await, leg.amount);
+    }
+    assert!(!account_exists(&mut context, &basket.basket).await);
+}
+
+#[tokio::test]
+async fn settlement_is_all_or_nothing() {
+    let mut context = common::start().await;
+    let basket = Basket::setup(&mut context, &[10, 20], &[40, 50]).await;
+    basket.open(&mut context).await.unwrap();
+
+    // Bob would receive the second token into an account of the first one
+    let mut ix = basket.exchange_instruction();
+    ix.accounts[6 + 5 + 3] = AccountMeta::new(basket.deposits[0].bob, false);
+    assert_error(
+        process(&mut context, &[ix], &[&basket.bob]).await,
+        ErrorCode::MintMismatch,
+    );
+
+    assert_eq!(
+        token_balance(&mut context, &basket.deposits[0].bob).await,
+        0
+    );
+    assert_eq!(
+        token_balance(&mut context, &basket.expected[0].bob).await,
+        40
+    );
+    assert!(account_exists(&mut context, &basket.basket).await);
+}
+
+#[tokio::test]
+async fn missing_leg_accounts_are_rejected() {
+    let mut context = common::start().await;
+    let basket = Basket::setup(&mut context, &[10, 20], &[40]).await;
+    basket.open(&mut context).await.unwrap();
+
+    let mut ix = basket.exchange_instruction();
+    ix.accounts.truncate(ix.accounts.len() - 5);
+    assert_error(
+        process(&mut context, &[ix], &[&basket.bob]).await,
+        ErrorCode::InvalidBasket,
+    );
+}
+
+#[tokio::test]
+async fn cancel_refunds_every_deposit() {
+    let mut context = common::start().await;
+    let basket = Basket::setup(&mut context, &[10, 20, 30], &[40]).await;
+    basket.open(&mut context).await.unwrap();
+
+    basket.cancel(&mut context).await.unwrap();
+
+    for leg in &basket.deposits {
+        assert_eq!(token_balance(&mut context, &leg.alice).await, leg.amount);
+        assert!(!account_exists(&mut context, &basket.vault(&leg.mint)).await);
+    }
+    assert!(!account_exists(&mut context, &basket.basket).await);
+}
+
+#[tokio::test]
+async fn protocol_fee_is_skimmed_from_every_leg() {
+    let mut context = common::start().await;
+    let admin = context.payer.insecure_clone();
+    set_fee(&mut context, &admin, 100).await.unwrap();
+    let basket = Basket::setup(&mut context, &[1_000, 2_000], &[500]).await;
+    basket.open(&mut context).await.unwrap();
+
+    basket.exchange(&mut context).await.unwrap();
+
+    assert_eq!(
+        token_balance(&mut context, &basket.deposits[0].bob).await,
+        990
+    );
+    assert_eq!(
+        token_balance(&mut context, &basket.deposits[1].bob).await,
+        1_980
+    );
+    assert_eq!(
+        token_balance(&mut context, &basket.expected[0].alice).await,
+        495
+    );
+    for (leg, fee) in basket
+        .deposits
+        .iter()
+        .chain(&basket.expected)
+        .zip([10, 20, 5])
+    {
+        assert_eq!(
+            token_balance(&mut context, &fee_vault_address(&leg.mint)).await,
+            fee
+        );
+    }
+}
+
+#[tokio::test]
+async fn baskets_are_bounded_and_hold_each_mint_once() {
+    let mut context = common::start().await;
+    let basket = Basket::setup(&mut context, &[1, 1, 1, 1, 1], &[1]).await;
+    assert_error(basket.open(&mut context).await, ErrorCode::InvalidBasket);
+
+    let mut basket = Basket::setup(&mut context, &[10], &[40, 50]).await;
+    basket.expected[1].alice = basket.expected[0].alice;
+    assert_error(basket.open(&mut context).await, ErrorCode::InvalidBasket);
+}
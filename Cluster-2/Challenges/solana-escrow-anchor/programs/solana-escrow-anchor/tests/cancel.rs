@@ -0,0 +1,62 @@
+mod common;
+
+use common::{
+    account_exists, assert_instruction_error, create_token_account, current_slot, token_balance,
+    warp_to_slot, Trade,
+};
+use solana_sdk::{instruction::InstructionError, signature::Signer};
+
+#[tokio::test]
+async fn cancel_refunds_the_whole_deposit() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    trade.cancel(&mut context, trade.alice_x).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 50);
+    assert!(!account_exists(&mut context, &trade.vault).await);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn cancel_works_after_time_out() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), Some(slot + 10))
+        .await
+        .unwrap();
+    warp_to_slot(&mut context, slot + 20).await;
+
+    trade.cancel(&mut context, trade.alice_x).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 50);
+}
+
+#[tokio::test]
+async fn cancel_rejects_a_refund_account_of_someone_else() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    assert_instruction_error(
+        trade.cancel(&mut context, trade.bob_x).await,
+        InstructionError::InvalidAccountData,
+    );
+}
+
+#[tokio::test]
+async fn cancel_rejects_a_refund_account_of_another_mint() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    let alice_other_y =
+        create_token_account(&mut context, &trade.mint_y, &trade.alice.pubkey()).await;
+    assert_instruction_error(
+        trade.cancel(&mut context, alice_other_y).await,
+        InstructionError::InvalidAccountData,
+    );
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::{config_address, Trade};
+use solana_client::rpc_filter::RpcFilterType;
+use solana_escrow_anchor::client::{self, decode_escrow, open_escrow_filters, ClientError};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    pubkey::Pubkey,
+    signature::Signer,
+};
+
+async fn account(context: &mut ProgramTestContext, address: &Pubkey) -> Account {
+    context
+        .banks_client
+        .get_account(*address)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+fn matches(filters: &[RpcFilterType], account: &Account) -> bool {
+    let account = AccountSharedData::from(account.clone());
+    filters.iter().all(|filter| filter.allows(&account))
+}
+
+#[tokio::test]
+async fn client_derives_the_addresses_of_the_trade() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    assert_eq!(
+        client::escrow_address(&trade.alice.pubkey(), trade.seed),
+        trade.escrow
+    );
+    assert_eq!(client::vault_address(&trade.escrow), trade.vault);
+    assert_eq!(
+        client::pda_address(&trade.escrow, &trade.alice.pubkey()),
+        trade.pda()
+    );
+    assert_eq!(client::config_address(), config_address());
+}
+
+#[tokio::test]
+async fn open_escrow_filters_match_escrows_only() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    let escrow = account(&mut context, &trade.escrow).await;
+    assert!(matches(&open_escrow_filters(None), &escrow));
+    assert!(matches(
+        &open_escrow_filters(Some(&trade.alice.pubkey())),
+        &escrow
+    ));
+    assert!(!matches(
+        &open_escrow_filters(Some(&trade.bob.pubkey())),
+        &escrow
+    ));
+
+    // Other accounts of the program and the vault it owns through the PDA
+    for other in [config_address(), trade.vault] {
+        let other = account(&mut context, &other).await;
+        assert!(!matches(&open_escrow_filters(None), &other));
+    }
+}
+
+#[tokio::test]
+async fn escrows_are_decoded_from_their_accounts() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    let escrow = account(&mut context, &trade.escrow).await;
+    let state = decode_escrow(&trade.escrow, &escrow).unwrap();
+    assert_eq!(state.initializer_pubkey, trade.alice.pubkey());
+    assert_eq!(state.temp_token_account_pubkey, trade.vault);
+    assert_eq!(state.remaining_amount, 50);
+
+    // The same data owned by another program isn't one of its escrows
+    let mut foreign = escrow.clone();
+    foreign.owner = Pubkey::new_unique();
+    assert!(matches!(
+        decode_escrow(&trade.escrow, &foreign),
+        Err(ClientError::NotAnEscrow(address)) if address == trade.escrow
+    ));
+    let config = account(&mut context, &config_address()).await;
+    assert!(matches!(
+        decode_escrow(&config_address(), &config),
+        Err(ClientError::NotAnEscrow(_))
+    ));
+}
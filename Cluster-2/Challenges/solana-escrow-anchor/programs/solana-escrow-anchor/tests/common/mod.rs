@@ -0,0 +1,913 @@
+#![allow(dead_code)]
+
+use anchor_lang::{AccountDeserialize, Event, InstructionData, ToAccountMetas};
+use anchor_spl::metadata::mpl_token_metadata;
+use anchor_spl::token::spl_token::{self, native_mint};
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{
+        transfer_fee::instruction as transfer_fee_instruction, BaseStateWithExtensions,
+        ExtensionType, StateWithExtensions,
+    },
+};
+use base64::{engine::general_purpose, Engine};
+use solana_escrow_anchor::{ErrorCode, EscrowConfig, NftTerms};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    account_info::AccountInfo,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{Instruction, InstructionError},
+    program_pack::Pack,
+    program_stubs::{self, SyscallStubs},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program, sysvar,
+    transaction::{Transaction, TransactionError},
+};
+use std::sync::Once;
+
+// Anchor's entry wants the accounts slice and the account infos to share one lifetime, which the
+// program-test processor doesn't promise
+fn process_instruction<'a, 'info>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'info>],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // SAFETY: the slice outlives the call and the program keeps no reference to it beyond that
+    let accounts: &'info [AccountInfo<'info>] = unsafe { std::mem::transmute(accounts) };
+    solana_escrow_anchor::entry(program_id, accounts, instruction_data)
+}
+
+// What the events written with `sol_log_data` show up as in the transaction logs
+const EVENT_LOG_PREFIX: &str = "Program log: Program data: ";
+
+// The program runs natively in the tests, where program-test drops the data written with
+// `sol_log_data`. Its syscall stubs are wrapped to log it like the runtime would
+struct LogData {
+    inner: Box<dyn SyscallStubs>,
+}
+
+impl SyscallStubs for LogData {
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| general_purpose::STANDARD.encode(field))
+            .collect();
+        self.inner
+            .sol_log(&format!("Program data: {}", fields.join(" ")))
+    }
+
+    fn sol_log(&self, message: &str) {
+        self.inner.sol_log(message)
+    }
+    fn sol_log_compute_units(&self) {
+        self.inner.sol_log_compute_units()
+    }
+    fn sol_remaining_compute_units(&self) -> u64 {
+        self.inner.sol_remaining_compute_units()
+    }
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        self.inner
+            .sol_invoke_signed(instruction, account_infos, signers_seeds)
+    }
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_clock_sysvar(var_addr)
+    }
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_epoch_schedule_sysvar(var_addr)
+    }
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_fees_sysvar(var_addr)
+    }
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_rent_sysvar(var_addr)
+    }
+    fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_epoch_rewards_sysvar(var_addr)
+    }
+    fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_last_restart_slot(var_addr)
+    }
+    unsafe fn sol_memcpy(&self, dst: *mut u8, src: *const u8, n: usize) {
+        self.inner.sol_memcpy(dst, src, n)
+    }
+    unsafe fn sol_memmove(&self, dst: *mut u8, src: *const u8, n: usize) {
+        self.inner.sol_memmove(dst, src, n)
+    }
+    unsafe fn sol_memcmp(&self, s1: *const u8, s2: *const u8, n: usize, result: *mut i32) {
+        self.inner.sol_memcmp(s1, s2, n, result)
+    }
+    unsafe fn sol_memset(&self, s: *mut u8, c: u8, n: usize) {
+        self.inner.sol_memset(s, c, n)
+    }
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        self.inner.sol_get_return_data()
+    }
+    fn sol_set_return_data(&self, data: &[u8]) {
+        self.inner.sol_set_return_data(data)
+    }
+    fn sol_get_processed_sibling_instruction(&self, index: usize) -> Option<Instruction> {
+        self.inner.sol_get_processed_sibling_instruction(index)
+    }
+    fn sol_get_stack_height(&self) -> u64 {
+        self.inner.sol_get_stack_height()
+    }
+}
+
+// Program-test installs its stubs when the first test starts. Every test waits here before
+// running a transaction, so none runs while the stubs are swapped
+fn log_data() {
+    struct Swapping;
+    impl SyscallStubs for Swapping {}
+
+    static LOG_DATA: Once = Once::new();
+    LOG_DATA.call_once(|| {
+        let inner = program_stubs::set_syscall_stubs(Box::new(Swapping));
+        program_stubs::set_syscall_stubs(Box::new(LogData { inner }));
+    });
+}
+
+/// The events of type `E` in the logs of a transaction, in order
+pub fn events<E: Event>(logs: &[String]) -> Vec<E> {
+    logs.iter()
+        .filter_map(|log| log.strip_prefix(EVENT_LOG_PREFIX))
+        .filter_map(|data| general_purpose::STANDARD.decode(data).ok())
+        .filter(|data| data.starts_with(&E::discriminator()))
+        .map(|data| E::try_from_slice(&data[8..]).unwrap())
+        .collect()
+}
+
+/// Starts the program with its config created, the payer being the admin and fee recipient
+/// and no protocol fee
+pub async fn start() -> ProgramTestContext {
+    let mut context = start_without_config().await;
+    let admin = context.payer.insecure_clone();
+    init_config(&mut context, &admin).await.unwrap();
+    context
+}
+
+/// Starts the program as deployed by the payer, its upgrade authority, before any config
+pub async fn start_without_config() -> ProgramTestContext {
+    let mut context = ProgramTest::new(
+        "solana_escrow_anchor",
+        solana_escrow_anchor::id(),
+        processor!(process_instruction),
+    )
+    .start_with_context()
+    .await;
+    log_data();
+    // The program runs natively, the program data the loader would keep for it is written here.
+    // Its lamports come from the payer, lamports out of nowhere fail the bank checks on warps
+    let lamports = 1_000_000_000;
+    let ix =
+        system_instruction::transfer(&context.payer.pubkey(), &program_data_address(), lamports);
+    process(&mut context, &[ix], &[]).await.unwrap();
+    let program_data = UpgradeableLoaderState::ProgramData {
+        slot: 0,
+        upgrade_authority_address: Some(context.payer.pubkey()),
+    };
+    context.set_account(
+        &program_data_address(),
+        &Account::new_data(lamports, &program_data, &bpf_loader_upgradeable::id())
+            .unwrap()
+            .into(),
+    );
+    context
+}
+
+/// Creates the config with `admin` as the admin and fee recipient and no protocol fee
+pub async fn init_config(
+    context: &mut ProgramTestContext,
+    admin: &Keypair,
+) -> Result<(), BanksClientError> {
+    let ix = Instruction {
+        program_id: solana_escrow_anchor::id(),
+        accounts: solana_escrow_anchor::accounts::InitConfig {
+            admin: admin.pubkey(),
+            config: config_address(),
+            fee_recipient: admin.pubkey(),
+            system_program: system_program::id(),
+            program_data: program_data_address(),
+        }
+        .to_account_metas(None),
+        data: solana_escrow_anchor::instruction::InitConfig {
+            fee_basis_points: 0,
+        }
+        .data(),
+    };
+    process(context, &[ix], &[admin]).await
+}
+
+pub fn program_data_address() -> Pubkey {
+    Pubkey::find_program_address(
+        &[solana_escrow_anchor::id().as_ref()],
+        &bpf_loader_upgradeable::id(),
+    )
+    .0
+}
+
+pub async fn set_fee(
+    context: &mut ProgramTestContext,
+    admin: &Keypair,
+    fee_basis_points: u16,
+) -> Result<(), BanksClientError> {
+    let ix = Instruction {
+        program_id: solana_escrow_anchor::id(),
+        accounts: solana_escrow_anchor::accounts::UpdateConfig {
+            admin: admin.pubkey(),
+            config: config_address(),
+        }
+        .to_account_metas(None),
+        data: solana_escrow_anchor::instruction::SetFee { fee_basis_points }.data(),
+    };
+    process(context, &[ix], &[admin]).await
+}
+
+pub async fn set_paused(
+    context: &mut ProgramTestContext,
+    admin: &Keypair,
+    paused: bool,
+) -> Result<(), BanksClientError> {
+    let ix = Instruction {
+        program_id: solana_escrow_anchor::id(),
+        accounts: solana_escrow_anchor::accounts::UpdateConfig {
+            admin: admin.pubkey(),
+            config: config_address(),
+        }
+        .to_account_metas(None),
+        data: solana_escrow_anchor::instruction::SetPaused { paused }.data(),
+    };
+    process(context, &[ix], &[admin]).await
+}
+
+pub async fn process(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        context.last_blockhash,
+    );
+    context.banks_client.process_transaction(transaction).await
+}
+
+/// Processes the instructions like `process`, returning the logs of the transaction
+pub async fn process_with_logs(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<Vec<String>, BanksClientError> {
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        context.last_blockhash,
+    );
+    let outcome = context
+        .banks_client
+        .process_transaction_with_metadata(transaction)
+        .await?;
+    outcome.result?;
+    Ok(outcome.metadata.unwrap().log_messages)
+}
+
+pub async fn warp_to_slot(context: &mut ProgramTestContext, slot: u64) {
+    context.warp_to_slot(slot).unwrap();
+    context.last_blockhash = context.get_new_latest_blockhash().await.unwrap();
+}
+
+pub async fn current_slot(context: &mut ProgramTestContext) -> u64 {
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+    clock.slot
+}
+
+pub async fn create_account(context: &mut ProgramTestContext, owner: &Keypair) {
+    let ix = system_instruction::transfer(&context.payer.pubkey(), &owner.pubkey(), 1_000_000_000);
+    process(context, &[ix], &[]).await.unwrap();
+}
+
+pub async fn create_mint(context: &mut ProgramTestContext) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &context.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &context.payer.pubkey(),
+            None,
+            0,
+        )
+        .unwrap(),
+    ];
+    process(context, &instructions, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+/// Token-2022 mint withholding `transfer_fee_basis_points` of every transfer
+pub async fn create_mint_2022(
+    context: &mut ProgramTestContext,
+    transfer_fee_basis_points: u16,
+) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let space = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[
+        ExtensionType::TransferFeeConfig,
+    ])
+    .unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &context.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(space),
+            space as u64,
+            &spl_token_2022::id(),
+        ),
+        transfer_fee_instruction::initialize_transfer_fee_config(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            None,
+            None,
+            transfer_fee_basis_points,
+            u64::MAX,
+        )
+        .unwrap(),
+        spl_token_2022::instruction::initialize_mint(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            &context.payer.pubkey(),
+            None,
+            0,
+        )
+        .unwrap(),
+    ];
+    process(context, &instructions, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+/// The token program owning the mint, the original one or Token-2022
+pub async fn token_program_of(context: &mut ProgramTestContext, mint: &Pubkey) -> Pubkey {
+    context
+        .banks_client
+        .get_account(*mint)
+        .await
+        .unwrap()
+        .unwrap()
+        .owner
+}
+
+pub async fn create_token_account(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    let account = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let mint_account = context
+        .banks_client
+        .get_account(*mint)
+        .await
+        .unwrap()
+        .unwrap();
+    let token_program = mint_account.owner;
+    let mint_extensions =
+        StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_account.data)
+            .unwrap()
+            .get_extension_types()
+            .unwrap();
+    let space = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(
+        &ExtensionType::get_required_init_account_extensions(&mint_extensions),
+    )
+    .unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &context.payer.pubkey(),
+            &account.pubkey(),
+            rent.minimum_balance(space),
+            space as u64,
+            &token_program,
+        ),
+        spl_token_2022::instruction::initialize_account(
+            &token_program,
+            &account.pubkey(),
+            mint,
+            owner,
+        )
+        .unwrap(),
+    ];
+    process(context, &instructions, &[&account]).await.unwrap();
+    account.pubkey()
+}
+
+/// Token account of `owner` for the mint, or the wallet itself for SOL
+pub async fn create_leg_account(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Keypair,
+) -> Pubkey {
+    if *mint == native_mint::id() {
+        owner.pubkey()
+    } else {
+        create_token_account(context, mint, &owner.pubkey()).await
+    }
+}
+
+pub async fn mint_to(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    account: &Pubkey,
+    amount: u64,
+) {
+    let token_program = token_program_of(context, mint).await;
+    let ix = spl_token_2022::instruction::mint_to(
+        &token_program,
+        mint,
+        account,
+        &context.payer.pubkey(),
+        &[],
+        amount,
+    )
+    .unwrap();
+    process(context, &[ix], &[]).await.unwrap();
+}
+
+pub async fn account_exists(context: &mut ProgramTestContext, account: &Pubkey) -> bool {
+    context
+        .banks_client
+        .get_account(*account)
+        .await
+        .unwrap()
+        .is_some()
+}
+
+pub async fn lamports(context: &mut ProgramTestContext, account: &Pubkey) -> u64 {
+    context.banks_client.get_balance(*account).await.unwrap()
+}
+
+pub async fn token_balance(context: &mut ProgramTestContext, account: &Pubkey) -> u64 {
+    let account = context
+        .banks_client
+        .get_account(*account)
+        .await
+        .unwrap()
+        .unwrap();
+    StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+        .unwrap()
+        .base
+        .amount
+}
+
+/// Rewrites the layout version of an escrow, as if it had been created by another release
+pub async fn set_escrow_version(context: &mut ProgramTestContext, escrow: &Pubkey, version: u8) {
+    let mut account = context
+        .banks_client
+        .get_account(*escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    // After the discriminator and the initialized flag
+    account.data[9] = version;
+    context.set_account(escrow, &account.into());
+}
+
+pub fn assert_instruction_error(result: Result<(), BanksClientError>, error: InstructionError) {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(_, other) => assert_eq!(other, error),
+        other => panic!("expected {:?}, got {:?}", error, other),
+    }
+}
+
+pub fn assert_error(result: Result<(), BanksClientError>, error: ErrorCode) {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            assert_eq!(code, u32::from(error))
+        }
+        other => panic!("expected {:?}, got {:?}", error, other),
+    }
+}
+
+pub fn escrow_address(initializer: &Pubkey, seed: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"escrow", initializer.as_ref(), &seed.to_le_bytes()],
+        &solana_escrow_anchor::id(),
+    )
+    .0
+}
+
+pub fn config_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"config"], &solana_escrow_anchor::id()).0
+}
+
+pub fn fee_vault_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"fee_vault", mint.as_ref()], &solana_escrow_anchor::id()).0
+}
+
+pub async fn protocol_fee_basis_points(context: &mut ProgramTestContext) -> u16 {
+    let account = context
+        .banks_client
+        .get_account(config_address())
+        .await
+        .unwrap()
+        .unwrap();
+    EscrowConfig::try_deserialize(&mut account.data.as_slice())
+        .unwrap()
+        .fee_basis_points
+}
+
+pub fn metadata_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"metadata", mpl_token_metadata::ID.as_ref(), mint.as_ref()],
+        &mpl_token_metadata::ID,
+    )
+    .0
+}
+
+/// Writes the Metaplex metadata of `mint`, with a verified `collection` and `creator` when given.
+/// The metadata program isn't loaded, so the account is borsh encoded here
+pub fn set_metadata(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    collection: Option<Pubkey>,
+    creator: Option<Pubkey>,
+) {
+    let mut data = vec![mpl_token_metadata::types::Key::MetadataV1 as u8];
+    data.extend_from_slice(context.payer.pubkey().as_ref());
+    data.extend_from_slice(mint.as_ref());
+    // Empty name, symbol and uri, no seller fee
+    data.extend_from_slice(&[0; 4 * 3 + 2]);
+    match creator {
+        Some(creator) => {
+            data.extend_from_slice(&[1, 1, 0, 0, 0]);
+            data.extend_from_slice(creator.as_ref());
+            data.extend_from_slice(&[1, 100]);
+        }
+        None => data.push(0),
+    }
+    // Primary sale, mutability, edition nonce and token standard
+    data.extend_from_slice(&[0, 1, 0, 0]);
+    match collection {
+        Some(collection) => {
+            data.extend_from_slice(&[1, 1]);
+            data.extend_from_slice(collection.as_ref());
+        }
+        None => data.push(0),
+    }
+    // Uses, collection details and programmable config
+    data.extend_from_slice(&[0, 0, 0]);
+
+    context.set_account(
+        &metadata_address(mint),
+        &Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: mpl_token_metadata::ID,
+            executable: false,
+            rent_epoch: 0,
+        }
+        .into(),
+    );
+}
+
+pub fn vault_address(escrow: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"vault", escrow.as_ref()], &solana_escrow_anchor::id()).0
+}
+
+/// Alice offers `deposit` X tokens for `expected` Y tokens, Bob is the taker
+pub struct Trade {
+    pub alice: Keypair,
+    pub bob: Keypair,
+    pub mint_x: Pubkey,
+    pub mint_y: Pubkey,
+    token_program_x: Pubkey,
+    token_program_y: Pubkey,
+    pub alice_x: Pubkey,
+    pub alice_y: Pubkey,
+    pub bob_x: Pubkey,
+    pub bob_y: Pubkey,
+    pub vault: Pubkey,
+    pub seed: u64,
+    pub escrow: Pubkey,
+    deposit: u64,
+    expected: u64,
+    pub allowed_taker: Option<Pubkey>,
+    pub deposit_nft: Option<NftTerms>,
+    pub expected_nft: Option<NftTerms>,
+    /// The expected NFT is left unpinned, any one meeting its terms will do
+    pub any_expected_nft: bool,
+    /// The most Bob agrees to pay for a fill
+    pub max_pay_amount: u64,
+}
+
+impl Trade {
+    pub async fn setup(context: &mut ProgramTestContext, deposit: u64, expected: u64) -> Self {
+        let mint_x = create_mint(context).await;
+        let mint_y = create_mint(context).await;
+        Self::setup_with_mints(context, mint_x, mint_y, deposit, expected).await
+    }
+
+    pub async fn setup_with_mints(
+        context: &mut ProgramTestContext,
+        mint_x: Pubkey,
+        mint_y: Pubkey,
+        deposit: u64,
+        expected: u64,
+    ) -> Self {
+        let alice = Keypair::new();
+        let bob = Keypair::new();
+        create_account(context, &alice).await;
+        create_account(context, &bob).await;
+
+        let token_program_x = token_program_of(context, &mint_x).await;
+        let token_program_y = token_program_of(context, &mint_y).await;
+        let alice_x = create_leg_account(context, &mint_x, &alice).await;
+        let alice_y = create_leg_account(context, &mint_y, &alice).await;
+        let bob_x = create_leg_account(context, &mint_x, &bob).await;
+        let bob_y = create_leg_account(context, &mint_y, &bob).await;
+        let seed = 0;
+        let escrow = escrow_address(&alice.pubkey(), seed);
+        let vault = vault_address(&escrow);
+        // SOL sides are paid from the lamports the wallets were created with
+        if mint_x != native_mint::id() {
+            mint_to(context, &mint_x, &alice_x, deposit).await;
+        }
+        if mint_y != native_mint::id() {
+            mint_to(context, &mint_y, &bob_y, expected).await;
+        }
+
+        Trade {
+            alice,
+            bob,
+            mint_x,
+            mint_y,
+            token_program_x,
+            token_program_y,
+            alice_x,
+            alice_y,
+            bob_x,
+            bob_y,
+            vault,
+            seed,
+            escrow,
+            deposit,
+            expected,
+            allowed_taker: None,
+            deposit_nft: None,
+            expected_nft: None,
+            any_expected_nft: false,
+            max_pay_amount: u64::MAX,
+        }
+    }
+
+    /// Points the trade at the escrow and vault of another seed of the same initializer
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.escrow = escrow_address(&self.alice.pubkey(), seed);
+        self.vault = vault_address(&self.escrow);
+    }
+
+    /// The token account of a side of the trade, left out when that side is SOL
+    fn leg(&self, mint: &Pubkey, account: Pubkey) -> Option<Pubkey> {
+        if *mint == native_mint::id() {
+            None
+        } else {
+            Some(account)
+        }
+    }
+
+    /// The metadata of an NFT side, only passed when its terms name a collection or creator
+    fn metadata(&self, terms: &Option<NftTerms>, mint: &Pubkey) -> Option<Pubkey> {
+        terms
+            .as_ref()
+            .filter(|terms| terms.collection.is_some() || terms.creator.is_some())
+            .map(|_| metadata_address(mint))
+    }
+
+    pub fn pda(&self) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                b"escrow",
+                self.escrow.as_ref(),
+                self.alice.pubkey().as_ref(),
+            ],
+            &solana_escrow_anchor::id(),
+        )
+        .0
+    }
+
+    pub async fn initialize(
+        &self,
+        context: &mut ProgramTestContext,
+        unlock_time: Option<u64>,
+        time_out: Option<u64>,
+    ) -> Result<(), BanksClientError> {
+        let ix = self.initialize_instruction(unlock_time, time_out);
+        process(context, &[ix], &[&self.alice]).await
+    }
+
+    pub fn initialize_instruction(
+        &self,
+        unlock_time: Option<u64>,
+        time_out: Option<u64>,
+    ) -> Instruction {
+        Instruction {
+            program_id: solana_escrow_anchor::id(),
+            accounts: solana_escrow_anchor::accounts::Initialize {
+                initializer: self.alice.pubkey(),
+                mint_a: self.mint_x,
+                initializers_deposit_token_account: self.leg(&self.mint_x, self.alice_x),
+                // Whichever NFT Bob brings isn't known yet
+                token_to_receive_account: self
+                    .leg(&self.mint_y, self.alice_y)
+                    .filter(|_| !self.any_expected_nft),
+                escrow_account: self.escrow,
+                vault: self.leg(&self.mint_x, self.vault),
+                pda_account: self.pda(),
+                token_program: self.token_program_x,
+                system_program: system_program::id(),
+                rent: sysvar::rent::id(),
+                config: config_address(),
+                deposit_metadata: self.metadata(&self.deposit_nft, &self.mint_x),
+            }
+            .to_account_metas(None),
+            data: solana_escrow_anchor::instruction::Initialize {
+                seed: self.seed,
+                amount: self.expected,
+                deposit_amount: self.deposit,
+                unlock_time,
+                time_out,
+                allowed_taker: self.allowed_taker,
+                deposit_nft: self.deposit_nft.clone(),
+                expected_nft: self.expected_nft.clone(),
+            }
+            .data(),
+        }
+    }
+
+    pub async fn exchange(&self, context: &mut ProgramTestContext) -> Result<(), BanksClientError> {
+        self.fill(context, self.deposit).await
+    }
+
+    pub async fn fill(
+        &self,
+        context: &mut ProgramTestContext,
+        amount: u64,
+    ) -> Result<(), BanksClientError> {
+        let ix = self.fill_instruction(context, amount).await;
+        process(context, &[ix], &[&self.bob]).await
+    }
+
+    pub async fn fill_instruction(
+        &self,
+        context: &mut ProgramTestContext,
+        amount: u64,
+    ) -> Instruction {
+        // Fee vaults are only needed while a fee is taken, otherwise the taker would pay their rent
+        let fee_taken = protocol_fee_basis_points(context).await > 0;
+        let fee_vault = |mint: &Pubkey| {
+            self.leg(mint, fee_vault_address(mint))
+                .filter(|_| fee_taken)
+        };
+        Instruction {
+            program_id: solana_escrow_anchor::id(),
+            accounts: solana_escrow_anchor::accounts::Exchange {
+                taker: self.bob.pubkey(),
+                takers_sending_token_account: self.leg(&self.mint_y, self.bob_y),
+                takers_token_to_receive_account: self.leg(&self.mint_x, self.bob_x),
+                pdas_temp_token_account: self.leg(&self.mint_x, self.vault),
+                initializers_main_account: self.alice.pubkey(),
+                initializers_token_to_receive_account: self.leg(&self.mint_y, self.alice_y),
+                escrow_account: self.escrow,
+                token_program: self.token_program_x,
+                pda_account: self.pda(),
+                mint_a: self.mint_x,
+                mint_b: self.mint_y,
+                token_program_b: self.token_program_y,
+                system_program: system_program::id(),
+                config: config_address(),
+                fee_vault_a: fee_vault(&self.mint_x),
+                fee_vault_b: fee_vault(&self.mint_y),
+                expected_metadata: self.metadata(&self.expected_nft, &self.mint_y),
+            }
+            .to_account_metas(None),
+            data: solana_escrow_anchor::instruction::Exchange {
+                amount_expected_by_taker: amount,
+                max_pay_amount: self.max_pay_amount,
+            }
+            .data(),
+        }
+    }
+
+    pub async fn cancel(
+        &self,
+        context: &mut ProgramTestContext,
+        refund_token_account: Pubkey,
+    ) -> Result<(), BanksClientError> {
+        let ix = self.cancel_instruction(refund_token_account);
+        process(context, &[ix], &[&self.alice]).await
+    }
+
+    pub fn cancel_instruction(&self, refund_token_account: Pubkey) -> Instruction {
+        Instruction {
+            program_id: solana_escrow_anchor::id(),
+            accounts: solana_escrow_anchor::accounts::Cancel {
+                initializer: self.alice.pubkey(),
+                pdas_temp_token_account: self.leg(&self.mint_x, self.vault),
+                initializers_refund_token_account: self.leg(&self.mint_x, refund_token_account),
+                escrow_account: self.escrow,
+                token_program: self.token_program_x,
+                pda_account: self.pda(),
+                mint_a: self.mint_x,
+            }
+            .to_account_metas(None),
+            data: solana_escrow_anchor::instruction::CancelEscrow {}.data(),
+        }
+    }
+
+    pub async fn reset_time_lock(
+        &self,
+        context: &mut ProgramTestContext,
+    ) -> Result<(), BanksClientError> {
+        let ix = self.reset_time_lock_instruction();
+        process(context, &[ix], &[&self.alice]).await
+    }
+
+    pub fn reset_time_lock_instruction(&self) -> Instruction {
+        Instruction {
+            program_id: solana_escrow_anchor::id(),
+            accounts: solana_escrow_anchor::accounts::Reset {
+                initializer: self.alice.pubkey(),
+                escrow_account: self.escrow,
+                config: config_address(),
+            }
+            .to_account_metas(None),
+            data: solana_escrow_anchor::instruction::ResetTimeLock {}.data(),
+        }
+    }
+}
+
+/// Changes to the terms of the trade, nothing changes by default
+#[derive(Default)]
+pub struct Update {
+    pub expected_amount: Option<u64>,
+    pub time_out: Option<u64>,
+    pub allowed_taker: Option<Option<Pubkey>>,
+    pub top_up_amount: u64,
+    pub withdraw_amount: u64,
+}
+
+pub async fn update(
+    context: &mut ProgramTestContext,
+    trade: &Trade,
+    initializer: &Keypair,
+    update: Update,
+) -> Result<(), BanksClientError> {
+    let ix = update_instruction(trade, initializer, update);
+    process(context, &[ix], &[initializer]).await
+}
+
+pub fn update_instruction(trade: &Trade, initializer: &Keypair, update: Update) -> Instruction {
+    Instruction {
+        program_id: solana_escrow_anchor::id(),
+        accounts: solana_escrow_anchor::accounts::UpdateEscrow {
+            initializer: initializer.pubkey(),
+            escrow_account: trade.escrow,
+            pdas_temp_token_account: trade.leg(&trade.mint_x, trade.vault),
+            initializers_token_account: trade.leg(&trade.mint_x, trade.alice_x),
+            mint_a: trade.mint_x,
+            token_program: trade.token_program_x,
+            pda_account: trade.pda(),
+            system_program: system_program::id(),
+            config: config_address(),
+        }
+        .to_account_metas(None),
+        data: solana_escrow_anchor::instruction::UpdateEscrow {
+            expected_amount: update.expected_amount,
+            time_out: update.time_out,
+            allowed_taker: update.allowed_taker,
+            top_up_amount: update.top_up_amount,
+            withdraw_amount: update.withdraw_amount,
+        }
+        .data(),
+    }
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use anchor_lang::AccountDeserialize;
+use anchor_spl::token::spl_token;
+use common::{
+    account_exists, assert_error, assert_instruction_error, current_slot, lamports,
+    set_escrow_version, token_balance, warp_to_slot, Trade,
+};
+use solana_escrow_anchor::{ErrorCode, Escrow};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    instruction::InstructionError, program_pack::Pack, pubkey::Pubkey, signature::Signer,
+};
+
+// The terms of the TypeScript suite: Alice offers 5 X tokens for 3 Y tokens
+const DEPOSIT: u64 = 5;
+const EXPECTED: u64 = 3;
+
+async fn escrow_state(context: &mut ProgramTestContext, trade: &Trade) -> Escrow {
+    let account = context
+        .banks_client
+        .get_account(trade.escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    Escrow::try_deserialize(&mut account.data.as_slice()).unwrap()
+}
+
+async fn token_owner(context: &mut ProgramTestContext, account: &Pubkey) -> Pubkey {
+    let account = context
+        .banks_client
+        .get_account(*account)
+        .await
+        .unwrap()
+        .unwrap();
+    spl_token::state::Account::unpack(&account.data)
+        .unwrap()
+        .owner
+}
+
+#[tokio::test]
+async fn initialize_moves_the_deposit_into_a_vault_of_the_pda() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, DEPOSIT, EXPECTED).await;
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), Some(slot + 100))
+        .await
+        .unwrap();
+
+    let escrow = escrow_state(&mut context, &trade).await;
+    assert!(escrow.is_initialized);
+    assert_eq!(escrow.initializer_pubkey, trade.alice.pubkey());
+    assert_eq!(escrow.temp_token_account_pubkey, trade.vault);
+    assert_eq!(
+        escrow.initializer_token_to_receive_account_pubkey,
+        trade.alice_y
+    );
+    assert_eq!(escrow.mint_a, trade.mint_x);
+    assert_eq!(escrow.mint_b, trade.mint_y);
+    assert_eq!(escrow.expected_amount, EXPECTED);
+    assert_eq!(escrow.remaining_amount, DEPOSIT);
+    assert_eq!((escrow.unlock_time, escrow.time_out), (slot, slot + 100));
+
+    assert_eq!(token_owner(&mut context, &trade.vault).await, trade.pda());
+    assert_eq!(token_balance(&mut context, &trade.vault).await, DEPOSIT);
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 0);
+}
+
+#[tokio::test]
+async fn every_escrow_vault_has_its_own_authority() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, DEPOSIT, EXPECTED).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+    let first_pda = trade.pda();
+
+    // Not the amount minted by the setup, which would be the same transaction
+    common::mint_to(&mut context, &trade.mint_x, &trade.alice_x, DEPOSIT * 2).await;
+    trade.set_seed(1);
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    assert_ne!(trade.pda(), first_pda);
+    assert_eq!(token_owner(&mut context, &trade.vault).await, trade.pda());
+}
+
+#[tokio::test]
+async fn exchange_swaps_the_tokens_and_closes_the_escrow() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, DEPOSIT, EXPECTED).await;
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), Some(slot + 100))
+        .await
+        .unwrap();
+    let alice_lamports = lamports(&mut context, &trade.alice.pubkey()).await;
+    let rent =
+        lamports(&mut context, &trade.escrow).await + lamports(&mut context, &trade.vault).await;
+
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, EXPECTED);
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, DEPOSIT);
+    assert_eq!(token_balance(&mut context, &trade.bob_y).await, 0);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+    assert!(!account_exists(&mut context, &trade.vault).await);
+    // The rent of both accounts goes back to Alice
+    assert_eq!(
+        lamports(&mut context, &trade.alice.pubkey()).await,
+        alice_lamports + rent
+    );
+}
+
+#[tokio::test]
+async fn locked_escrow_is_taken_once_its_time_lock_is_reset() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, DEPOSIT, EXPECTED).await;
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot + 10), Some(slot + 20))
+        .await
+        .unwrap();
+    assert_error(
+        trade.exchange(&mut context).await,
+        ErrorCode::EscrowTradeLocked,
+    );
+
+    // Still locked, then reset to the default window opening 100 slots later
+    warp_to_slot(&mut context, slot + 5).await;
+    trade.reset_time_lock(&mut context).await.unwrap();
+    let escrow = escrow_state(&mut context, &trade).await;
+    warp_to_slot(&mut context, escrow.unlock_time).await;
+
+    trade.exchange(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, EXPECTED);
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, DEPOSIT);
+}
+
+#[tokio::test]
+async fn cancelled_escrow_can_be_opened_again() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, DEPOSIT, EXPECTED).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    trade.cancel(&mut context, trade.alice_x).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, DEPOSIT);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+
+    // The escrow and vault addresses of the seed are free again
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), Some(slot + 100))
+        .await
+        .unwrap();
+    trade.exchange(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, EXPECTED);
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, DEPOSIT);
+}
+
+#[tokio::test]
+async fn escrow_of_another_layout_version_is_rejected() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, DEPOSIT, EXPECTED).await;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+    set_escrow_version(&mut context, &trade.escrow, 4).await;
+
+    assert_instruction_error(
+        trade.exchange(&mut context).await,
+        InstructionError::InvalidAccountData,
+    );
+    assert_instruction_error(
+        trade.reset_time_lock(&mut context).await,
+        InstructionError::InvalidAccountData,
+    );
+    assert_instruction_error(
+        trade.cancel(&mut context, trade.alice_x).await,
+        InstructionError::InvalidAccountData,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, DEPOSIT);
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use anchor_lang::AccountDeserialize;
+use common::{account_exists, current_slot, escrow_address, mint_to, token_balance, Trade};
+use solana_escrow_anchor::Escrow;
+use solana_sdk::signature::Signer;
+
+#[tokio::test]
+async fn escrow_is_created_at_the_address_of_initializer_and_seed() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 50, 30).await;
+    trade.set_seed(42);
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    let escrow = escrow_address(&trade.alice.pubkey(), 42);
+    let account = context
+        .banks_client
+        .get_account(escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.owner, solana_escrow_anchor::id());
+    let state = Escrow::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(state.seed, 42);
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 50);
+}
+
+#[tokio::test]
+async fn initializer_can_open_one_escrow_per_seed() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    // The escrow of seed 0 already exists, so it can't be created again
+    mint_to(&mut context, &trade.mint_x, &trade.alice_x, 100).await;
+    let slot = current_slot(&mut context).await;
+    assert!(trade
+        .initialize(&mut context, Some(slot), None)
+        .await
+        .is_err());
+
+    let first_escrow = trade.escrow;
+    trade.set_seed(1);
+    trade.initialize(&mut context, None, None).await.unwrap();
+    assert!(account_exists(&mut context, &first_escrow).await);
+    assert!(account_exists(&mut context, &trade.escrow).await);
+    assert_ne!(first_escrow, trade.escrow);
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::{
+    current_slot, events, process_with_logs, update_instruction, warp_to_slot, Trade, Update,
+};
+use solana_escrow_anchor::{
+    EscrowCancelled, EscrowCreated, EscrowExchanged, EscrowTimeReset, EscrowUpdated,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signer};
+
+#[tokio::test]
+async fn opening_and_filling_an_escrow_emits_its_events() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let slot = current_slot(&mut context).await;
+    let ix = trade.initialize_instruction(Some(slot), Some(slot + 100));
+    let logs = process_with_logs(&mut context, &[ix], &[&trade.alice])
+        .await
+        .unwrap();
+
+    let created = events::<EscrowCreated>(&logs);
+    assert_eq!(created.len(), 1);
+    let created = &created[0];
+    assert_eq!(created.escrow, trade.escrow);
+    assert_eq!(created.initializer, trade.alice.pubkey());
+    assert_eq!(created.mint_a, trade.mint_x);
+    assert_eq!(created.mint_b, trade.mint_y);
+    assert_eq!(created.deposit_amount, 50);
+    assert_eq!(created.expected_amount, 30);
+    assert_eq!(created.unlock_time, slot);
+    assert_eq!(created.time_out, slot + 100);
+    assert_eq!(created.allowed_taker, None);
+    assert_eq!(created.slot, slot);
+
+    // Filled in two parts, the escrow is closed by the second one
+    for &(amount, paid_amount, remaining_amount) in &[(20, 12, 30), (30, 18, 0)] {
+        let ix = trade.fill_instruction(&mut context, amount).await;
+        let logs = process_with_logs(&mut context, &[ix], &[&trade.bob])
+            .await
+            .unwrap();
+        let exchanged = events::<EscrowExchanged>(&logs);
+        assert_eq!(exchanged.len(), 1);
+        let event = &exchanged[0];
+        assert_eq!(event.escrow, trade.escrow);
+        assert_eq!(event.initializer, trade.alice.pubkey());
+        assert_eq!(event.taker, trade.bob.pubkey());
+        assert_eq!(event.mint_a, trade.mint_x);
+        assert_eq!(event.mint_b, trade.mint_y);
+        assert_eq!(event.taken_amount, amount);
+        assert_eq!(event.paid_amount, paid_amount);
+        assert_eq!(event.remaining_amount, remaining_amount);
+        assert_eq!(event.slot, slot);
+    }
+}
+
+#[tokio::test]
+async fn cancelling_an_escrow_emits_the_refund() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+    trade.fill(&mut context, 20).await.unwrap();
+    let slot = current_slot(&mut context).await;
+
+    let ix = trade.cancel_instruction(trade.alice_x);
+    let logs = process_with_logs(&mut context, &[ix], &[&trade.alice])
+        .await
+        .unwrap();
+
+    let cancelled = events::<EscrowCancelled>(&logs);
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].escrow, trade.escrow);
+    assert_eq!(cancelled[0].initializer, trade.alice.pubkey());
+    assert_eq!(cancelled[0].mint_a, trade.mint_x);
+    assert_eq!(cancelled[0].refunded_amount, 30);
+    assert_eq!(cancelled[0].slot, slot);
+}
+
+#[tokio::test]
+async fn resetting_the_time_lock_emits_the_new_window() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot + 50), Some(slot + 60))
+        .await
+        .unwrap();
+    warp_to_slot(&mut context, slot + 10).await;
+
+    let ix = trade.reset_time_lock_instruction();
+    let logs = process_with_logs(&mut context, &[ix], &[&trade.alice])
+        .await
+        .unwrap();
+
+    let reset = events::<EscrowTimeReset>(&logs);
+    assert_eq!(reset.len(), 1);
+    assert_eq!(reset[0].escrow, trade.escrow);
+    assert_eq!(reset[0].initializer, trade.alice.pubkey());
+    // The default window, counted from the slot of the reset
+    assert_eq!(reset[0].unlock_time, slot + 10 + 100);
+    assert_eq!(reset[0].time_out, slot + 10 + 100 + 1000);
+    assert_eq!(reset[0].slot, slot + 10);
+}
+
+#[tokio::test]
+async fn updating_an_escrow_emits_its_new_terms() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+    trade.fill(&mut context, 20).await.unwrap();
+    let slot = current_slot(&mut context).await;
+
+    let taker = Pubkey::new_unique();
+    let ix = update_instruction(
+        &trade,
+        &trade.alice,
+        Update {
+            expected_amount: Some(45),
+            time_out: Some(slot + 500),
+            allowed_taker: Some(Some(taker)),
+            ..Update::default()
+        },
+    );
+    let logs = process_with_logs(&mut context, &[ix], &[&trade.alice])
+        .await
+        .unwrap();
+
+    let updated = events::<EscrowUpdated>(&logs);
+    assert_eq!(updated.len(), 1);
+    let updated = &updated[0];
+    assert_eq!(updated.escrow, trade.escrow);
+    assert_eq!(updated.initializer, trade.alice.pubkey());
+    assert_eq!(updated.mint_a, trade.mint_x);
+    assert_eq!(updated.mint_b, trade.mint_y);
+    // What was left after the fill, offered at the new price
+    assert_eq!(updated.deposit_amount, 30);
+    assert_eq!(updated.expected_amount, 45);
+    assert_eq!(updated.time_out, slot + 500);
+    assert_eq!(updated.allowed_taker, Some(taker));
+    assert_eq!(updated.slot, slot);
+}
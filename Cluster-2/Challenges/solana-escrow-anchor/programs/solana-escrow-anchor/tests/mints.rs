@@ -0,0 +1,60 @@
+mod common;
+
+use anchor_lang::AccountDeserialize;
+use common::{assert_error, create_token_account, current_slot, Trade};
+use solana_escrow_anchor::{ErrorCode, Escrow};
+use solana_sdk::signature::Signer;
+
+#[tokio::test]
+async fn initialize_records_mints_and_deposit() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    let account = context
+        .banks_client
+        .get_account(trade.escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow = Escrow::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(escrow.version, 5);
+    assert_eq!(escrow.mint_a, trade.mint_x);
+    assert_eq!(escrow.mint_b, trade.mint_y);
+    assert_eq!(escrow.deposit_amount, 50);
+    assert_eq!(escrow.expected_amount, 30);
+    assert_eq!(escrow.allowed_taker, None);
+}
+
+#[tokio::test]
+async fn initialize_rejects_trading_a_mint_for_itself() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 50, 30).await;
+    trade.alice_y = create_token_account(&mut context, &trade.mint_x, &trade.alice.pubkey()).await;
+
+    assert_error(
+        trade.initialize(&mut context, None, None).await,
+        ErrorCode::MintMismatch,
+    );
+}
+
+#[tokio::test]
+async fn exchange_rejects_taker_accounts_of_the_wrong_mint() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 50, 30).await;
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), None)
+        .await
+        .unwrap();
+
+    // Bob tries to pay with X instead of Y
+    let bob_y = trade.bob_y;
+    trade.bob_y = create_token_account(&mut context, &trade.mint_x, &trade.bob.pubkey()).await;
+    assert_error(trade.exchange(&mut context).await, ErrorCode::MintMismatch);
+
+    // and to receive X into a Y account
+    trade.bob_y = bob_y;
+    trade.bob_x = create_token_account(&mut context, &trade.mint_y, &trade.bob.pubkey()).await;
+    assert_error(trade.exchange(&mut context).await, ErrorCode::MintMismatch);
+}
@@ -0,0 +1,218 @@
+mod common;
+
+use anchor_lang::AccountDeserialize;
+use common::{
+    assert_error, assert_instruction_error, create_mint, create_token_account, mint_to,
+    set_metadata, token_balance, Trade,
+};
+use solana_escrow_anchor::{ErrorCode, Escrow, NftTerms};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{instruction::InstructionError, pubkey::Pubkey, signature::Signer};
+
+fn any_nft() -> Option<NftTerms> {
+    Some(NftTerms {
+        collection: None,
+        creator: None,
+    })
+}
+
+fn of_collection(collection: Pubkey) -> Option<NftTerms> {
+    Some(NftTerms {
+        collection: Some(collection),
+        creator: None,
+    })
+}
+
+/// Points the Y side of the trade at a new NFT of `collection` held by Bob
+async fn bring_nft(context: &mut ProgramTestContext, trade: &mut Trade, collection: Pubkey) {
+    trade.mint_y = create_mint(context).await;
+    trade.bob_y = create_token_account(context, &trade.mint_y, &trade.bob.pubkey()).await;
+    trade.alice_y = create_token_account(context, &trade.mint_y, &trade.alice.pubkey()).await;
+    mint_to(context, &trade.mint_y, &trade.bob_y, 1).await;
+    set_metadata(context, &trade.mint_y, Some(collection), None);
+}
+
+#[tokio::test]
+async fn nft_is_sold_for_tokens() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 1, 500).await;
+    trade.deposit_nft = any_nft();
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 1);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 500);
+}
+
+#[tokio::test]
+async fn nft_is_swapped_for_an_nft_of_the_expected_collection() {
+    let mut context = common::start().await;
+    let creator = Pubkey::new_unique();
+    let collection = Pubkey::new_unique();
+    let mut trade = Trade::setup(&mut context, 1, 1).await;
+    set_metadata(&mut context, &trade.mint_x, None, Some(creator));
+    trade.deposit_nft = Some(NftTerms {
+        collection: None,
+        creator: Some(creator),
+    });
+    trade.expected_nft = of_collection(collection);
+    trade.any_expected_nft = true;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+
+    // No NFT is pinned, Bob picks his once the escrow is open
+    let account = context
+        .banks_client
+        .get_account(trade.escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow = Escrow::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(escrow.mint_b, Pubkey::default());
+    bring_nft(&mut context, &mut trade, collection).await;
+
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 1);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 1);
+}
+
+#[tokio::test]
+async fn any_nft_of_the_expected_collection_is_accepted() {
+    let mut context = common::start().await;
+    let collection = Pubkey::new_unique();
+    let mut trade = Trade::setup(&mut context, 500, 1).await;
+    trade.expected_nft = of_collection(collection);
+    trade.any_expected_nft = true;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+
+    // Both are of the collection, Bob trades the second one and keeps the first
+    bring_nft(&mut context, &mut trade, collection).await;
+    let (first_mint, first_nft) = (trade.mint_y, trade.bob_y);
+    bring_nft(&mut context, &mut trade, collection).await;
+    assert_ne!(trade.mint_y, first_mint);
+
+    trade.exchange(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 500);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 1);
+    assert_eq!(token_balance(&mut context, &first_nft).await, 1);
+}
+
+#[tokio::test]
+async fn expected_nft_is_only_paid_to_the_initializer() {
+    let mut context = common::start().await;
+    let collection = Pubkey::new_unique();
+    let mut trade = Trade::setup(&mut context, 1, 1).await;
+    trade.expected_nft = of_collection(collection);
+    trade.any_expected_nft = true;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+    bring_nft(&mut context, &mut trade, collection).await;
+
+    // Bob names another account of his own as the one of Alice
+    trade.alice_y = create_token_account(&mut context, &trade.mint_y, &trade.bob.pubkey()).await;
+    assert_instruction_error(
+        trade.exchange(&mut context).await,
+        InstructionError::InvalidAccountData,
+    );
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 1);
+}
+
+#[tokio::test]
+async fn nft_of_another_collection_is_not_accepted() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 1, 1).await;
+    set_metadata(
+        &mut context,
+        &trade.mint_y,
+        Some(Pubkey::new_unique()),
+        None,
+    );
+    trade.expected_nft = Some(NftTerms {
+        collection: Some(Pubkey::new_unique()),
+        creator: None,
+    });
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+
+    assert_error(
+        trade.exchange(&mut context).await,
+        ErrorCode::NftTermsMismatch,
+    );
+    assert_eq!(token_balance(&mut context, &trade.bob_y).await, 1);
+}
+
+#[tokio::test]
+async fn nft_of_another_creator_is_not_deposited() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 1, 500).await;
+    set_metadata(
+        &mut context,
+        &trade.mint_x,
+        None,
+        Some(Pubkey::new_unique()),
+    );
+    trade.deposit_nft = Some(NftTerms {
+        collection: None,
+        creator: Some(Pubkey::new_unique()),
+    });
+
+    assert_error(
+        trade.initialize(&mut context, Some(0), None).await,
+        ErrorCode::NftTermsMismatch,
+    );
+}
+
+#[tokio::test]
+async fn nfts_are_single_whole_tokens() {
+    let mut context = common::start().await;
+
+    // A second token of the mint has been minted
+    let mut trade = Trade::setup(&mut context, 1, 500).await;
+    mint_to(&mut context, &trade.mint_x, &trade.bob_x, 1).await;
+    trade.deposit_nft = any_nft();
+    assert_error(
+        trade.initialize(&mut context, Some(0), None).await,
+        ErrorCode::NotAnNft,
+    );
+
+    // An NFT can't be asked for in several pieces
+    let mut trade = Trade::setup(&mut context, 500, 2).await;
+    trade.expected_nft = any_nft();
+    assert_error(
+        trade.initialize(&mut context, Some(0), None).await,
+        ErrorCode::NotAnNft,
+    );
+}
+
+#[tokio::test]
+async fn nft_of_any_mint_cannot_be_left_unpinned() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 500, 1).await;
+    trade.expected_nft = any_nft();
+    trade.any_expected_nft = true;
+
+    // Bob could mint a token of his own to take the deposit
+    assert_error(
+        trade.initialize(&mut context, Some(0), None).await,
+        ErrorCode::UnpinnedNft,
+    );
+}
+
+#[tokio::test]
+async fn pinned_nft_is_the_only_one_accepted() {
+    let mut context = common::start().await;
+    let mut trade = Trade::setup(&mut context, 500, 1).await;
+    trade.expected_nft = any_nft();
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+    let (pinned_mint, pinned_nft) = (trade.mint_y, trade.bob_y);
+
+    // Bob mints an NFT of his own instead
+    trade.mint_y = create_mint(&mut context).await;
+    trade.bob_y = create_token_account(&mut context, &trade.mint_y, &trade.bob.pubkey()).await;
+    mint_to(&mut context, &trade.mint_y, &trade.bob_y, 1).await;
+    assert_error(trade.exchange(&mut context).await, ErrorCode::MintMismatch);
+
+    (trade.mint_y, trade.bob_y) = (pinned_mint, pinned_nft);
+    trade.exchange(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 500);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 1);
+}
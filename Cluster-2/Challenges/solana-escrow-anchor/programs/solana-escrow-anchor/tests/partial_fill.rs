@@ -0,0 +1,88 @@
+mod common;
+
+use anchor_lang::AccountDeserialize;
+use common::{account_exists, assert_error, current_slot, token_balance, warp_to_slot, Trade};
+use solana_escrow_anchor::{ErrorCode, Escrow};
+use solana_program_test::ProgramTestContext;
+
+async fn escrow_state(context: &mut ProgramTestContext, trade: &Trade) -> Escrow {
+    let account = context
+        .banks_client
+        .get_account(trade.escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    Escrow::try_deserialize(&mut account.data.as_slice()).unwrap()
+}
+
+async fn open_trade(context: &mut ProgramTestContext, deposit: u64, expected: u64) -> Trade {
+    let trade = Trade::setup(context, deposit, expected).await;
+    let slot = current_slot(context).await;
+    trade
+        .initialize(context, Some(slot), Some(slot + 100))
+        .await
+        .unwrap();
+    trade
+}
+
+#[tokio::test]
+async fn partial_fill_pays_pro_rata_and_keeps_escrow_open() {
+    let mut context = common::start().await;
+    let trade = open_trade(&mut context, 50, 30).await;
+
+    trade.fill(&mut context, 20).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 20);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 12);
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 30);
+
+    let escrow = escrow_state(&mut context, &trade).await;
+    assert_eq!(escrow.remaining_amount, 30);
+    assert_eq!(escrow.remaining_expected_amount, 18);
+}
+
+#[tokio::test]
+async fn last_fill_pays_the_rest_and_closes_escrow() {
+    let mut context = common::start().await;
+    let trade = open_trade(&mut context, 3, 10).await;
+
+    // 1 of 3 at 10/3 rounds up to 4, the initializer never gets less than their ratio
+    trade.fill(&mut context, 1).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 4);
+
+    let slot = current_slot(&mut context).await;
+    warp_to_slot(&mut context, slot + 1).await;
+    trade.fill(&mut context, 2).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 3);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 10);
+    assert!(!account_exists(&mut context, &trade.vault).await);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn fill_rejects_zero_or_more_than_remaining() {
+    let mut context = common::start().await;
+    let trade = open_trade(&mut context, 50, 30).await;
+
+    assert_error(
+        trade.fill(&mut context, 0).await,
+        ErrorCode::ExpectedAmountMismatch,
+    );
+    assert_error(
+        trade.fill(&mut context, 51).await,
+        ErrorCode::ExpectedAmountMismatch,
+    );
+}
+
+#[tokio::test]
+async fn cancel_refunds_what_is_left_after_a_partial_fill() {
+    let mut context = common::start().await;
+    let trade = open_trade(&mut context, 50, 30).await;
+
+    trade.fill(&mut context, 20).await.unwrap();
+    trade.cancel(&mut context, trade.alice_x).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 30);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::token::spl_token::native_mint;
+use common::{
+    assert_error, config_address, create_account, create_mint, create_token_account, current_slot,
+    fee_vault_address, init_config, lamports, process, set_fee, token_balance, Trade,
+};
+use solana_escrow_anchor::ErrorCode;
+use solana_program_test::{BanksClientError, ProgramTestContext};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+async fn open_trade(context: &mut ProgramTestContext, trade: &Trade) {
+    let slot = current_slot(context).await;
+    trade
+        .initialize(context, Some(slot), Some(slot + 100))
+        .await
+        .unwrap();
+}
+
+async fn withdraw_fees(
+    context: &mut ProgramTestContext,
+    mint: Pubkey,
+    fee_recipient_token_account: Option<Pubkey>,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    let sol = mint == native_mint::id();
+    let ix = Instruction {
+        program_id: solana_escrow_anchor::id(),
+        accounts: solana_escrow_anchor::accounts::WithdrawFees {
+            admin: context.payer.pubkey(),
+            config: config_address(),
+            mint,
+            fee_vault: if sol {
+                None
+            } else {
+                Some(fee_vault_address(&mint))
+            },
+            fee_recipient: context.payer.pubkey(),
+            fee_recipient_token_account,
+            token_program: anchor_spl::token::ID,
+        }
+        .to_account_metas(None),
+        data: solana_escrow_anchor::instruction::WithdrawFees { amount }.data(),
+    };
+    process(context, &[ix], &[]).await
+}
+
+#[tokio::test]
+async fn only_the_upgrade_authority_creates_the_config() {
+    let mut context = common::start_without_config().await;
+    let mallory = Keypair::new();
+    create_account(&mut context, &mallory).await;
+
+    assert_error(
+        init_config(&mut context, &mallory).await,
+        ErrorCode::InvalidAdmin,
+    );
+
+    let admin = context.payer.insecure_clone();
+    init_config(&mut context, &admin).await.unwrap();
+    set_fee(&mut context, &admin, 100).await.unwrap();
+}
+
+#[tokio::test]
+async fn exchange_skims_the_fee_from_both_legs() {
+    let mut context = common::start().await;
+    let admin = context.payer.insecure_clone();
+    set_fee(&mut context, &admin, 100).await.unwrap();
+    let trade = Trade::setup(&mut context, 1_000, 500).await;
+    open_trade(&mut context, &trade).await;
+
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 990);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 495);
+    assert_eq!(
+        token_balance(&mut context, &fee_vault_address(&trade.mint_x)).await,
+        10
+    );
+    assert_eq!(
+        token_balance(&mut context, &fee_vault_address(&trade.mint_y)).await,
+        5
+    );
+}
+
+#[tokio::test]
+async fn only_the_admin_sets_a_fee_under_the_cap() {
+    let mut context = common::start().await;
+    let admin = context.payer.insecure_clone();
+
+    assert_error(
+        set_fee(&mut context, &admin, 1_001).await,
+        ErrorCode::FeeTooHigh,
+    );
+    assert_error(
+        set_fee(&mut context, &Keypair::new(), 100).await,
+        ErrorCode::InvalidAdmin,
+    );
+    set_fee(&mut context, &admin, 1_000).await.unwrap();
+}
+
+#[tokio::test]
+async fn admin_withdraws_token_fees_to_the_fee_recipient() {
+    let mut context = common::start().await;
+    let admin = context.payer.insecure_clone();
+    set_fee(&mut context, &admin, 100).await.unwrap();
+    let trade = Trade::setup(&mut context, 1_000, 500).await;
+    open_trade(&mut context, &trade).await;
+    trade.exchange(&mut context).await.unwrap();
+
+    let recipient_x = create_token_account(&mut context, &trade.mint_x, &admin.pubkey()).await;
+    withdraw_fees(&mut context, trade.mint_x, Some(recipient_x), 10)
+        .await
+        .unwrap();
+
+    assert_eq!(token_balance(&mut context, &recipient_x).await, 10);
+    assert_eq!(
+        token_balance(&mut context, &fee_vault_address(&trade.mint_x)).await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn sol_fees_are_held_by_the_config_account() {
+    let mut context = common::start().await;
+    let admin = context.payer.insecure_clone();
+    set_fee(&mut context, &admin, 100).await.unwrap();
+    let mint_x = create_mint(&mut context).await;
+    let trade =
+        Trade::setup_with_mints(&mut context, mint_x, native_mint::id(), 1_000, 50_000).await;
+    open_trade(&mut context, &trade).await;
+
+    let config_lamports = lamports(&mut context, &config_address()).await;
+    trade.exchange(&mut context).await.unwrap();
+    assert_eq!(
+        lamports(&mut context, &config_address()).await,
+        config_lamports + 500
+    );
+
+    withdraw_fees(&mut context, native_mint::id(), None, 500)
+        .await
+        .unwrap();
+    assert_eq!(
+        lamports(&mut context, &config_address()).await,
+        config_lamports
+    );
+    // Nothing below the rent exemption of the config can be withdrawn
+    assert!(withdraw_fees(&mut context, native_mint::id(), None, 1)
+        .await
+        .is_err());
+}
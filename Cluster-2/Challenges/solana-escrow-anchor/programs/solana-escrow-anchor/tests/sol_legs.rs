@@ -0,0 +1,103 @@
+mod common;
+
+use anchor_spl::token::spl_token::native_mint;
+use common::{
+    account_exists, assert_error, create_mint, current_slot, lamports, token_balance, Trade,
+};
+use solana_escrow_anchor::ErrorCode;
+use solana_program_test::ProgramTestContext;
+use solana_sdk::signature::Signer;
+
+async fn open_trade(context: &mut ProgramTestContext, trade: &Trade) {
+    let slot = current_slot(context).await;
+    trade
+        .initialize(context, Some(slot), Some(slot + 100))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn sol_deposit_is_held_by_the_escrow_and_paid_to_the_taker() {
+    let mut context = common::start().await;
+    let mint_y = create_mint(&mut context).await;
+    let trade = Trade::setup_with_mints(&mut context, native_mint::id(), mint_y, 5_000, 30).await;
+    open_trade(&mut context, &trade).await;
+
+    let escrow_lamports = lamports(&mut context, &trade.escrow).await;
+    assert!(!account_exists(&mut context, &trade.vault).await);
+
+    let bob_lamports = lamports(&mut context, &trade.bob.pubkey()).await;
+    trade.fill(&mut context, 2_000).await.unwrap();
+    assert_eq!(
+        lamports(&mut context, &trade.bob.pubkey()).await,
+        bob_lamports + 2_000
+    );
+    assert_eq!(
+        lamports(&mut context, &trade.escrow).await,
+        escrow_lamports - 2_000
+    );
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 12);
+
+    trade.fill(&mut context, 3_000).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 30);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn sol_price_is_paid_to_the_initializer_wallet() {
+    let mut context = common::start().await;
+    let mint_x = create_mint(&mut context).await;
+    let trade = Trade::setup_with_mints(&mut context, mint_x, native_mint::id(), 50, 7_000).await;
+    open_trade(&mut context, &trade).await;
+
+    let alice_lamports = lamports(&mut context, &trade.alice.pubkey()).await;
+    let escrow_lamports = lamports(&mut context, &trade.escrow).await;
+    let vault_lamports = lamports(&mut context, &trade.vault).await;
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 50);
+    // The price plus the rent of the closed escrow and vault
+    assert_eq!(
+        lamports(&mut context, &trade.alice.pubkey()).await,
+        alice_lamports + 7_000 + escrow_lamports + vault_lamports
+    );
+    assert!(!account_exists(&mut context, &trade.vault).await);
+}
+
+#[tokio::test]
+async fn cancel_returns_a_sol_deposit() {
+    let mut context = common::start().await;
+    let mint_y = create_mint(&mut context).await;
+    let trade = Trade::setup_with_mints(&mut context, native_mint::id(), mint_y, 5_000, 30).await;
+    let alice_lamports = lamports(&mut context, &trade.alice.pubkey()).await;
+    open_trade(&mut context, &trade).await;
+
+    trade.cancel(&mut context, trade.alice_x).await.unwrap();
+
+    assert_eq!(
+        lamports(&mut context, &trade.alice.pubkey()).await,
+        alice_lamports
+    );
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn both_sides_can_not_be_sol() {
+    let mut context = common::start().await;
+    let trade = Trade::setup_with_mints(
+        &mut context,
+        native_mint::id(),
+        native_mint::id(),
+        5_000,
+        7_000,
+    )
+    .await;
+    let slot = current_slot(&mut context).await;
+
+    assert_error(
+        trade
+            .initialize(&mut context, Some(slot), Some(slot + 100))
+            .await,
+        ErrorCode::MintMismatch,
+    );
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use common::{assert_error, current_slot, token_balance, warp_to_slot, Trade};
+use solana_escrow_anchor::ErrorCode;
+
+#[tokio::test]
+async fn exchange_is_locked_until_unlock_time() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    let slot = current_slot(&mut context).await;
+    let unlock_time = slot + 10;
+    trade
+        .initialize(&mut context, Some(unlock_time), Some(unlock_time + 10))
+        .await
+        .unwrap();
+
+    warp_to_slot(&mut context, unlock_time - 1).await;
+    assert_error(
+        trade.exchange(&mut context).await,
+        ErrorCode::EscrowTradeLocked,
+    );
+
+    warp_to_slot(&mut context, unlock_time).await;
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 50);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 30);
+}
+
+#[tokio::test]
+async fn exchange_is_open_until_time_out() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    let slot = current_slot(&mut context).await;
+    let time_out = slot + 20;
+    trade
+        .initialize(&mut context, Some(slot), Some(time_out))
+        .await
+        .unwrap();
+
+    warp_to_slot(&mut context, time_out).await;
+    trade.exchange(&mut context).await.unwrap();
+}
+
+#[tokio::test]
+async fn exchange_fails_after_time_out() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    let slot = current_slot(&mut context).await;
+    let time_out = slot + 20;
+    trade
+        .initialize(&mut context, Some(slot), Some(time_out))
+        .await
+        .unwrap();
+
+    warp_to_slot(&mut context, time_out + 1).await;
+    assert_error(
+        trade.exchange(&mut context).await,
+        ErrorCode::EscrowTimedOut,
+    );
+}
+
+#[tokio::test]
+async fn default_window_unlocks_before_it_times_out() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    let slot = current_slot(&mut context).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    warp_to_slot(&mut context, slot + 50).await;
+    assert_error(
+        trade.exchange(&mut context).await,
+        ErrorCode::EscrowTradeLocked,
+    );
+
+    warp_to_slot(&mut context, slot + 500).await;
+    trade.exchange(&mut context).await.unwrap();
+}
+
+#[tokio::test]
+async fn reset_time_lock_restarts_the_default_window() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), Some(slot + 20))
+        .await
+        .unwrap();
+
+    warp_to_slot(&mut context, slot + 10).await;
+    let slot = current_slot(&mut context).await;
+    trade.reset_time_lock(&mut context).await.unwrap();
+
+    warp_to_slot(&mut context, slot + 1).await;
+    assert_error(
+        trade.exchange(&mut context).await,
+        ErrorCode::EscrowTradeLocked,
+    );
+
+    warp_to_slot(&mut context, slot + 500).await;
+    trade.exchange(&mut context).await.unwrap();
+}
+
+#[tokio::test]
+async fn reset_time_lock_fails_after_time_out() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), Some(slot + 20))
+        .await
+        .unwrap();
+
+    warp_to_slot(&mut context, slot + 21).await;
+    assert_error(
+        trade.reset_time_lock(&mut context).await,
+        ErrorCode::EscrowTimedOut,
+    );
+
+    // It can still be cancelled
+    trade.cancel(&mut context, trade.alice_x).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 50);
+}
+
+#[tokio::test]
+async fn initialize_rejects_inverted_or_expired_windows() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    warp_to_slot(&mut context, 100).await;
+
+    let slot = current_slot(&mut context).await;
+    assert_error(
+        trade
+            .initialize(&mut context, Some(slot + 20), Some(slot + 10))
+            .await,
+        ErrorCode::InvalidTimeWindow,
+    );
+    assert_error(
+        trade
+            .initialize(&mut context, Some(slot - 50), Some(slot))
+            .await,
+        ErrorCode::InvalidTimeWindow,
+    );
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use anchor_lang::AccountDeserialize;
+use common::{
+    account_exists, create_mint, create_mint_2022, current_slot, mint_to, token_balance, Trade,
+};
+use solana_escrow_anchor::Escrow;
+
+// 1% transfer fee, rounded up by the token program
+const FEE_BASIS_POINTS: u16 = 100;
+
+#[tokio::test]
+async fn escrow_offers_what_reaches_the_vault_of_a_transfer_fee_mint() {
+    let mut context = common::start().await;
+    let mint_x = create_mint_2022(&mut context, FEE_BASIS_POINTS).await;
+    let mint_y = create_mint(&mut context).await;
+    let trade = Trade::setup_with_mints(&mut context, mint_x, mint_y, 1000, 500).await;
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), None)
+        .await
+        .unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 990);
+    let account = context
+        .banks_client
+        .get_account(trade.escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    let escrow = Escrow::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(escrow.deposit_amount, 990);
+    assert_eq!(escrow.remaining_amount, 990);
+
+    // The vault holds withheld fees, it is still closed once the offer is filled
+    trade.fill(&mut context, 990).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 980);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 500);
+    assert!(!account_exists(&mut context, &trade.vault).await);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
+
+#[tokio::test]
+async fn taker_covers_the_transfer_fee_of_the_price() {
+    let mut context = common::start().await;
+    let mint_x = create_mint(&mut context).await;
+    let mint_y = create_mint_2022(&mut context, FEE_BASIS_POINTS).await;
+    let trade = Trade::setup_with_mints(&mut context, mint_x, mint_y, 50, 1000).await;
+    mint_to(&mut context, &trade.mint_y, &trade.bob_y, 100).await;
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), None)
+        .await
+        .unwrap();
+
+    trade.exchange(&mut context).await.unwrap();
+
+    // 1011 are sent so that 1000 arrive after the 11 withheld
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 1000);
+    assert_eq!(token_balance(&mut context, &trade.bob_y).await, 1100 - 1011);
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 50);
+}
+
+#[tokio::test]
+async fn cancel_refunds_a_transfer_fee_mint_and_closes_the_vault() {
+    let mut context = common::start().await;
+    let mint_x = create_mint_2022(&mut context, FEE_BASIS_POINTS).await;
+    let mint_y = create_mint_2022(&mut context, 0).await;
+    let trade = Trade::setup_with_mints(&mut context, mint_x, mint_y, 1000, 500).await;
+    trade.initialize(&mut context, None, None).await.unwrap();
+
+    trade.cancel(&mut context, trade.alice_x).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 980);
+    assert!(!account_exists(&mut context, &trade.vault).await);
+    assert!(!account_exists(&mut context, &trade.escrow).await);
+}
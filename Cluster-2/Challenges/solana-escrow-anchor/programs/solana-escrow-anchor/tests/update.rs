@@ -0,0 +1,250 @@
+mod common;
+
+use anchor_lang::AccountDeserialize;
+use anchor_spl::token::spl_token::native_mint;
+use common::{
+    assert_error, assert_instruction_error, create_mint, current_slot, lamports, mint_to,
+    set_escrow_version, token_balance, update, warp_to_slot, Trade, Update,
+};
+use solana_escrow_anchor::{ErrorCode, Escrow};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{instruction::InstructionError, signature::Signer};
+
+async fn escrow_state(context: &mut ProgramTestContext, trade: &Trade) -> Escrow {
+    let account = context
+        .banks_client
+        .get_account(trade.escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    Escrow::try_deserialize(&mut account.data.as_slice()).unwrap()
+}
+
+#[tokio::test]
+async fn initializer_changes_price_expiry_and_taker() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+    let slot = current_slot(&mut context).await;
+    let alice = trade.alice.insecure_clone();
+    update(
+        &mut context,
+        &trade,
+        &alice,
+        Update {
+            expected_amount: Some(45),
+            time_out: Some(slot + 500),
+            allowed_taker: Some(Some(trade.bob.pubkey())),
+            ..Update::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let escrow = escrow_state(&mut context, &trade).await;
+    assert_eq!(escrow.expected_amount, 45);
+    assert_eq!(escrow.remaining_expected_amount, 45);
+    assert_eq!(escrow.time_out, slot + 500);
+    assert_eq!(escrow.allowed_taker, Some(trade.bob.pubkey()));
+
+    mint_to(&mut context, &trade.mint_y, &trade.bob_y, 15).await;
+    trade.exchange(&mut context).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 45);
+}
+
+#[tokio::test]
+async fn top_up_and_withdrawal_change_the_deposit() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    mint_to(&mut context, &trade.mint_x, &trade.alice_x, 20).await;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+    let alice = trade.alice.insecure_clone();
+
+    update(
+        &mut context,
+        &trade,
+        &alice,
+        Update {
+            top_up_amount: 20,
+            ..Update::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 70);
+    assert_eq!(escrow_state(&mut context, &trade).await.deposit_amount, 70);
+
+    update(
+        &mut context,
+        &trade,
+        &alice,
+        Update {
+            withdraw_amount: 30,
+            ..Update::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(token_balance(&mut context, &trade.vault).await, 40);
+    assert_eq!(token_balance(&mut context, &trade.alice_x).await, 30);
+    let escrow = escrow_state(&mut context, &trade).await;
+    assert_eq!(escrow.deposit_amount, 40);
+    assert_eq!(escrow.remaining_amount, 40);
+
+    trade.fill(&mut context, 40).await.unwrap();
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 40);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 30);
+}
+
+#[tokio::test]
+async fn sol_deposit_is_withdrawn_to_the_wallet() {
+    let mut context = common::start().await;
+    let mint_y = create_mint(&mut context).await;
+    let trade = Trade::setup_with_mints(&mut context, native_mint::id(), mint_y, 5_000, 30).await;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+    let alice = trade.alice.insecure_clone();
+    let escrow_lamports = lamports(&mut context, &trade.escrow).await;
+
+    update(
+        &mut context,
+        &trade,
+        &alice,
+        Update {
+            withdraw_amount: 2_000,
+            ..Update::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        lamports(&mut context, &trade.escrow).await,
+        escrow_lamports - 2_000
+    );
+    assert_eq!(
+        escrow_state(&mut context, &trade).await.remaining_amount,
+        3_000
+    );
+}
+
+#[tokio::test]
+async fn only_the_initializer_updates() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+    let bob = trade.bob.insecure_clone();
+
+    assert_instruction_error(
+        update(
+            &mut context,
+            &trade,
+            &bob,
+            Update {
+                expected_amount: Some(1),
+                ..Update::default()
+            },
+        )
+        .await,
+        InstructionError::InvalidAccountData,
+    );
+}
+
+#[tokio::test]
+async fn deposit_and_price_cannot_be_emptied() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+    let alice = trade.alice.insecure_clone();
+
+    assert_error(
+        update(
+            &mut context,
+            &trade,
+            &alice,
+            Update {
+                withdraw_amount: 50,
+                ..Update::default()
+            },
+        )
+        .await,
+        ErrorCode::InvalidUpdate,
+    );
+    assert_error(
+        update(
+            &mut context,
+            &trade,
+            &alice,
+            Update {
+                expected_amount: Some(0),
+                ..Update::default()
+            },
+        )
+        .await,
+        ErrorCode::InvalidUpdate,
+    );
+}
+
+#[tokio::test]
+async fn timed_out_escrow_cannot_be_updated() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let slot = current_slot(&mut context).await;
+    trade
+        .initialize(&mut context, Some(slot), Some(slot + 100))
+        .await
+        .unwrap();
+    warp_to_slot(&mut context, slot + 101).await;
+    let alice = trade.alice.insecure_clone();
+
+    // Pushing the time out back would otherwise reopen the trade
+    assert_error(
+        update(
+            &mut context,
+            &trade,
+            &alice,
+            Update {
+                time_out: Some(slot + 500),
+                ..Update::default()
+            },
+        )
+        .await,
+        ErrorCode::EscrowTimedOut,
+    );
+    assert_error(
+        update(
+            &mut context,
+            &trade,
+            &alice,
+            Update {
+                expected_amount: Some(45),
+                ..Update::default()
+            },
+        )
+        .await,
+        ErrorCode::EscrowTimedOut,
+    );
+    let escrow = escrow_state(&mut context, &trade).await;
+    assert_eq!(escrow.time_out, slot + 100);
+    assert_eq!(escrow.expected_amount, 30);
+}
+
+#[tokio::test]
+async fn escrow_of_another_layout_version_cannot_be_updated() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context, Some(0), None).await.unwrap();
+    set_escrow_version(&mut context, &trade.escrow, 4).await;
+
+    let alice = trade.alice.insecure_clone();
+    let result = update(
+        &mut context,
+        &trade,
+        &alice,
+        Update {
+            expected_amount: Some(40),
+            ..Update::default()
+        },
+    )
+    .await;
+    assert_instruction_error(result, InstructionError::InvalidAccountData);
+}
@@ -1,86 +0,0 @@
-import { Connection, Keypair, PublicKey } from "@solana/web3.js";
-import * as BufferLayout from "buffer-layout";
-
-import * as fs from "fs";
-
-export const logError = (msg: string) => {
-    console.log(`\x1b[31m${msg}\x1b[0m`);
-};
-
-export const writePublicKey = (publicKey: PublicKey, name: string) => {
-    fs.writeFileSync(
-        `./tests/keys/${name}_pub.json`,
-        JSON.stringify(publicKey.toString())
-    );
-};
-
-export const getPublicKey = (name: string) =>
-    new PublicKey(
-        JSON.parse(fs.readFileSync(`./tests/keys/${name}_pub.json`) as unknown as string)
-    );
-
-export const getPrivateKey = (name: string) =>
-    Uint8Array.from(
-        JSON.parse(fs.readFileSync(`./tests/keys/${name}.json`) as unknown as string)
-    );
-
-export const getKeypair = (name: string) =>
-    new Keypair({
-        publicKey: getPublicKey(name).toBytes(),
-        secretKey: getPrivateKey(name),
-    });
-
-export const getProgramId = () => {
-    try {
-        return getPublicKey("program");
-    } catch (e) {
-        logError("Given programId is missing or incorrect");
-        process.exit(1);
-    }
-};
-
-export const getTerms = (): {
-    aliceExpectedAmount: number;
-    bobExpectedAmount: number;
-} => {
-    return JSON.parse(fs.readFileSync(`./tests/terms.json`) as unknown as string);
-};
-
-export const getTokenBalance = async (
-    pubkey: PublicKey,
-    connection: Connection
-) => {
-    return parseInt(
-        (await connection.getTokenAccountBalance(pubkey)).value.amount
-    );
-};
-
-/**
- * Layout for a public key
- */
-const publicKey = (property = "publicKey") => {
-    return BufferLayout.blob(32, property);
-};
-
-/**
- * Layout for a 64bit unsigned value
- */
-const uint64 = (property = "uint64") => {
-    return BufferLayout.blob(8, property);
-};
-
-export const ESCROW_ACCOUNT_DATA_LAYOUT = BufferLayout.struct([
-    BufferLayout.u8("isInitialized"),
-    publicKey("initializerPubkey"),
-    publicKey("initializerTempTokenAccountPubkey"),
-    publicKey("initializerReceivingTokenAccountPubkey"),
-    uint64("expectedAmount"),
-]);
-
-export interface EscrowLayout {
-    isInitialized: number;
-    initializerPubkey: Uint8Array;
-    initializerReceivingTokenAccountPubkey: Uint8Array;
-    initializerTempTokenAccountPubkey: Uint8Array;
-    expectedAmount: Uint8Array;
-}
@@ -0,0 +1,306 @@
+#![allow(dead_code)]
+
+use bpf_program_template::{error::EscrowError, processor::Processor, state::Escrow};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, sysvar,
+    transaction::{Transaction, TransactionError},
+};
+
+// Any address works, the program runs in-process under it
+pub const PROGRAM_ID: Pubkey = pubkey!("DnPESwBtnTBwDifUXFVC4R2geUH8Wszjee8RFHG6tJSs");
+
+pub async fn start() -> ProgramTestContext {
+    ProgramTest::new(
+        "bpf_program_template",
+        PROGRAM_ID,
+        processor!(Processor::process),
+    )
+    .start_with_context()
+    .await
+}
+
+pub async fn process(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        context.last_blockhash,
+    );
+    context.banks_client.process_transaction(transaction).await
+}
+
+pub async fn create_account(context: &mut ProgramTestContext, owner: &Keypair) {
+    let ix = system_instruction::transfer(&context.payer.pubkey(), &owner.pubkey(), 1_000_000_000);
+    process(context, &[ix], &[]).await.unwrap();
+}
+
+pub async fn create_mint(context: &mut ProgramTestContext) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &context.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &context.payer.pubkey(),
+            None,
+            0,
+        )
+        .unwrap(),
+    ];
+    process(context, &instructions, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+pub async fn create_token_account(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    let account = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &context.payer.pubkey(),
+            &account.pubkey(),
+            rent.minimum_balance(spl_token::state::Account::LEN),
+            spl_token::state::Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &account.pubkey(),
+            mint,
+            owner,
+        )
+        .unwrap(),
+    ];
+    process(context, &instructions, &[&account]).await.unwrap();
+    account.pubkey()
+}
+
+pub async fn mint_to(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    account: &Pubkey,
+    amount: u64,
+) {
+    let ix = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        mint,
+        account,
+        &context.payer.pubkey(),
+        &[],
+        amount,
+    )
+    .unwrap();
+    process(context, &[ix], &[]).await.unwrap();
+}
+
+pub async fn account_exists(context: &mut ProgramTestContext, account: &Pubkey) -> bool {
+    context
+        .banks_client
+        .get_account(*account)
+        .await
+        .unwrap()
+        .is_some()
+}
+
+pub async fn token_account(
+    context: &mut ProgramTestContext,
+    account: &Pubkey,
+) -> spl_token::state::Account {
+    let account = context
+        .banks_client
+        .get_account(*account)
+        .await
+        .unwrap()
+        .unwrap();
+    spl_token::state::Account::unpack(&account.data).unwrap()
+}
+
+pub async fn token_balance(context: &mut ProgramTestContext, account: &Pubkey) -> u64 {
+    token_account(context, account).await.amount
+}
+
+pub fn assert_instruction_error(result: Result<(), BanksClientError>, error: InstructionError) {
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, error)
+    );
+}
+
+/// The escrow instruction is the last one of the transactions built here
+pub fn assert_error(result: Result<(), BanksClientError>, error: EscrowError) {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            assert_eq!(code, error as u32)
+        }
+        other => panic!("expected {:?}, got {:?}", error, other),
+    }
+}
+
+/// The single PDA owning the temp token accounts of every escrow
+pub fn pda() -> Pubkey {
+    Pubkey::find_program_address(&[b"escrow"], &PROGRAM_ID).0
+}
+
+/// Alice offers `deposit` X tokens for `expected` Y tokens, Bob holds exactly the Y tokens to take it
+pub struct Trade {
+    pub alice: Keypair,
+    pub bob: Keypair,
+    pub mint_x: Pubkey,
+    pub mint_y: Pubkey,
+    /// Alice's temp account holding the deposit, handed to the PDA at init
+    pub temp: Pubkey,
+    pub alice_y: Pubkey,
+    pub bob_x: Pubkey,
+    pub bob_y: Pubkey,
+    pub escrow: Keypair,
+    pub deposit: u64,
+    pub expected: u64,
+}
+
+impl Trade {
+    pub async fn setup(context: &mut ProgramTestContext, deposit: u64, expected: u64) -> Self {
+        let mint_x = create_mint(context).await;
+        let mint_y = create_mint(context).await;
+        Self::setup_with_mints(context, mint_x, mint_y, deposit, expected).await
+    }
+
+    pub async fn setup_with_mints(
+        context: &mut ProgramTestContext,
+        mint_x: Pubkey,
+        mint_y: Pubkey,
+        deposit: u64,
+        expected: u64,
+    ) -> Self {
+        let alice = Keypair::new();
+        let bob = Keypair::new();
+        create_account(context, &alice).await;
+        create_account(context, &bob).await;
+
+        let temp = create_token_account(context, &mint_x, &alice.pubkey()).await;
+        let alice_y = create_token_account(context, &mint_y, &alice.pubkey()).await;
+        let bob_x = create_token_account(context, &mint_x, &bob.pubkey()).await;
+        let bob_y = create_token_account(context, &mint_y, &bob.pubkey()).await;
+        mint_to(context, &mint_x, &temp, deposit).await;
+        mint_to(context, &mint_y, &bob_y, expected).await;
+
+        Trade {
+            alice,
+            bob,
+            mint_x,
+            mint_y,
+            temp,
+            alice_y,
+            bob_x,
+            bob_y,
+            escrow: Keypair::new(),
+            deposit,
+            expected,
+        }
+    }
+
+    /// Creates the escrow account with `lamports` and initializes it in the same transaction
+    pub async fn initialize_with_lamports(
+        &self,
+        context: &mut ProgramTestContext,
+        lamports: u64,
+    ) -> Result<(), BanksClientError> {
+        let create_escrow_ix = system_instruction::create_account(
+            &self.alice.pubkey(),
+            &self.escrow.pubkey(),
+            lamports,
+            Escrow::LEN as u64,
+            &PROGRAM_ID,
+        );
+        let ix = self.init_instruction();
+        process(
+            context,
+            &[create_escrow_ix, ix],
+            &[&self.alice, &self.escrow],
+        )
+        .await
+    }
+
+    pub async fn initialize(
+        &self,
+        context: &mut ProgramTestContext,
+    ) -> Result<(), BanksClientError> {
+        let rent = context.banks_client.get_rent().await.unwrap();
+        self.initialize_with_lamports(context, rent.minimum_balance(Escrow::LEN))
+            .await
+    }
+
+    pub fn init_instruction(&self) -> Instruction {
+        let mut data = vec![0];
+        data.extend_from_slice(&self.expected.to_le_bytes());
+        Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new_readonly(self.alice.pubkey(), true),
+                AccountMeta::new(self.temp, false),
+                AccountMeta::new_readonly(self.alice_y, false),
+                AccountMeta::new(self.escrow.pubkey(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+            data,
+        }
+    }
+
+    pub async fn exchange(&self, context: &mut ProgramTestContext) -> Result<(), BanksClientError> {
+        let ix = self.exchange_instruction(self.deposit);
+        process(context, &[ix], &[&self.bob]).await
+    }
+
+    /// Bob takes the deposit, expecting it to hold `amount` X tokens
+    pub fn exchange_instruction(&self, amount: u64) -> Instruction {
+        let mut data = vec![1];
+        data.extend_from_slice(&amount.to_le_bytes());
+        Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new_readonly(self.bob.pubkey(), true),
+                AccountMeta::new(self.bob_y, false),
+                AccountMeta::new(self.bob_x, false),
+                AccountMeta::new(self.temp, false),
+                AccountMeta::new(self.alice.pubkey(), false),
+                AccountMeta::new(self.alice_y, false),
+                AccountMeta::new(self.escrow.pubkey(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(pda(), false),
+            ],
+            data,
+        }
+    }
+
+    pub async fn state(&self, context: &mut ProgramTestContext) -> Escrow {
+        let account = context
+            .banks_client
+            .get_account(self.escrow.pubkey())
+            .await
+            .unwrap()
+            .unwrap();
+        Escrow::unpack(&account.data).unwrap()
+    }
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use bpf_program_template::error::EscrowError;
+use common::{
+    account_exists, assert_error, assert_instruction_error, pda, process, token_account,
+    token_balance, Trade,
+};
+use solana_sdk::{
+    instruction::{AccountMeta, InstructionError},
+    signature::Signer,
+};
+
+#[tokio::test]
+async fn init_escrow_hands_the_temp_account_to_the_pda() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    trade.initialize(&mut context).await.unwrap();
+
+    let escrow = trade.state(&mut context).await;
+    assert!(escrow.is_initialized);
+    assert_eq!(escrow.initializer_pubkey, trade.alice.pubkey());
+    assert_eq!(escrow.temp_token_account_pubkey, trade.temp);
+    assert_eq!(
+        escrow.initializer_token_to_receive_account_pubkey,
+        trade.alice_y
+    );
+    assert_eq!(escrow.expected_amount, 30);
+    let temp = token_account(&mut context, &trade.temp).await;
+    assert_eq!(temp.owner, pda());
+    assert_eq!(temp.amount, 50);
+}
+
+#[tokio::test]
+async fn escrow_cannot_be_initialized_twice() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context).await.unwrap();
+
+    let ix = trade.init_instruction();
+    assert_instruction_error(
+        process(&mut context, &[ix], &[&trade.alice]).await,
+        InstructionError::AccountAlreadyInitialized,
+    );
+}
+
+#[tokio::test]
+async fn init_escrow_rejects_an_escrow_that_is_not_rent_exempt() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    assert_error(
+        trade.initialize_with_lamports(&mut context, 1_000).await,
+        EscrowError::NotRentExempt,
+    );
+    assert!(!account_exists(&mut context, &trade.escrow.pubkey()).await);
+}
+
+#[tokio::test]
+async fn init_escrow_rejects_a_receive_account_that_is_not_a_token_account() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+
+    let mut ix = trade.init_instruction();
+    ix.accounts[2] = AccountMeta::new_readonly(trade.alice.pubkey(), false);
+    assert_instruction_error(
+        process(&mut context, &[ix], &[&trade.alice]).await,
+        InstructionError::IncorrectProgramId,
+    );
+}
+
+#[tokio::test]
+async fn exchange_settles_both_sides_and_closes_the_escrow() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context).await.unwrap();
+    let alice_lamports = context
+        .banks_client
+        .get_balance(trade.alice.pubkey())
+        .await
+        .unwrap();
+
+    trade.exchange(&mut context).await.unwrap();
+
+    assert_eq!(token_balance(&mut context, &trade.bob_x).await, 50);
+    assert_eq!(token_balance(&mut context, &trade.bob_y).await, 0);
+    assert_eq!(token_balance(&mut context, &trade.alice_y).await, 30);
+    assert!(!account_exists(&mut context, &trade.temp).await);
+    assert!(!account_exists(&mut context, &trade.escrow.pubkey()).await);
+    // The rent of the escrow and of the temp account goes back to Alice
+    assert!(
+        context
+            .banks_client
+            .get_balance(trade.alice.pubkey())
+            .await
+            .unwrap()
+            > alice_lamports
+    );
+}
+
+#[tokio::test]
+async fn exchange_rejects_an_amount_other_than_the_deposit() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context).await.unwrap();
+
+    for amount in [49, 51] {
+        let ix = trade.exchange_instruction(amount);
+        assert_error(
+            process(&mut context, &[ix], &[&trade.bob]).await,
+            EscrowError::ExpectedAmountMismatch,
+        );
+    }
+    assert_eq!(token_balance(&mut context, &trade.temp).await, 50);
+}
+
+#[tokio::test]
+async fn exchange_rejects_accounts_the_escrow_does_not_name() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    let other = Trade::setup_with_mints(&mut context, trade.mint_x, trade.mint_y, 50, 30).await;
+    trade.initialize(&mut context).await.unwrap();
+    other.initialize(&mut context).await.unwrap();
+
+    // The temp account of another escrow holding the same deposit
+    let mut other_temp = trade.exchange_instruction(trade.deposit);
+    other_temp.accounts[3] = AccountMeta::new(other.temp, false);
+    // Another initializer to send the rent to
+    let mut other_initializer = trade.exchange_instruction(trade.deposit);
+    other_initializer.accounts[4] = AccountMeta::new(other.alice.pubkey(), false);
+    // Another account to pay the price into
+    let mut other_receiver = trade.exchange_instruction(trade.deposit);
+    other_receiver.accounts[5] = AccountMeta::new(other.alice_y, false);
+    for ix in [other_temp, other_initializer, other_receiver] {
+        assert_instruction_error(
+            process(&mut context, &[ix], &[&trade.bob]).await,
+            InstructionError::InvalidAccountData,
+        );
+    }
+    assert_eq!(token_balance(&mut context, &trade.temp).await, 50);
+    assert_eq!(token_balance(&mut context, &other.temp).await, 50);
+}
+
+#[tokio::test]
+async fn exchange_needs_the_taker_signature() {
+    let mut context = common::start().await;
+    let trade = Trade::setup(&mut context, 50, 30).await;
+    trade.initialize(&mut context).await.unwrap();
+
+    let mut ix = trade.exchange_instruction(trade.deposit);
+    ix.accounts[0] = AccountMeta::new_readonly(trade.bob.pubkey(), false);
+    assert_instruction_error(
+        process(&mut context, &[ix], &[]).await,
+        InstructionError::MissingRequiredSignature,
+    );
+}
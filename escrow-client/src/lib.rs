@@ -0,0 +1,115 @@
+//! RPC plumbing shared by the `client` modules of the native and Anchor escrow programs.
+//! Each program only describes where its escrow header sits and how its instructions are built
+
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    client_error::ClientError as RpcError,
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    account::Account,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    // Boxed, the RPC errors are large enough to bloat every result
+    #[error(transparent)]
+    Rpc(Box<RpcError>),
+    /// The account isn't an initialized escrow of the program, or of another layout version
+    #[error("{0} is not an escrow of the program")]
+    NotAnEscrow(Pubkey),
+    #[error("{0} is not the config of the program")]
+    NotTheConfig(Pubkey),
+    #[error("{0} is not a token account")]
+    NotATokenAccount(Pubkey),
+}
+
+impl From<RpcError> for ClientError {
+    fn from(e: RpcError) -> Self {
+        ClientError::Rpc(Box::new(e))
+    }
+}
+
+/// The `getProgramAccounts` filters matching the open escrows, those of `initializer` only when given.
+/// Both programs start an escrow with `is_initialized`, `version` and the initializer, right behind
+/// `discriminator` (empty without one). Escrows are closed once filled or cancelled, so every
+/// initialized one is open
+pub fn open_escrow_filters(
+    len: usize,
+    discriminator: &[u8],
+    version: u8,
+    initializer: Option<&Pubkey>,
+) -> Vec<RpcFilterType> {
+    let is_initialized_offset = discriminator.len();
+    let mut filters = vec![RpcFilterType::DataSize(len as u64)];
+    if !discriminator.is_empty() {
+        filters.push(RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            0,
+            discriminator,
+        )));
+    }
+    filters.extend([
+        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(is_initialized_offset, &[1])),
+        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            is_initialized_offset + 1,
+            &[version],
+        )),
+    ]);
+    if let Some(initializer) = initializer {
+        filters.push(RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            is_initialized_offset + 2,
+            initializer.as_ref(),
+        )));
+    }
+    filters
+}
+
+/// The accounts of `program_id` matching `filters`, with their addresses
+pub fn program_accounts(
+    rpc: &RpcClient,
+    program_id: &Pubkey,
+    filters: Vec<RpcFilterType>,
+) -> Result<Vec<(Pubkey, Account)>, ClientError> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(filters),
+        account_config: RpcAccountInfoConfig {
+            // Escrows are too large for the default base58 encoding
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    Ok(rpc.get_program_accounts_with_config(program_id, config)?)
+}
+
+/// The token program owning a mint, the original one or Token-2022
+pub fn token_program_of(rpc: &RpcClient, mint: &Pubkey) -> Result<Pubkey, ClientError> {
+    Ok(rpc.get_account(mint)?.owner)
+}
+
+/// Signs a transaction paid by `signer` with the latest blockhash
+pub fn sign(
+    rpc: &RpcClient,
+    instructions: &[Instruction],
+    signer: &Keypair,
+) -> Result<Transaction, ClientError> {
+    let blockhash = rpc.get_latest_blockhash()?;
+    Ok(Transaction::new_signed_with_payer(
+        instructions,
+        Some(&signer.pubkey()),
+        &[signer],
+        blockhash,
+    ))
+}
+
+/// Sends a signed transaction and waits for it to be confirmed
+pub fn send(rpc: &RpcClient, transaction: &Transaction) -> Result<Signature, ClientError> {
+    Ok(rpc.send_and_confirm_transaction(transaction)?)
+}
@@ -0,0 +1,24 @@
+//! Fill and fee math shared by the native and Anchor escrow programs, so that both round alike.
+//! Amounts are widened to u128 so that no product overflows
+
+/// Price of a fill of `amount` at the ratio of what is left in an escrow, rounded up in favour of
+/// the initializer. Filling the whole remainder always pays exactly the remaining expected amount.
+/// `None` if the price doesn't fit a u64
+pub fn amount_to_pay(
+    amount: u64,
+    remaining_amount: u64,
+    remaining_expected_amount: u64,
+) -> Option<u64> {
+    let value = amount as u128 * remaining_expected_amount as u128;
+    let remaining_amount = remaining_amount as u128;
+    let mut amount_to_pay = value / remaining_amount;
+    if amount_to_pay * remaining_amount < value {
+        amount_to_pay += 1;
+    }
+    u64::try_from(amount_to_pay).ok()
+}
+
+/// Protocol fee skimmed from a leg of `amount`, rounded down in favour of the traders
+pub fn protocol_fee(amount: u64, fee_basis_points: u16) -> u64 {
+    (amount as u128 * fee_basis_points as u128 / 10_000) as u64
+}
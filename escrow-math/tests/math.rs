@@ -0,0 +1,26 @@
+use escrow_math::{amount_to_pay, protocol_fee};
+
+#[test]
+fn partial_fills_round_up_for_the_initializer() {
+    // 3 of 10 left for 7: 2.1 is rounded up
+    assert_eq!(amount_to_pay(3, 10, 7), Some(3));
+    assert_eq!(amount_to_pay(5, 10, 8), Some(4));
+}
+
+#[test]
+fn filling_the_remainder_pays_what_is_left() {
+    assert_eq!(amount_to_pay(7, 7, 3), Some(3));
+    assert_eq!(amount_to_pay(u64::MAX, u64::MAX, u64::MAX), Some(u64::MAX));
+}
+
+#[test]
+fn prices_beyond_a_u64_are_refused() {
+    assert_eq!(amount_to_pay(u64::MAX, 1, 2), None);
+}
+
+#[test]
+fn protocol_fee_rounds_down_for_the_traders() {
+    assert_eq!(protocol_fee(1_000, 100), 10);
+    assert_eq!(protocol_fee(99, 100), 0);
+    assert_eq!(protocol_fee(u64::MAX, 10_000), u64::MAX);
+}